@@ -1,6 +1,6 @@
 use crate::lazy::Lazy;
 use crate::slot::Slot;
-use crate::{Pool, Transaction, TxPool, TxSlot};
+use crate::{Pool, Transaction, TxPool, TxRejection, TxSlot};
 use axum::extract::{FromRequestParts, State};
 use axum::http::request::Parts;
 use axum::http::Request;
@@ -85,11 +85,14 @@ impl TxSlot<RedisTransaction> {
 
 #[async_trait]
 impl<S: Sized> FromRequestParts<S> for Transaction<RedisTransaction> {
-    type Rejection = ();
+    type Rejection = TxRejection;
 
     async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
-        let ext: &mut Lazy<Pool<deadpool_redis::Pool>> = parts.extensions.get_mut().ok_or(())?;
-        let tx = ext.get_or_begin().await?;
+        let ext: &mut Lazy<Pool<deadpool_redis::Pool>> = parts
+            .extensions
+            .get_mut()
+            .ok_or(TxRejection::MissingLayer)?;
+        let tx = ext.get_or_begin().await.or(Err(TxRejection::Unavailable))?;
 
         Ok(Self(tx))
     }
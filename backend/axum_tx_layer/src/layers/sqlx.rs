@@ -1,6 +1,6 @@
 use crate::lazy::Lazy;
 use crate::slot::Slot;
-use crate::{Pool, TxPool, TxSlot};
+use crate::{Pool, TxPool, TxRejection, TxSlot};
 use axum::extract::{FromRequestParts, State};
 use axum::http::request::Parts;
 use axum::http::Request;
@@ -62,11 +62,14 @@ impl TxSlot<SqlxTransaction> {
 
 #[async_trait]
 impl<S: Sized> FromRequestParts<S> for crate::Transaction<SqlxTransaction> {
-    type Rejection = ();
+    type Rejection = TxRejection;
 
     async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
-        let ext: &mut Lazy<Pool<PgPool>> = parts.extensions.get_mut().ok_or(())?;
-        let tx = ext.get_or_begin().await?;
+        let ext: &mut Lazy<Pool<PgPool>> = parts
+            .extensions
+            .get_mut()
+            .ok_or(TxRejection::MissingLayer)?;
+        let tx = ext.get_or_begin().await.or(Err(TxRejection::Unavailable))?;
 
         Ok(Self(tx))
     }
@@ -5,6 +5,8 @@ mod slot;
 use crate::lease::Lease;
 use crate::slot::Slot;
 use axum::async_trait;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
 pub use layers::*;
 
 #[derive(Clone)]
@@ -21,6 +23,21 @@ pub trait TxPool: Send + Sync {
 // will be used as parameter inside axum handlers
 pub struct Transaction<Tx>(pub Lease<Tx>);
 
+/// rejection of a [Transaction], responds with 500
+#[derive(Debug)]
+pub enum TxRejection {
+    /// the tx_layer of the transaction has not been installed
+    MissingLayer,
+    /// the transaction could not be started or is already extracted
+    Unavailable,
+}
+
+impl IntoResponse for TxRejection {
+    fn into_response(self) -> Response {
+        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    }
+}
+
 impl<Tx> AsRef<Tx> for Transaction<Tx> {
     fn as_ref(&self) -> &Tx {
         self.0.as_ref()
@@ -7,9 +7,12 @@
 mod error;
 mod replay;
 mod types;
+#[cfg(feature = "serde")]
+mod wire;
 
 use arena::Arena;
-use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::hash::Hash;
 use types::{Action, Grantee};
 
@@ -376,12 +379,12 @@ impl<GranteeId: Hash + Eq + Copy, ActionId: Hash + Eq + Copy> CanDo<GranteeId, A
             .get(grantee_of_index)
             .grantees
             .iter()
-            .position(|el: &usize| el == &grantee_of_index)
+            .position(|el: &usize| el == &grantee_index)
         {
             let _ = &self
                 .grantees_arena
-                .get_mut(grantee_index)
-                .grantee_of
+                .get_mut(grantee_of_index)
+                .grantees
                 .swap_remove(i);
         }
         Ok(())
@@ -495,6 +498,241 @@ impl<GranteeId: Hash + Eq + Copy, ActionId: Hash + Eq + Copy> CanDo<GranteeId, A
         Ok(false)
     }
 
+    /// returns all grantees a grantee is a member of
+    ///
+    /// uses breadth first search over grantee_of connections
+    /// includes direct and transitive memberships but not the grantee itself
+    pub fn groups_of(&self, grantee_id: &GranteeId) -> Result<Vec<GranteeId>, CanDoError> {
+        let Some(&grantee_idx) = self.grantees.get(grantee_id) else {
+            return Err(CanDoError::GranteeNotFound);
+        };
+
+        let groups = self.collect_grantee_idx(grantee_idx, true, |grantee| &grantee.grantee_of);
+        Ok(self.grantee_ids(&groups))
+    }
+
+    /// returns all members of a grantee
+    ///
+    /// if transitive is set members of members are returned as well
+    /// uses breadth first search over grantees connections
+    pub fn members_of(
+        &self,
+        grantee_id: &GranteeId,
+        transitive: bool,
+    ) -> Result<Vec<GranteeId>, CanDoError> {
+        let Some(&grantee_idx) = self.grantees.get(grantee_id) else {
+            return Err(CanDoError::GranteeNotFound);
+        };
+
+        let members =
+            self.collect_grantee_idx(grantee_idx, transitive, |grantee| &grantee.grantees);
+        Ok(self.grantee_ids(&members))
+    }
+
+    /// walks the grantee graph starting at grantee_idx following the given edges
+    ///
+    /// the starting grantee is never part of the result
+    fn collect_grantee_idx(
+        &self,
+        grantee_idx: usize,
+        transitive: bool,
+        edges: impl Fn(&Grantee) -> &Vec<usize>,
+    ) -> HashSet<usize> {
+        let mut grantees_checked = HashSet::from([grantee_idx]);
+        let mut collected = HashSet::new();
+
+        let mut grantees_to_check = vec![edges(self.grantees_arena.get(grantee_idx))];
+        while !grantees_to_check.is_empty() {
+            let mut next_grantees_to_check = Vec::<&Vec<usize>>::new();
+            for next_to_check in grantees_to_check {
+                for &grantee_to_check in next_to_check {
+                    // prevent loops
+                    if !grantees_checked.insert(grantee_to_check) {
+                        continue;
+                    }
+                    collected.insert(grantee_to_check);
+                    if transitive {
+                        next_grantees_to_check
+                            .push(edges(self.grantees_arena.get(grantee_to_check)));
+                    }
+                }
+            }
+
+            grantees_to_check = next_grantees_to_check;
+        }
+
+        collected
+    }
+
+    /// returns every grant, connection and root flag involving a grantee
+    ///
+    /// replaying them restores the grantee after it has been removed
+    pub fn grantee_edges(
+        &self,
+        grantee_id: &GranteeId,
+    ) -> Result<Vec<Replay<GranteeId, ActionId>>, CanDoError> {
+        let Some(&grantee_idx) = self.grantees.get(grantee_id) else {
+            return Err(CanDoError::GranteeNotFound);
+        };
+        let grantee = self.grantees_arena.get(grantee_idx);
+        let reversed_grantees: HashMap<usize, GranteeId> =
+            self.grantees.iter().map(|(k, v)| (*v, *k)).collect();
+        let reversed_actions: HashMap<usize, ActionId> =
+            self.actions.iter().map(|(k, v)| (*v, *k)).collect();
+
+        let grants = grantee
+            .actions
+            .iter()
+            .map(|action_idx| Replay::Grant(*grantee_id, reversed_actions[action_idx]));
+        let groups = grantee.grantee_of.iter().map(|grantee_of_idx| {
+            Replay::ConnectGrantees(*grantee_id, reversed_grantees[grantee_of_idx])
+        });
+        let members = grantee
+            .grantees
+            .iter()
+            .map(|member_idx| Replay::ConnectGrantees(reversed_grantees[member_idx], *grantee_id));
+        let root = grantee.is_root.then_some(Replay::Root(*grantee_id));
+
+        Ok(grants.chain(groups).chain(members).chain(root).collect())
+    }
+
+    /// returns every grant and connection involving an action
+    ///
+    /// replaying them restores the action after it has been removed
+    pub fn action_edges(
+        &self,
+        action_id: &ActionId,
+    ) -> Result<Vec<Replay<GranteeId, ActionId>>, CanDoError> {
+        let Some(&action_idx) = self.actions.get(action_id) else {
+            return Err(CanDoError::ActionNotFound);
+        };
+        let action = self.actions_arena.get(action_idx);
+        let reversed_grantees: HashMap<usize, GranteeId> =
+            self.grantees.iter().map(|(k, v)| (*v, *k)).collect();
+        let reversed_actions: HashMap<usize, ActionId> =
+            self.actions.iter().map(|(k, v)| (*v, *k)).collect();
+
+        let grants = action
+            .grantees
+            .iter()
+            .map(|grantee_idx| Replay::Grant(reversed_grantees[grantee_idx], *action_id));
+        let sub_actions = action.main_action_of.iter().map(|sub_action_idx| {
+            Replay::ConnectActions(*action_id, reversed_actions[sub_action_idx])
+        });
+        let main_actions = action.sub_action_of.iter().map(|main_action_idx| {
+            Replay::ConnectActions(reversed_actions[main_action_idx], *action_id)
+        });
+
+        Ok(grants.chain(sub_actions).chain(main_actions).collect())
+    }
+
+    /// explains why a grantee can perform an action
+    ///
+    /// returns the shortest chain of memberships followed by the grant and the action inheritances
+    /// eg. [ConnectGrantees(User1, Group1), Grant(Group1, Action1), ConnectActions(Action1, Action2)]
+    /// returns None if the grantee can not perform the action
+    pub fn explain(
+        &self,
+        grantee_id: &GranteeId,
+        action_id: &ActionId,
+    ) -> Result<Option<Vec<Replay<GranteeId, ActionId>>>, CanDoError> {
+        let Some(&grantee_idx) = self.grantees.get(grantee_id) else {
+            return Err(CanDoError::GranteeNotFound);
+        };
+        let Some(&action_idx) = self.actions.get(action_id) else {
+            return Err(CanDoError::ActionNotFound);
+        };
+
+        // every main action of action_idx mapped to the sub action it has been reached from
+        let mut sub_actions: HashMap<usize, Option<usize>> = HashMap::from([(action_idx, None)]);
+        let mut actions_to_check = VecDeque::from([action_idx]);
+        while let Some(next_to_check) = actions_to_check.pop_front() {
+            for &main_action_idx in &self.actions_arena.get(next_to_check).sub_action_of {
+                // prevent loops
+                if let Entry::Vacant(entry) = sub_actions.entry(main_action_idx) {
+                    entry.insert(Some(next_to_check));
+                    actions_to_check.push_back(main_action_idx);
+                }
+            }
+        }
+
+        // every group of grantee_idx mapped to the member it has been reached from
+        let mut members: HashMap<usize, Option<usize>> = HashMap::from([(grantee_idx, None)]);
+        let mut grantees_to_check = VecDeque::from([grantee_idx]);
+        while let Some(next_to_check) = grantees_to_check.pop_front() {
+            let grantee = self.grantees_arena.get(next_to_check);
+            if let Some(&granted_idx) = grantee
+                .actions
+                .iter()
+                .find(|action_idx| sub_actions.contains_key(action_idx))
+            {
+                return Ok(Some(self.explanation(
+                    next_to_check,
+                    granted_idx,
+                    &members,
+                    &sub_actions,
+                )));
+            }
+            for &grantee_of_idx in &grantee.grantee_of {
+                // prevent loops
+                if let Entry::Vacant(entry) = members.entry(grantee_of_idx) {
+                    entry.insert(Some(next_to_check));
+                    grantees_to_check.push_back(grantee_of_idx);
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// follows the paths found by [explain](Self::explain) from the granted grantee and action
+    fn explanation(
+        &self,
+        grantee_idx: usize,
+        action_idx: usize,
+        members: &HashMap<usize, Option<usize>>,
+        sub_actions: &HashMap<usize, Option<usize>>,
+    ) -> Vec<Replay<GranteeId, ActionId>> {
+        let reversed_grantees: HashMap<usize, GranteeId> =
+            self.grantees.iter().map(|(k, v)| (*v, *k)).collect();
+        let reversed_actions: HashMap<usize, ActionId> =
+            self.actions.iter().map(|(k, v)| (*v, *k)).collect();
+
+        let mut memberships = vec![];
+        let mut grantee_of_idx = grantee_idx;
+        while let Some(member_idx) = members[&grantee_of_idx] {
+            memberships.push(Replay::ConnectGrantees(
+                reversed_grantees[&member_idx],
+                reversed_grantees[&grantee_of_idx],
+            ));
+            grantee_of_idx = member_idx;
+        }
+        memberships.reverse();
+
+        let mut explanation = memberships;
+        explanation.push(Replay::Grant(
+            reversed_grantees[&grantee_idx],
+            reversed_actions[&action_idx],
+        ));
+        let mut main_action_idx = action_idx;
+        while let Some(sub_action_idx) = sub_actions[&main_action_idx] {
+            explanation.push(Replay::ConnectActions(
+                reversed_actions[&main_action_idx],
+                reversed_actions[&sub_action_idx],
+            ));
+            main_action_idx = sub_action_idx;
+        }
+        explanation
+    }
+
+    /// maps arena indices back to their grantee ids
+    fn grantee_ids(&self, grantee_idx: &HashSet<usize>) -> Vec<GranteeId> {
+        self.grantees
+            .iter()
+            .filter_map(|(id, idx)| grantee_idx.contains(idx).then_some(*id))
+            .collect()
+    }
+
     pub fn compact(&mut self) -> Vec<Replay<GranteeId, ActionId>> {
         // find and remove orphaned actions
         // an action is orphaned if it is
@@ -580,7 +818,13 @@ impl<GranteeId: Hash + Eq + Copy, ActionId: Hash + Eq + Copy> CanDo<GranteeId, A
             }
         }
 
-        // map current state into Replays
+        self.replays()
+    }
+
+    /// maps the current state into Replays without compacting
+    ///
+    /// unlike [CanDo::compact()] orphaned grantees and actions are kept
+    pub fn replays(&self) -> Vec<Replay<GranteeId, ActionId>> {
         let reversed_grantees: HashMap<usize, GranteeId> =
             self.grantees.iter().map(|(k, v)| (*v, *k)).collect();
         let reversed_actions: HashMap<usize, ActionId> =
@@ -664,7 +908,7 @@ mod tests {
         Read(ItemId),
     }
 
-    #[derive(Eq, PartialEq, Hash, Copy, Clone)]
+    #[derive(Debug, Eq, PartialEq, Hash, Copy, Clone)]
     enum Grantee {
         User(Id),
         Group(Id),
@@ -849,6 +1093,88 @@ mod tests {
         assert_eq!(1, can_do.actions.len());
     }
 
+    #[test]
+    fn groups_of_should_return_transitive_groups() {
+        let mut can_do = CanDo::<Grantee, ActionItem<Id>>::new();
+        let user1 = Grantee::User(1);
+        let group1 = Grantee::Group(1);
+        let group2 = Grantee::Group(2);
+        let group3 = Grantee::Group(3);
+
+        can_do.connect_grantees(&user1, &group1);
+        can_do.connect_grantees(&group1, &group2);
+        // loops must not be followed twice
+        can_do.connect_grantees(&group2, &group1);
+        can_do.connect_grantees(&group3, &group1);
+
+        let mut groups = can_do.groups_of(&user1).unwrap();
+        groups.sort_unstable_by_key(|grantee| match grantee {
+            Grantee::User(id) | Grantee::Group(id) => *id,
+        });
+        assert_eq!(groups, vec![group1, group2]);
+    }
+
+    #[test]
+    fn groups_of_should_fail_for_unknown_grantee() {
+        let can_do = CanDo::<Grantee, ActionItem<Id>>::new();
+
+        assert!(matches!(
+            can_do.groups_of(&Grantee::User(1)),
+            Err(CanDoError::GranteeNotFound)
+        ));
+    }
+
+    #[test]
+    fn members_of_should_return_direct_members() {
+        let mut can_do = CanDo::<Grantee, ActionItem<Id>>::new();
+        let user1 = Grantee::User(1);
+        let user2 = Grantee::User(2);
+        let group1 = Grantee::Group(1);
+        let group2 = Grantee::Group(2);
+
+        can_do.connect_grantees(&user1, &group1);
+        can_do.connect_grantees(&group1, &group2);
+        can_do.connect_grantees(&user2, &group2);
+
+        let mut members = can_do.members_of(&group2, false).unwrap();
+        members.sort_unstable_by_key(|grantee| match grantee {
+            Grantee::User(id) | Grantee::Group(id) => *id,
+        });
+        assert_eq!(members, vec![group1, user2]);
+    }
+
+    #[test]
+    fn members_of_should_return_transitive_members() {
+        let mut can_do = CanDo::<Grantee, ActionItem<Id>>::new();
+        let user1 = Grantee::User(1);
+        let user2 = Grantee::User(2);
+        let group1 = Grantee::Group(3);
+        let group2 = Grantee::Group(4);
+
+        can_do.connect_grantees(&user1, &group1);
+        can_do.connect_grantees(&group1, &group2);
+        can_do.connect_grantees(&user2, &group2);
+
+        let members = can_do.members_of(&group2, true).unwrap();
+        assert_eq!(3, members.len());
+        assert!(members.contains(&user1));
+        assert!(members.contains(&user2));
+        assert!(members.contains(&group1));
+    }
+
+    #[test]
+    fn members_of_should_respect_disconnects() {
+        let mut can_do = CanDo::<Grantee, ActionItem<Id>>::new();
+        let user1 = Grantee::User(1);
+        let group1 = Grantee::Group(1);
+
+        can_do.connect_grantees(&user1, &group1);
+        can_do.disconnect_grantees(&user1, &group1).unwrap();
+
+        assert!(can_do.members_of(&group1, true).unwrap().is_empty());
+        assert!(can_do.groups_of(&user1).unwrap().is_empty());
+    }
+
     #[test]
     fn compact_should_not_need_to_do_anything() {
         let mut can_do = CanDo::<Grantee, ActionItem<Id>>::new();
@@ -903,6 +1229,122 @@ mod tests {
         assert_eq!(0, can_do.grantees.len());
     }
 
+    #[test]
+    fn grantee_edges_should_contain_all_connections() {
+        let mut can_do = CanDo::<Grantee, ActionItem<Id>>::new();
+
+        let user1 = Grantee::User(1);
+        let group1 = Grantee::Group(2);
+        let group2 = Grantee::Group(3);
+        let action1 = ActionItem::Read(1);
+
+        can_do.connect_grantees(&user1, &group1);
+        can_do.connect_grantees(&group1, &group2);
+        can_do.add_grant(&group1, &action1);
+        can_do.add_root(&group1);
+
+        let edges = can_do.grantee_edges(&group1).expect("Expected edges");
+        assert_eq!(4, edges.len());
+        assert!(edges.contains(&Replay::ConnectGrantees(user1, group1)));
+        assert!(edges.contains(&Replay::ConnectGrantees(group1, group2)));
+        assert!(edges.contains(&Replay::Grant(group1, action1)));
+        assert!(edges.contains(&Replay::Root(group1)));
+
+        assert!(matches!(
+            can_do.grantee_edges(&Grantee::User(9)),
+            Err(CanDoError::GranteeNotFound)
+        ));
+    }
+
+    #[test]
+    fn action_edges_should_contain_all_connections() {
+        let mut can_do = CanDo::<Grantee, ActionItem<Id>>::new();
+
+        let user1 = Grantee::User(1);
+        let action1 = ActionItem::Read(1);
+        let action2 = ActionItem::Read(2);
+        let action3 = ActionItem::Read(3);
+
+        can_do.connect_actions(&action1, &action2);
+        can_do.connect_actions(&action2, &action3);
+        can_do.add_grant(&user1, &action2);
+
+        let edges = can_do.action_edges(&action2).expect("Expected edges");
+        assert_eq!(3, edges.len());
+        assert!(edges.contains(&Replay::Grant(user1, action2)));
+        assert!(edges.contains(&Replay::ConnectActions(action1, action2)));
+        assert!(edges.contains(&Replay::ConnectActions(action2, action3)));
+    }
+
+    #[test]
+    fn explain_should_return_shortest_path() {
+        let mut can_do = CanDo::<Grantee, ActionItem<Id>>::new();
+
+        let user1 = Grantee::User(1);
+        let group1 = Grantee::Group(2);
+        let group2 = Grantee::Group(3);
+        let group3 = Grantee::Group(4);
+        let action1 = ActionItem::Read(1);
+        let action2 = ActionItem::Read(2);
+        let action3 = ActionItem::Read(3);
+
+        // user1 -> group1 -> group2 -> action1 <- action2 <- action3
+        // user1 -> group3 -> group1 is a longer path to the same grant
+        can_do.connect_grantees(&user1, &group3);
+        can_do.connect_grantees(&group3, &group1);
+        can_do.connect_grantees(&user1, &group1);
+        can_do.connect_grantees(&group1, &group2);
+        can_do.add_grant(&group2, &action1);
+        can_do.connect_actions(&action1, &action2);
+        can_do.connect_actions(&action2, &action3);
+
+        assert!(
+            can_do.explain(&user1, &action3)
+                == Ok(Some(vec![
+                    Replay::ConnectGrantees(user1, group1),
+                    Replay::ConnectGrantees(group1, group2),
+                    Replay::Grant(group2, action1),
+                    Replay::ConnectActions(action1, action2),
+                    Replay::ConnectActions(action2, action3),
+                ]))
+        );
+        assert!(
+            can_do.explain(&group2, &action1) == Ok(Some(vec![Replay::Grant(group2, action1)]))
+        );
+        assert!(matches!(
+            can_do.explain(&group3, &ActionItem::Read(9)),
+            Err(CanDoError::ActionNotFound)
+        ));
+
+        can_do.add_grant(&user1, &ActionItem::Read(4));
+        assert!(matches!(
+            can_do.explain(&user1, &ActionItem::Read(4)),
+            Ok(Some(explanation)) if explanation.len() == 1
+        ));
+        assert!(matches!(
+            can_do.explain(&group1, &ActionItem::Read(4)),
+            Ok(None)
+        ));
+    }
+
+    #[test]
+    fn replays_should_keep_orphans() {
+        let mut can_do = CanDo::<Grantee, ActionItem<Id>>::new();
+
+        let user1 = Grantee::User(1);
+        let group1 = Grantee::Group(2);
+        let action1 = ActionItem::Read(1);
+
+        can_do.connect_grantees(&user1, &group1);
+        can_do.add_grant(&group1, &action1);
+
+        let replays = can_do.replays();
+        assert_eq!(2, replays.len());
+        assert!(replays.contains(&Replay::ConnectGrantees(user1, group1)));
+        assert!(replays.contains(&Replay::Grant(group1, action1)));
+        assert_eq!(2, can_do.grantees.len());
+    }
+
     #[test]
     fn compact_should_remove_all_grantees_not_connected_to_root() {
         let mut can_do = CanDo::<Grantee, ActionItem<Id>>::new();
@@ -1,4 +1,17 @@
-#[derive(Eq, PartialEq, Hash, Copy, Clone)]
+/// with the serde feature a replay is serialized as `{"v1": {"grant": [grantee, action]}}`
+#[derive(Eq, PartialEq, Hash, Copy, Clone, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(
+        into = "crate::wire::VersionedReplay<GranteeId, ActionId>",
+        from = "crate::wire::VersionedReplay<GranteeId, ActionId>",
+        bound(
+            serialize = "GranteeId: serde::Serialize + Clone, ActionId: serde::Serialize + Clone",
+            deserialize = "GranteeId: serde::Deserialize<'de>, ActionId: serde::Deserialize<'de>"
+        )
+    )
+)]
 pub enum Replay<GranteeId, ActionId> {
     Grant(GranteeId, ActionId),
     // Grantee - GranteeOf
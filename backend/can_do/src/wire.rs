@@ -0,0 +1,89 @@
+//! stable wire format of [Replay]
+//!
+//! every replay is wrapped into its format version, the variant names are snake_case
+//! a format version must never change once released, changes require a new version
+//! older versions are still deserialized and converted into the current Replay
+use crate::Replay;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum VersionedReplay<GranteeId, ActionId> {
+    V1(ReplayV1<GranteeId, ActionId>),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ReplayV1<GranteeId, ActionId> {
+    Grant(GranteeId, ActionId),
+    ConnectGrantees(GranteeId, GranteeId),
+    ConnectActions(ActionId, ActionId),
+    Root(GranteeId),
+}
+
+impl<GranteeId, ActionId> From<Replay<GranteeId, ActionId>>
+    for VersionedReplay<GranteeId, ActionId>
+{
+    fn from(replay: Replay<GranteeId, ActionId>) -> Self {
+        VersionedReplay::V1(match replay {
+            Replay::Grant(grantee_id, action_id) => ReplayV1::Grant(grantee_id, action_id),
+            Replay::ConnectGrantees(grantee_id, grantee_of_id) => {
+                ReplayV1::ConnectGrantees(grantee_id, grantee_of_id)
+            }
+            Replay::ConnectActions(main_action_id, sub_action_id) => {
+                ReplayV1::ConnectActions(main_action_id, sub_action_id)
+            }
+            Replay::Root(grantee_id) => ReplayV1::Root(grantee_id),
+        })
+    }
+}
+
+impl<GranteeId, ActionId> From<VersionedReplay<GranteeId, ActionId>>
+    for Replay<GranteeId, ActionId>
+{
+    fn from(replay: VersionedReplay<GranteeId, ActionId>) -> Self {
+        match replay {
+            VersionedReplay::V1(ReplayV1::Grant(grantee_id, action_id)) => {
+                Replay::Grant(grantee_id, action_id)
+            }
+            VersionedReplay::V1(ReplayV1::ConnectGrantees(grantee_id, grantee_of_id)) => {
+                Replay::ConnectGrantees(grantee_id, grantee_of_id)
+            }
+            VersionedReplay::V1(ReplayV1::ConnectActions(main_action_id, sub_action_id)) => {
+                Replay::ConnectActions(main_action_id, sub_action_id)
+            }
+            VersionedReplay::V1(ReplayV1::Root(grantee_id)) => Replay::Root(grantee_id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Replay;
+
+    #[test]
+    fn v1_format_is_stable() {
+        let replays: Vec<Replay<u32, &str>> = vec![
+            Replay::Grant(1, "read"),
+            Replay::ConnectGrantees(1, 2),
+            Replay::ConnectActions("write", "read"),
+            Replay::Root(2),
+        ];
+
+        let json = serde_json::to_string(&replays).expect("Expected replays to serialize");
+        assert_eq!(
+            json,
+            r#"[{"v1":{"grant":[1,"read"]}},{"v1":{"connect_grantees":[1,2]}},{"v1":{"connect_actions":["write","read"]}},{"v1":{"root":2}}]"#
+        );
+
+        let restored: Vec<Replay<u32, &str>> =
+            serde_json::from_str(&json).expect("Expected replays to deserialize");
+        assert_eq!(replays, restored);
+    }
+
+    #[test]
+    fn unknown_versions_are_rejected() {
+        assert!(serde_json::from_str::<Replay<u32, u32>>(r#"{"v0":{"root":2}}"#).is_err());
+        assert!(serde_json::from_str::<Replay<u32, u32>>(r#"{"root":2}"#).is_err());
+    }
+}
@@ -0,0 +1,27 @@
+-- Add migration script here
+
+CREATE TYPE permission_change_kind AS ENUM (
+    'clear',
+    'remove_grantee',
+    'remove_action',
+    'add_grant',
+    'remove_grant',
+    'connect_grantees',
+    'disconnect_grantees',
+    'connect_actions',
+    'disconnect_actions',
+    'add_root',
+    'remove_root'
+);
+create table permission_change
+(
+    seq         bigint generated always as identity,
+    kind        permission_change_kind not null,
+    grantee     jsonb,
+    grantee_of  jsonb,
+    action      jsonb,
+    sub_action  jsonb,
+    created_at  timestamp not null default NOW()
+);
+
+alter table permission_change add constraint permission_change_pk primary key (seq);
@@ -0,0 +1,14 @@
+-- Add migration script here
+
+create table permission_snapshot
+(
+    seq         bigint generated always as identity,
+    kind        permission_change_kind not null,
+    grantee     jsonb,
+    grantee_of  jsonb,
+    action      jsonb,
+    sub_action  jsonb,
+    created_at  timestamp not null default NOW()
+);
+
+alter table permission_snapshot add constraint permission_snapshot_pk primary key (seq);
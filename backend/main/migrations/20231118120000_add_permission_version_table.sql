@@ -0,0 +1,14 @@
+-- Add migration script here
+
+-- single row counter, incremented once per committed batch of permission changes
+-- rolled back batches do not consume a version, so peers can detect missed batches
+create table permission_version
+(
+    id          boolean not null default true,
+    version     bigint not null default 0,
+    constraint permission_version_single_row check (id)
+);
+
+alter table permission_version add constraint permission_version_pk primary key (id);
+
+insert into permission_version default values;
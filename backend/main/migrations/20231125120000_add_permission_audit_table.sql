@@ -0,0 +1,17 @@
+-- Add migration script here
+
+-- every committed batch of permission changes together with who made it and why
+-- unlike permission_change this table is not truncated by checkpoints
+create table permission_audit
+(
+    id          bigint generated always as identity,
+    actor       jsonb,
+    reason      text,
+    request_id  text,
+    changes     jsonb not null,
+    changed_at  timestamp with time zone not null
+);
+
+alter table permission_audit add constraint permission_audit_pk primary key (id);
+
+create index permission_audit_changes_idx on permission_audit using gin (changes jsonb_path_ops);
@@ -0,0 +1,4 @@
+-- Add migration script here
+
+-- the changes reverting a batch, captured when it was committed
+alter table permission_audit add column inverse jsonb not null default '[]';
@@ -0,0 +1,14 @@
+-- Add migration script here
+
+-- single use password reset tokens, only their sha256 is stored
+create table password_reset
+(
+    token_hash  varchar not null,
+    account_id  uuid    not null references account (id) on delete cascade,
+    expires_at  timestamp with time zone not null,
+    created_at  timestamp with time zone not null default NOW()
+);
+
+alter table password_reset add constraint password_reset_pk primary key (token_hash);
+
+create index password_reset_account_idx on password_reset (account_id);
@@ -0,0 +1,5 @@
+-- Add migration script here
+
+-- new accounts have to verify their external_id before they can log in
+-- a new enum value can not be used in the transaction adding it, see the following migration
+ALTER TYPE account_status ADD VALUE 'unverified';
@@ -0,0 +1,17 @@
+-- Add migration script here
+
+-- existing accounts stay active
+alter table account alter column status set default 'unverified';
+
+-- single use verification tokens, only their sha256 is stored
+create table account_verification
+(
+    token_hash  varchar not null,
+    account_id  uuid    not null references account (id) on delete cascade,
+    expires_at  timestamp with time zone not null,
+    created_at  timestamp with time zone not null default NOW()
+);
+
+alter table account_verification add constraint account_verification_pk primary key (token_hash);
+
+create index account_verification_account_idx on account_verification (account_id);
@@ -0,0 +1,27 @@
+-- Add migration script here
+
+create table tenant
+(
+    id          uuid      default gen_random_uuid(),
+    created_at  timestamp not null default NOW(),
+    updated_at  timestamp not null default NOW()
+);
+
+alter table tenant add constraint tenant_pk primary key (id);
+
+call create_updated_at_trigger('tenant');
+
+-- an account has at most one user per tenant
+create table tenant_user
+(
+    id          uuid      default gen_random_uuid(),
+    account_id  uuid      not null references account (id) on delete cascade,
+    tenant_id   uuid      not null references tenant (id) on delete cascade,
+    created_at  timestamp not null default NOW(),
+    updated_at  timestamp not null default NOW()
+);
+
+alter table tenant_user add constraint tenant_user_pk primary key (id);
+alter table tenant_user add constraint tenant_user_pk2 unique (account_id, tenant_id);
+
+call create_updated_at_trigger('tenant_user');
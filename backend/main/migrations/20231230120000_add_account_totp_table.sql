@@ -0,0 +1,27 @@
+-- Add migration script here
+
+-- optional second factor, unconfirmed until the first code has been entered
+create table account_totp
+(
+    account_id   uuid      not null references account (id) on delete cascade,
+    secret       bytea     not null,
+    confirmed_at timestamp with time zone,
+    -- time step of the last accepted code, codes can not be used twice
+    last_step    bigint,
+    created_at   timestamp not null default NOW(),
+    updated_at   timestamp not null default NOW()
+);
+
+alter table account_totp add constraint account_totp_pk primary key (account_id);
+
+call create_updated_at_trigger('account_totp');
+
+-- single use recovery codes, only their sha256 is stored
+create table account_recovery_code
+(
+    account_id  uuid    not null references account (id) on delete cascade,
+    code_hash   varchar not null,
+    created_at  timestamp with time zone not null default NOW()
+);
+
+alter table account_recovery_code add constraint account_recovery_code_pk primary key (account_id, code_hash);
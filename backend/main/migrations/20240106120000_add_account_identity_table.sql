@@ -0,0 +1,14 @@
+-- Add migration script here
+
+-- subjects of external identity providers, they are only unique per issuer
+create table account_identity
+(
+    issuer      varchar not null,
+    subject     varchar not null,
+    account_id  uuid    not null references account (id) on delete cascade,
+    created_at  timestamp with time zone not null default NOW()
+);
+
+alter table account_identity add constraint account_identity_pk primary key (issuer, subject);
+
+create index account_identity_account_idx on account_identity (account_id);
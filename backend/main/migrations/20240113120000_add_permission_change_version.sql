@@ -0,0 +1,6 @@
+-- Add migration script here
+
+-- version of the batch a change was committed with, a checkpoint only truncates the batches it contains
+alter table permission_change add column version bigint not null default 0;
+
+alter table permission_change alter column version drop default;
@@ -11,6 +11,7 @@ use std::env;
 use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::RwLock;
 
 mod helper;
@@ -18,36 +19,132 @@ mod services;
 mod types;
 mod web;
 
-use crate::services::auth::io_provider::{LocalAccountIO, LocalTokenIO};
+use crate::services::auth::io_provider::{
+    Backoff, KeyRing, LocalAccountIO, LocalAttemptIO, LocalFactorIO, LocalIdentityProviderIO,
+    LocalOneTimeTokenIO, LocalTokenIO, OidcConfig,
+};
+use crate::services::mail::FileMailSender;
+use crate::services::permission::{
+    Action, FilePermissionIO, Grantee, PermissionBackend, PostgresPermissionIO,
+    PostgresPermissionListener,
+};
 use crate::web::auth_layer::AuthService;
 use helper::{create_postgres_pool, create_redis_pool};
-use types::{Services, UserId};
+use types::{CurrentUser, Services};
+
+const PERMISSION_CHECKPOINT_INTERVAL: Duration = Duration::from_secs(60 * 60);
 
 #[tokio::main]
 async fn main() {
     // build config
     dotenv().expect("Expected to read .env file"); //TODO: make these into a singleton to use everywhere
+    tracing_subscriber::fmt::init();
     let postgres_url = env::var("DATABASE_URL").unwrap();
     let redis_url = env::var("REDIS_URL").unwrap();
+    let mail_spool = env::var("MAIL_SPOOL").unwrap();
+    let public_url = env::var("PUBLIC_URL").unwrap();
     let hygge_url = env::var("HYGGE_URL").unwrap().parse::<IpAddr>().unwrap();
+    // tokens of other instances and restarts are only verified with shared keys
+    let jwt_keys = env::var("JWT_KEYS").expect("Expected JWT_KEYS directory of pem keys");
+    let jwt_signing_key = env::var("JWT_SIGNING_KEY").expect("Expected JWT_SIGNING_KEY kid");
+    let oidc_issuer = env::var("OIDC_ISSUER").ok();
+    let app_url = env::var("OIDC_APP_URL").unwrap_or_else(|_| public_url.clone());
+    // permissions are kept in postgres unless a file is configured
+    let permission_log = env::var("PERMISSION_LOG").ok();
 
     // build connections
     let pg_pool = create_postgres_pool(&postgres_url).await.unwrap();
     let redis_pool = create_redis_pool(&redis_url).unwrap();
 
     let account_provider = LocalAccountIO::new();
-    let token_provider = LocalTokenIO::new(Duration::from_secs(60 * 60), "test1234");
+    let keys = KeyRing::load(&jwt_keys, &jwt_signing_key).expect("Expected to load jwt keys");
+    let token_provider = LocalTokenIO::new(
+        Duration::from_secs(15 * 60),
+        Duration::from_secs(30 * 24 * 60 * 60),
+        keys,
+    );
+    let attempt_provider = LocalAttemptIO::new(
+        Backoff {
+            free_attempts: 5,
+            base: Duration::from_secs(1),
+            lockout: Duration::from_secs(15 * 60),
+            window: Duration::from_secs(60 * 60),
+        },
+        Backoff {
+            free_attempts: 20,
+            base: Duration::from_secs(1),
+            lockout: Duration::from_secs(15 * 60),
+            window: Duration::from_secs(60 * 60),
+        },
+    );
+    reload_keys_on_hangup(token_provider.clone(), jwt_keys);
+    let factor_provider = LocalFactorIO::new();
+    let identity_provider = oidc_issuer.map(|issuer| {
+        LocalIdentityProviderIO::new(OidcConfig {
+            issuer,
+            client_id: env::var("OIDC_CLIENT_ID").unwrap(),
+            client_secret: env::var("OIDC_CLIENT_SECRET").ok(),
+            redirect_uri: env::var("OIDC_REDIRECT_URI")
+                .unwrap_or_else(|_| format!("{public_url}/auth/oidc/callback")),
+        })
+    });
+    let reset_provider = LocalOneTimeTokenIO::password_reset(Duration::from_secs(30 * 60));
+    let verification_provider =
+        LocalOneTimeTokenIO::verification(Duration::from_secs(24 * 60 * 60));
+    let mail_sender = FileMailSender::new(mail_spool);
+    let permission_io = match &permission_log {
+        Some(path) => PermissionBackend::File(Box::new(
+            FilePermissionIO::open(path)
+                .await
+                .expect("Expected to open permission log"),
+        )),
+        None => PermissionBackend::Postgres(PostgresPermissionIO::new()),
+    };
+    let permission_origin = match &permission_io {
+        PermissionBackend::Postgres(io) => Some(io.origin()),
+        PermissionBackend::File(_) => None,
+    };
+    let permission = {
+        let mut conn = pg_pool
+            .acquire()
+            .await
+            .expect("Expected to acquire postgres connection");
+        permission::Permission::with_retry(
+            permission_io,
+            permission::RetryPolicy::default(),
+            &mut conn,
+        )
+        .await
+        .expect("Expected to replay permissions")
+    };
+    // apply permission changes of other instances, a permission log is local to this instance
+    if let Some(origin) = permission_origin {
+        PostgresPermissionListener::new(permission.clone(), pg_pool.clone(), origin).spawn();
+    }
+    checkpoint_permissions(permission.clone(), pg_pool.clone());
     // build services
     let global_state = Arc::new(Services {
         account_provider: Arc::new(RwLock::new(account_provider)),
+        attempt_provider: Arc::new(RwLock::new(attempt_provider)),
+        factor_provider: Arc::new(RwLock::new(factor_provider)),
+        identity_provider: identity_provider.map(Arc::new),
         token_provider: Arc::new(RwLock::new(token_provider.clone())),
-        redis: redis_pool,
+        reset_provider: Arc::new(RwLock::new(reset_provider)),
+        verification_provider: Arc::new(RwLock::new(verification_provider)),
+        mail_sender: Arc::new(mail_sender),
+        public_url: public_url.into(),
+        app_url: app_url.into(),
+        redis: redis_pool.clone(),
+        postgres: pg_pool.clone(),
+        permission,
     });
 
     // build axum router
     let app = Router::new()
         // --- Begin authenticated routes
         .route("/", get(hello_world))
+        .nest("/auth", web::authenticated_auth_router())
+        .nest("/permissions", web::permission_router())
         // --- END authenticated routes
         .layer(from_fn_with_state(
             AuthService {
@@ -58,19 +155,74 @@ async fn main() {
         ))
         .merge(
             // unauthenticated
-            Router::new().nest("/auth", web::auth_router()),
+            Router::new()
+                .nest("/auth", web::auth_router())
+                .nest("/.well-known", web::well_known_router()),
         )
         .with_state(global_state)
-        .layer(from_fn_with_state(pg_pool, axum_tx_layer::sqlx::tx_layer));
+        .layer(from_fn_with_state(pg_pool, axum_tx_layer::sqlx::tx_layer))
+        .layer(from_fn_with_state(
+            redis_pool,
+            axum_tx_layer::redis::tx_layer,
+        ));
 
     // start server
     let addr = SocketAddr::new(hygge_url, 3000);
     axum::Server::bind(&addr)
-        .serve(app.into_make_service())
+        // client addresses are needed to count failed logins
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .await
         .unwrap();
 }
 
-async fn hello_world(id: UserId) -> Response {
-    format!("Hello {}", id.0.to_string()).into_response()
+/// rotating keys: add the next key to JWT_KEYS, send SIGHUP to publish it,
+/// then point JWT_SIGNING_KEY in .env to it and send SIGHUP again to sign with it
+fn reload_keys_on_hangup(tokens: LocalTokenIO, dir: String) {
+    tokio::spawn(async move {
+        let mut hangup = signal(SignalKind::hangup()).expect("Expected to listen for SIGHUP");
+        while hangup.recv().await.is_some() {
+            // the environment is read once, a changed .env is only read here
+            let Some(kid) = dotenvy::dotenv_iter().ok().and_then(|vars| {
+                vars.flatten()
+                    .find(|(key, _)| key == "JWT_SIGNING_KEY")
+                    .map(|(_, kid)| kid)
+            }) else {
+                tracing::warn!("JWT_SIGNING_KEY missing in .env, keys are not reloaded");
+                continue;
+            };
+            match tokens.reload_keys(&dir, &kid) {
+                Ok(()) => tracing::info!("reloaded jwt keys, signing with {kid}"),
+                Err(error) => tracing::warn!("failed to reload jwt keys: {error:?}"),
+            }
+        }
+    });
+}
+
+/// keeps the permission log short by replacing it with a snapshot once in a while
+///
+/// a checkpoint refused because of batches in flight is simply retried on the next tick
+fn checkpoint_permissions(
+    permission: permission::Permission<Grantee, Action, PermissionBackend<Grantee, Action>>,
+    pool: sqlx::PgPool,
+) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PERMISSION_CHECKPOINT_INTERVAL);
+        // the first tick completes immediately, permissions have just been replayed
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let Ok(mut conn) = pool.acquire().await else {
+                tracing::warn!("failed to acquire postgres connection to checkpoint permissions");
+                continue;
+            };
+            match permission.checkpoint(&mut conn).await {
+                Ok(()) => tracing::info!("checkpointed permissions"),
+                Err(error) => tracing::warn!("failed to checkpoint permissions: {error}"),
+            }
+        }
+    });
+}
+
+async fn hello_world(current: CurrentUser) -> Response {
+    format!("Hello {}", current.account.to_string()).into_response()
 }
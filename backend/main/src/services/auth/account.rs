@@ -7,6 +7,7 @@ pub enum AccountStatus {
     Active,
     Inactive,
     Removed,
+    Unverified,
 }
 
 #[derive(Debug, Clone, sqlx::FromRow)]
@@ -1,39 +1,76 @@
+use crate::services::auth::account::AccountStatus;
 use crate::services::auth::error::AuthError;
-use crate::services::auth::io::{AccountIO, TokenIO};
+use crate::services::auth::io::{AccountIO, AttemptIO, AttemptKey, FactorError, FactorIO, TokenIO};
+use crate::services::auth::totp;
 use crate::services::auth::Auth;
-use crate::types::Services;
+use crate::types::{CurrentUser, Services};
 use axum::async_trait;
 use axum::extract::FromRequestParts;
 use axum::http::request::Parts;
+use std::convert::Infallible;
+use std::net::IpAddr;
 use std::sync::Arc;
+use std::time::SystemTime;
 use tokio::sync::RwLock;
 use uuid::Uuid;
 
+/// tokens handed out on login and refresh
+#[derive(Debug)]
+pub struct LoginTokens {
+    /// short lived token to authenticate requests
+    pub access_token: String,
+    /// single use token to get new tokens once the access_token expired
+    pub refresh_token: String,
+}
+
+/// result of logging in with a password
+#[derive(Debug)]
+pub enum Login {
+    Tokens(LoginTokens),
+    /// the account requires its second factor, a code has to be sent along with the challenge
+    SecondFactor {
+        challenge: String,
+    },
+}
+
+/// a totp secret waiting for its first code
+#[derive(Debug)]
+pub struct TotpEnrollment {
+    /// base32 encoded secret to enter it manually
+    pub secret: String,
+    /// otpauth uri of the secret, usually shown as qr code
+    pub uri: String,
+}
+
 // TODO: documentation
-pub struct AuthenticationClient<AccountCtx, TokenCtx, Accounts, Tokens> {
+pub struct AuthenticationClient<AccountCtx, TokenCtx, Accounts, Tokens, Attempts, Factors> {
     tokens: Arc<RwLock<Tokens>>,
     accounts: Arc<RwLock<Accounts>>,
-    account_ctx: AccountCtx,
-    token_ctx: TokenCtx,
+    attempts: Arc<RwLock<Attempts>>,
+    factors: Arc<RwLock<Factors>>,
+    ctx: std::marker::PhantomData<(AccountCtx, TokenCtx)>,
 }
 
-impl<'a, AccountCtx, TokenCtx, Accounts, Tokens>
-    AuthenticationClient<AccountCtx, TokenCtx, Accounts, Tokens>
+impl<AccountCtx, TokenCtx, Accounts, Tokens, Attempts, Factors>
+    AuthenticationClient<AccountCtx, TokenCtx, Accounts, Tokens, Attempts, Factors>
 where
     Accounts: AccountIO<Ctx = AccountCtx>,
     Tokens: TokenIO<Ctx = TokenCtx>,
+    Attempts: AttemptIO<Ctx = TokenCtx>,
+    Factors: FactorIO<Ctx = AccountCtx>,
 {
     pub fn new(
         accounts: Arc<RwLock<Accounts>>,
         tokens: Arc<RwLock<Tokens>>,
-        account_ctx: AccountCtx,
-        token_ctx: TokenCtx,
+        attempts: Arc<RwLock<Attempts>>,
+        factors: Arc<RwLock<Factors>>,
     ) -> Self {
         Self {
             tokens,
             accounts,
-            account_ctx,
-            token_ctx,
+            attempts,
+            factors,
+            ctx: std::marker::PhantomData,
         }
     }
 
@@ -47,10 +84,18 @@ where
         password: &str,
         account_ctx: &mut AccountCtx,
     ) -> Result<Uuid, AuthError> {
+        // hashing is slow, only hold the write lock to insert the account
+        let hash = self
+            .accounts
+            .read()
+            .await
+            .create_password_hash(password, account_ctx)
+            .await?;
+
         let mut accounts = self.accounts.write().await;
         match accounts.exists(external_id, account_ctx).await {
             Ok(_) => Err(AuthError::Credentials),
-            _ => Ok(accounts.create(external_id, password, account_ctx).await?),
+            _ => Ok(accounts.create(external_id, &hash, account_ctx).await?),
         }
     }
 
@@ -87,17 +132,250 @@ where
         Ok(())
     }
 
+    /// changes the password of an account after verifying its current password
+    ///
+    /// returns Credentials if the current password does not match
+    /// tokens are not revoked, call [logout_all](Self::logout_all) once the new password is committed
+    pub async fn change_password(
+        &mut self,
+        user_id: &Uuid,
+        password: &str,
+        new_password: &str,
+        account_ctx: &mut AccountCtx,
+    ) -> Result<(), AuthError> {
+        // hashing is slow, only hold the write lock to store the new hash
+        let hash = {
+            let accounts = self.accounts.read().await;
+            let account = accounts.get_by_internal(user_id, account_ctx).await?;
+            accounts
+                .verify_credentials(&account, password, account_ctx)
+                .await?;
+            accounts
+                .create_password_hash(new_password, account_ctx)
+                .await?
+        };
+
+        let mut accounts = self.accounts.write().await;
+        let mut account = accounts.get_by_internal(user_id, account_ctx).await?;
+        account.hash = hash;
+        accounts.update(account, account_ctx).await?;
+        Ok(())
+    }
+
+    /// revokes all login_tokens for this internal_id, every client has to log in again
+    pub async fn logout_all(
+        &mut self,
+        user_id: &Uuid,
+        token_ctx: &mut TokenCtx,
+    ) -> Result<(), AuthError> {
+        match self
+            .tokens
+            .write()
+            .await
+            .revoke_all(user_id, token_ctx)
+            .await
+        {
+            Some(error) => Err(error.into()),
+            None => Ok(()),
+        }
+    }
+
     /// logs in an external id authenticated by its password
-    /// returns a login token on success
+    /// returns a login token on success,
+    /// or a challenge for [login_second_factor](Self::login_second_factor) if the account has a second factor
+    ///
+    /// returns Unverified if the account has not been verified yet
+    ///
+    /// failed logins are counted per external_id and client in attempt_ctx,
+    /// which must not be rolled back with the request,
+    /// returns TooManyAttempts without verifying the password while either is locked
     ///
     /// caches login tokens for a configured [Duration](Tokens::new)
     pub async fn login(
         &mut self,
         external_id: &str,
         password: &str,
+        client: IpAddr,
         account_ctx: &mut AccountCtx,
         token_ctx: &mut TokenCtx,
-    ) -> Result<String, AuthError> {
+        attempt_ctx: &mut TokenCtx,
+    ) -> Result<Login, AuthError> {
+        let keys = [
+            &AttemptKey::Account(external_id.to_string()),
+            &AttemptKey::Client(client),
+        ];
+        // counted before verifying, thus concurrent logins can not exceed the limit,
+        // hashing is slow, the attempts are not locked meanwhile
+        reserve_attempt(&mut *self.attempts.write().await, keys, attempt_ctx).await?;
+        let result = self.verify_login(external_id, password, account_ctx).await;
+        let internal_id =
+            settle_attempt(&mut *self.attempts.write().await, keys, result, attempt_ctx).await?;
+
+        complete_login(
+            &internal_id,
+            &self.tokens,
+            &self.factors,
+            account_ctx,
+            token_ctx,
+        )
+        .await
+    }
+
+    /// completes a login with a code of the second factor or one of its recovery codes
+    ///
+    /// failed codes are counted per account and client like failed logins
+    /// the challenge is consumed in attempt_ctx once the code has been accepted,
+    /// until then a mistyped code can be retried
+    pub async fn login_second_factor(
+        &mut self,
+        challenge: &str,
+        code: &str,
+        client: IpAddr,
+        account_ctx: &mut AccountCtx,
+        token_ctx: &mut TokenCtx,
+        attempt_ctx: &mut TokenCtx,
+    ) -> Result<LoginTokens, AuthError> {
+        let internal_id = self
+            .tokens
+            .read()
+            .await
+            .verify_challenge(challenge, token_ctx)
+            .await?;
+
+        let keys = [
+            &AttemptKey::SecondFactor(internal_id),
+            &AttemptKey::Client(client),
+        ];
+        reserve_attempt(&mut *self.attempts.write().await, keys, attempt_ctx).await?;
+        let result = self
+            .verify_second_factor(&internal_id, code, account_ctx)
+            .await;
+        settle_attempt(&mut *self.attempts.write().await, keys, result, attempt_ctx).await?;
+        self.tokens
+            .write()
+            .await
+            .consume_challenge(challenge, attempt_ctx)
+            .await?;
+
+        let user = CurrentUser::without_user(internal_id);
+        issue(&self.tokens, &user, token_ctx).await
+    }
+
+    /// accepts each code of the factor and each recovery code only once
+    ///
+    /// use_step and consume_recovery_code reject reused codes themselves
+    async fn verify_second_factor(
+        &self,
+        internal_id: &Uuid,
+        code: &str,
+        account_ctx: &mut AccountCtx,
+    ) -> Result<(), AuthError> {
+        let factor = self
+            .factors
+            .read()
+            .await
+            .get(internal_id, account_ctx)
+            .await?;
+        if !factor.confirmed {
+            return Err(AuthError::Credentials);
+        }
+
+        let code = code.trim();
+        let error = match totp::verify(&factor.secret, code, SystemTime::now()) {
+            // the code has already been used, it might have been observed
+            Some(step) if factor.last_step.is_some_and(|last| step <= last) => {
+                Some(FactorError::Invalid)
+            }
+            Some(step) => {
+                self.factors
+                    .write()
+                    .await
+                    .use_step(internal_id, step, account_ctx)
+                    .await
+            }
+            // not a current code, it might be a recovery code
+            None => {
+                self.factors
+                    .write()
+                    .await
+                    .consume_recovery_code(internal_id, &code.to_ascii_uppercase(), account_ctx)
+                    .await
+            }
+        };
+        match error {
+            Some(e) => Err(e.into()),
+            None => Ok(()),
+        }
+    }
+
+    /// creates a new totp secret for an account
+    ///
+    /// it is not required on login until it has been confirmed with [confirm_totp](Self::confirm_totp)
+    /// returns Credentials if the account already has a confirmed factor
+    pub async fn enroll_totp(
+        &mut self,
+        internal_id: &Uuid,
+        account_ctx: &mut AccountCtx,
+    ) -> Result<TotpEnrollment, AuthError> {
+        let account = self
+            .accounts
+            .read()
+            .await
+            .get_by_internal(internal_id, account_ctx)
+            .await?;
+
+        let secret = totp::generate_secret();
+        if let Some(e) = self
+            .factors
+            .write()
+            .await
+            .enroll(internal_id, &secret, account_ctx)
+            .await
+        {
+            return Err(e.into());
+        }
+
+        Ok(TotpEnrollment {
+            secret: totp::base32(&secret),
+            uri: totp::otpauth_uri(totp::ISSUER, &account.id_external, &secret),
+        })
+    }
+
+    /// confirms an enrolled totp secret with its first code
+    ///
+    /// returns the recovery codes of the account, they are not stored in plain and can not be shown again
+    pub async fn confirm_totp(
+        &mut self,
+        internal_id: &Uuid,
+        code: &str,
+        account_ctx: &mut AccountCtx,
+    ) -> Result<Vec<String>, AuthError> {
+        let mut factors = self.factors.write().await;
+        let factor = factors.get(internal_id, account_ctx).await?;
+        if factor.confirmed {
+            return Err(AuthError::Credentials);
+        }
+        let Some(step) = totp::verify(&factor.secret, code.trim(), SystemTime::now()) else {
+            return Err(AuthError::Credentials);
+        };
+
+        let recovery_codes = totp::generate_recovery_codes();
+        match factors
+            .confirm(internal_id, step, &recovery_codes, account_ctx)
+            .await
+        {
+            Some(e) => Err(e.into()),
+            None => Ok(recovery_codes),
+        }
+    }
+
+    /// returns the internal_id of an active account authenticated by its password
+    async fn verify_login(
+        &self,
+        external_id: &str,
+        password: &str,
+        account_ctx: &mut AccountCtx,
+    ) -> Result<Uuid, AuthError> {
         let accounts = self.accounts.read().await;
         let account = accounts.get_by_external(external_id, account_ctx).await?;
 
@@ -105,17 +383,72 @@ where
             .verify_credentials(&account, password, account_ctx)
             .await
         {
-            Ok(self
-                .tokens
-                .write()
-                .await
-                .create(&internal_id, token_ctx)
-                .await?)
+            // only reveal the status to clients knowing the password
+            match account.status {
+                AccountStatus::Active => Ok(internal_id),
+                AccountStatus::Unverified => Err(AuthError::Unverified),
+                AccountStatus::Inactive | AccountStatus::Removed => Err(AuthError::Credentials),
+            }
         } else {
             Err(AuthError::Credentials)
         }
     }
 
+    /// exchanges a refresh_token for a new access and refresh token
+    ///
+    /// a refresh_token can only be used once, using it again logs out the client it was issued to
+    pub async fn refresh(
+        &mut self,
+        refresh_token: &str,
+        token_ctx: &mut TokenCtx,
+    ) -> Result<LoginTokens, AuthError> {
+        let mut tokens = self.tokens.write().await;
+        let (user, refresh_token) = tokens.rotate_refresh(refresh_token, token_ctx).await?;
+        Ok(LoginTokens {
+            access_token: tokens.create(&user, token_ctx).await?,
+            refresh_token,
+        })
+    }
+
+    /// switches a client of an account to its user in tenant
+    ///
+    /// the access and refresh token of the client are revoked,
+    /// returns new tokens for the user
+    pub async fn switch(
+        &mut self,
+        current: &CurrentUser,
+        access_token: &str,
+        refresh_token: &str,
+        tenant: &Uuid,
+        account_ctx: &mut AccountCtx,
+        token_ctx: &mut TokenCtx,
+    ) -> Result<LoginTokens, AuthError> {
+        let user = self
+            .accounts
+            .read()
+            .await
+            .get_user(&current.account, tenant, account_ctx)
+            .await?;
+
+        let mut tokens = self.tokens.write().await;
+        if let Some(e) = tokens.revoke_refresh(refresh_token, token_ctx).await {
+            return Err(e.into());
+        }
+        if let Some(e) = tokens.revoke(access_token, token_ctx).await {
+            return Err(e.into());
+        }
+
+        let user = CurrentUser {
+            account: current.account,
+            user: Some(user),
+            tenant: Some(*tenant),
+        };
+        Ok(LoginTokens {
+            access_token: tokens.create(&user, token_ctx).await?,
+            refresh_token: tokens.create_refresh(&user, token_ctx).await?,
+        })
+    }
+
     /// log out a client identified by the login_token
     ///
     /// other clients of this account might still have a valid token
@@ -142,12 +475,12 @@ where
     /// - it needs to exist
     /// - it had to be used within a configured [Duration](Tokens::new)
     ///
-    /// returns the internal id connected with the login token
+    /// returns the account and user connected with the login token
     pub async fn verify_token(
         &mut self,
         login_token: &str,
         token_ctx: &mut TokenCtx,
-    ) -> Result<Uuid, AuthError> {
+    ) -> Result<CurrentUser, AuthError> {
         self.tokens
             .read()
             .await
@@ -157,29 +490,155 @@ where
     }
 }
 
+/// issues tokens for an authenticated account, or a challenge if it has a confirmed second factor
+pub(super) async fn complete_login<Tokens: TokenIO, Factors: FactorIO>(
+    internal_id: &Uuid,
+    tokens: &RwLock<Tokens>,
+    factors: &RwLock<Factors>,
+    account_ctx: &mut Factors::Ctx,
+    token_ctx: &mut Tokens::Ctx,
+) -> Result<Login, AuthError> {
+    match factors.read().await.get(internal_id, account_ctx).await {
+        Ok(factor) if factor.confirmed => {
+            let challenge = tokens
+                .write()
+                .await
+                .create_challenge(internal_id, token_ctx)
+                .await?;
+            return Ok(Login::SecondFactor { challenge });
+        }
+        Ok(_) | Err(FactorError::NotFound) => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    // clients choose one of the users of the account with switch
+    let user = CurrentUser::without_user(*internal_id);
+    Ok(Login::Tokens(issue(tokens, &user, token_ctx).await?))
+}
+
+/// creates an access and refresh token for a user
+async fn issue<Tokens: TokenIO>(
+    tokens: &RwLock<Tokens>,
+    user: &CurrentUser,
+    token_ctx: &mut Tokens::Ctx,
+) -> Result<LoginTokens, AuthError> {
+    let mut tokens = tokens.write().await;
+    Ok(LoginTokens {
+        access_token: tokens.create(user, token_ctx).await?,
+        refresh_token: tokens.create_refresh(user, token_ctx).await?,
+    })
+}
+
+/// counts an attempt as failed for all keys before verifying its credentials
+///
+/// refuses to verify them while any of keys is locked, the attempt is not counted then
+async fn reserve_attempt<Attempts: AttemptIO>(
+    attempts: &mut Attempts,
+    keys: [&AttemptKey; 2],
+    attempt_ctx: &mut Attempts::Ctx,
+) -> Result<(), AuthError> {
+    for (reserved, key) in keys.iter().enumerate() {
+        let refused = match attempts.reserve(key, attempt_ctx).await {
+            Ok(None) => continue,
+            Ok(Some(retry_after)) => AuthError::TooManyAttempts(retry_after),
+            Err(e) => e.into(),
+        };
+        // the attempt is not made, thus it must not count for the keys before
+        for key in &keys[..reserved] {
+            if let Some(e) = attempts.release(key, attempt_ctx).await {
+                return Err(e.into());
+            }
+        }
+        return Err(refused);
+    }
+    Ok(())
+}
+
+/// keeps a reserved attempt with invalid credentials counted for all keys,
+/// forgets the failures of the first key and uncounts the attempt for the other otherwise
+///
+/// failures of the client are kept as it might still be guessing credentials of other accounts
+async fn settle_attempt<Attempts: AttemptIO, T>(
+    attempts: &mut Attempts,
+    keys: [&AttemptKey; 2],
+    result: Result<T, AuthError>,
+    attempt_ctx: &mut Attempts::Ctx,
+) -> Result<T, AuthError> {
+    if matches!(result, Err(AuthError::Credentials)) {
+        return result;
+    }
+    if let Some(e) = attempts.reset(keys[0], attempt_ctx).await {
+        return Err(e.into());
+    }
+    if let Some(e) = attempts.release(keys[1], attempt_ctx).await {
+        return Err(e.into());
+    }
+    result
+}
+
+#[async_trait]
+impl FromRequestParts<Arc<Services>> for Auth {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        _parts: &mut Parts,
+        state: &Arc<Services>,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(AuthenticationClient::new(
+            state.account_provider.clone(),
+            state.token_provider.clone(),
+            state.attempt_provider.clone(),
+            state.factor_provider.clone(),
+        ))
+    }
+}
+
 // TODO: what tests do we actually need? Some of these do not make sense
 // TODO: check if tests are correct and use correct asserts instead of expects
 #[cfg(test)]
 mod tests {
     use crate::services::auth::account::{Account, AccountStatus};
-    use crate::services::auth::client::AuthenticationClient;
-    use crate::services::auth::io::{AccountError, MockAccountIO, MockTokenIO, TokenError};
+    use crate::services::auth::client::{AuthenticationClient, Login};
+    use crate::services::auth::error::AuthError;
+    use crate::services::auth::io::{
+        AccountError, AttemptKey, FactorError, MockAccountIO, MockAttemptIO, MockFactorIO,
+        MockTokenIO, TokenError,
+    };
+    use crate::services::auth::totp::TotpFactor;
+    use crate::types::CurrentUser;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::sync::RwLock;
     use uuid::Uuid;
 
+    fn client() -> IpAddr {
+        IpAddr::V4(Ipv4Addr::LOCALHOST)
+    }
+
     #[tokio::test]
     async fn test_create_account() {
         let mut accounts = MockAccountIO::new();
-        let tokens = MockTokenIO::new();
 
         // given accounts can insert a new account
         accounts
             .expect_exists()
             .returning(|_, _| Err(AccountError::NotFound));
+        accounts
+            .expect_create_password_hash()
+            .returning(|_, _| Ok("some hash".to_string()));
         accounts
             .expect_create()
+            .withf(|_, hash, _| hash == "some hash")
             .returning(|_, _, _| Ok(Uuid::new_v4()));
 
-        let mut auth = AuthenticationService::new(accounts, tokens);
+        let mut auth = AuthenticationClient::new(
+            Arc::new(RwLock::new(accounts)),
+            Arc::new(RwLock::new(MockTokenIO::new())),
+            Arc::new(RwLock::new(MockAttemptIO::new())),
+            Arc::new(RwLock::new(MockFactorIO::new())),
+        );
 
         auth.create_account("some mail", "test1234", &mut ())
             .await
@@ -190,13 +649,21 @@ mod tests {
     #[should_panic(expected = "Expected Account to be created: Credentials")]
     async fn test_create_account_already_exists() {
         let mut accounts = MockAccountIO::new();
-        let tokens = MockTokenIO::new();
 
+        accounts
+            .expect_create_password_hash()
+            .returning(|_, _| Ok("some hash".to_string()));
         accounts
             .expect_exists()
             .returning(|_, _| Ok(Uuid::new_v4()));
+        accounts.expect_create().never();
 
-        let mut auth = AuthenticationService::new(accounts, tokens);
+        let mut auth = AuthenticationClient::new(
+            Arc::new(RwLock::new(accounts)),
+            Arc::new(RwLock::new(MockTokenIO::new())),
+            Arc::new(RwLock::new(MockAttemptIO::new())),
+            Arc::new(RwLock::new(MockFactorIO::new())),
+        );
 
         auth.create_account("some mail", "test1234", &mut ())
             .await
@@ -207,6 +674,11 @@ mod tests {
     async fn test_login() {
         let mut accounts = MockAccountIO::new();
         let mut tokens = MockTokenIO::new();
+        let mut attempts = MockAttemptIO::new();
+        let mut factors = MockFactorIO::new();
+        attempts.expect_reserve().returning(|_, _| Ok(None));
+        attempts.expect_reset().returning(|_, _| None);
+        attempts.expect_release().returning(|_, _| None);
         accounts.expect_get_by_external().returning(|_, _| {
             Ok(Account {
                 id: Default::default(),
@@ -219,13 +691,25 @@ mod tests {
         accounts
             .expect_verify_credentials()
             .returning(|_, _, _| Ok(Uuid::new_v4()));
+        // given the account has no second factor
+        factors
+            .expect_get()
+            .returning(|_, _| Err(FactorError::NotFound));
         tokens
             .expect_create()
             .returning(|_, _| Ok("some hash".to_string()));
+        tokens
+            .expect_create_refresh()
+            .returning(|_, _| Ok("some family.some secret".to_string()));
 
-        let mut auth = AuthenticationService::new(accounts, tokens);
+        let mut auth = AuthenticationClient::new(
+            Arc::new(RwLock::new(accounts)),
+            Arc::new(RwLock::new(tokens)),
+            Arc::new(RwLock::new(attempts)),
+            Arc::new(RwLock::new(factors)),
+        );
 
-        auth.login("some mail", "test1234", &mut (), &mut ())
+        auth.login("some mail", "test1234", client(), &mut (), &mut (), &mut ())
             .await
             .expect("Expected LoginToken");
     }
@@ -234,25 +718,37 @@ mod tests {
     #[should_panic(expected = "Expected LoginToken: Credentials")]
     async fn test_login_failure() {
         let mut accounts = MockAccountIO::new();
-        let tokens = MockTokenIO::new();
+        let mut attempts = MockAttemptIO::new();
+        attempts.expect_reserve().returning(|_, _| Ok(None));
         accounts
             .expect_get_by_external()
             .returning(|_, _| Err(AccountError::NotFound)); // the given user does not exist
 
-        let mut auth = AuthenticationService::new(accounts, tokens);
+        let mut auth = AuthenticationClient::new(
+            Arc::new(RwLock::new(accounts)),
+            Arc::new(RwLock::new(MockTokenIO::new())),
+            Arc::new(RwLock::new(attempts)),
+            Arc::new(RwLock::new(MockFactorIO::new())),
+        );
 
-        auth.login("some mail", "test1234", &mut (), &mut ())
+        auth.login("some mail", "test1234", client(), &mut (), &mut (), &mut ())
             .await
             .expect("Expected LoginToken");
     }
 
     #[tokio::test]
     async fn test_verify_token() {
-        let accounts = MockAccountIO::new();
         let mut tokens = MockTokenIO::new();
-        tokens.expect_verify().returning(|_, _| Ok(Uuid::new_v4()));
+        tokens
+            .expect_verify()
+            .returning(|_, _| Ok(CurrentUser::without_user(Uuid::new_v4())));
 
-        let mut auth = AuthenticationService::new(accounts, tokens);
+        let mut auth = AuthenticationClient::new(
+            Arc::new(RwLock::new(MockAccountIO::new())),
+            Arc::new(RwLock::new(tokens)),
+            Arc::new(RwLock::new(MockAttemptIO::new())),
+            Arc::new(RwLock::new(MockFactorIO::new())),
+        );
 
         auth.verify_token("some token", &mut ())
             .await
@@ -262,37 +758,779 @@ mod tests {
     #[tokio::test]
     #[should_panic(expected = "Expected Token to be valid: Credentials")]
     async fn test_verify_token_failure() {
-        let accounts = MockAccountIO::new();
         let mut tokens = MockTokenIO::new();
         tokens
             .expect_verify()
             .returning(|_, _| Err(TokenError::Invalid));
 
-        let mut auth = AuthenticationService::new(accounts, tokens);
+        let mut auth = AuthenticationClient::new(
+            Arc::new(RwLock::new(MockAccountIO::new())),
+            Arc::new(RwLock::new(tokens)),
+            Arc::new(RwLock::new(MockAttemptIO::new())),
+            Arc::new(RwLock::new(MockFactorIO::new())),
+        );
 
         auth.verify_token("some token", &mut ())
             .await
             .expect("Expected Token to be valid");
     }
-}
 
-#[async_trait]
-impl FromRequestParts<Arc<Services>> for Auth {
-    type Rejection = ();
+    #[tokio::test]
+    async fn test_login_unverified() {
+        let mut accounts = MockAccountIO::new();
+        let mut tokens = MockTokenIO::new();
+        let mut attempts = MockAttemptIO::new();
+        let factors = MockFactorIO::new();
+        attempts
+            .expect_reserve()
+            .times(2)
+            .returning(|_, _| Ok(None));
+        // the password was correct
+        attempts
+            .expect_reset()
+            .withf(|key, _| *key == AttemptKey::Account("some mail".to_string()))
+            .times(1)
+            .returning(|_, _| None);
+        attempts
+            .expect_release()
+            .withf(|key, _| *key == AttemptKey::Client(client()))
+            .times(1)
+            .returning(|_, _| None);
+        accounts.expect_get_by_external().returning(|_, _| {
+            Ok(Account {
+                id: Default::default(),
+                id_external: "some mail".to_string(),
+                hash: "some hash".to_string(),
+                status: AccountStatus::Unverified,
+            })
+        });
+        accounts
+            .expect_verify_credentials()
+            .returning(|account, _, _| Ok(account.id));
+        tokens.expect_create().never();
 
-    async fn from_request_parts(
-        parts: &mut Parts,
-        state: &Arc<Services>,
-    ) -> Result<Self, Self::Rejection> {
-        let token_provider = &state.token_provider;
-        let account_provider = &state.account_provider;
+        let mut auth = AuthenticationClient::new(
+            Arc::new(RwLock::new(accounts)),
+            Arc::new(RwLock::new(tokens)),
+            Arc::new(RwLock::new(attempts)),
+            Arc::new(RwLock::new(factors)),
+        );
 
-        //TODO: how can we then providers into  a new client?
-        // cloning would allow us to simply work without refs
-        //  rwlock?
-        Ok(AuthenticationClient::new(
-            account_provider.get_mut(),
-            token_provider.get_mut(),
-        ))
+        assert!(matches!(
+            auth.login("some mail", "test1234", client(), &mut (), &mut (), &mut ())
+                .await,
+            Err(AuthError::Unverified)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_login_locked() {
+        let mut accounts = MockAccountIO::new();
+        let tokens = MockTokenIO::new();
+        let mut attempts = MockAttemptIO::new();
+        let factors = MockFactorIO::new();
+        // given the client failed too often
+        attempts.expect_reserve().returning(|key, _| match key {
+            AttemptKey::Client(_) => Ok(Some(Duration::from_secs(30))),
+            _ => Ok(None),
+        });
+        // the password is not verified while locked, nor is the attempt counted for the account
+        accounts.expect_get_by_external().never();
+        accounts.expect_verify_credentials().never();
+        attempts
+            .expect_release()
+            .withf(|key, _| *key == AttemptKey::Account("some mail".to_string()))
+            .times(1)
+            .returning(|_, _| None);
+        attempts.expect_reset().never();
+
+        let mut auth = AuthenticationClient::new(
+            Arc::new(RwLock::new(accounts)),
+            Arc::new(RwLock::new(tokens)),
+            Arc::new(RwLock::new(attempts)),
+            Arc::new(RwLock::new(factors)),
+        );
+
+        assert!(matches!(
+            auth.login("some mail", "test1234", client(), &mut (), &mut (), &mut ()).await,
+            Err(AuthError::TooManyAttempts(retry_after)) if retry_after == Duration::from_secs(30)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_login_failure_counts_attempts() {
+        let mut accounts = MockAccountIO::new();
+        let tokens = MockTokenIO::new();
+        let mut attempts = MockAttemptIO::new();
+        let factors = MockFactorIO::new();
+        accounts.expect_get_by_external().returning(|_, _| {
+            Ok(Account {
+                id: Default::default(),
+                id_external: "some mail".to_string(),
+                hash: "some hash".to_string(),
+                status: AccountStatus::Active,
+            })
+        });
+        accounts
+            .expect_verify_credentials()
+            .returning(|_, _, _| Err(AccountError::Invalid));
+        // failures are counted for the account and the client
+        attempts
+            .expect_reserve()
+            .withf(|key, _| *key == AttemptKey::Account("some mail".to_string()))
+            .times(1)
+            .returning(|_, _| Ok(None));
+        attempts
+            .expect_reserve()
+            .withf(|key, _| *key == AttemptKey::Client(client()))
+            .times(1)
+            .returning(|_, _| Ok(None));
+        attempts.expect_release().never();
+        attempts.expect_reset().never();
+
+        let mut auth = AuthenticationClient::new(
+            Arc::new(RwLock::new(accounts)),
+            Arc::new(RwLock::new(tokens)),
+            Arc::new(RwLock::new(attempts)),
+            Arc::new(RwLock::new(factors)),
+        );
+
+        assert!(matches!(
+            auth.login("some mail", "wrong", client(), &mut (), &mut (), &mut ())
+                .await,
+            Err(AuthError::Credentials)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_login_verifies_without_holding_attempts() {
+        let mut accounts = MockAccountIO::new();
+        let tokens = MockTokenIO::new();
+        let mut attempts = MockAttemptIO::new();
+        let factors = MockFactorIO::new();
+        attempts.expect_reserve().returning(|_, _| Ok(None));
+        let attempts = Arc::new(RwLock::new(attempts));
+
+        accounts.expect_get_by_external().returning(|_, _| {
+            Ok(Account {
+                id: Default::default(),
+                id_external: "some mail".to_string(),
+                hash: "some hash".to_string(),
+                status: AccountStatus::Active,
+            })
+        });
+        // other logins may be counted while the password is hashed
+        let locked = attempts.clone();
+        accounts
+            .expect_verify_credentials()
+            .times(1)
+            .returning(move |_, _, _| {
+                assert!(locked.try_write().is_ok());
+                Err(AccountError::Invalid)
+            });
+
+        let mut auth = AuthenticationClient::new(
+            Arc::new(RwLock::new(accounts)),
+            Arc::new(RwLock::new(tokens)),
+            attempts,
+            Arc::new(RwLock::new(factors)),
+        );
+
+        assert!(matches!(
+            auth.login("some mail", "wrong", client(), &mut (), &mut (), &mut ())
+                .await,
+            Err(AuthError::Credentials)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_login_failures_are_limited() {
+        let mut accounts = MockAccountIO::new();
+        let mut attempts = MockAttemptIO::new();
+        // given the account is locked after 3 attempts
+        let reserved = Arc::new(AtomicU32::new(0));
+        attempts
+            .expect_reserve()
+            .returning(move |key, _| match key {
+                AttemptKey::Account(_) if reserved.fetch_add(1, Ordering::SeqCst) >= 3 => {
+                    Ok(Some(Duration::from_secs(1)))
+                }
+                _ => Ok(None),
+            });
+        accounts.expect_get_by_external().returning(|_, _| {
+            Ok(Account {
+                id: Default::default(),
+                id_external: "some mail".to_string(),
+                hash: "some hash".to_string(),
+                status: AccountStatus::Active,
+            })
+        });
+        // only the reserved attempts are verified
+        accounts
+            .expect_verify_credentials()
+            .times(3)
+            .returning(|_, _, _| Err(AccountError::Invalid));
+
+        let accounts = Arc::new(RwLock::new(accounts));
+        let tokens = Arc::new(RwLock::new(MockTokenIO::new()));
+        let attempts = Arc::new(RwLock::new(attempts));
+        let factors = Arc::new(RwLock::new(MockFactorIO::new()));
+        let login = || async {
+            AuthenticationClient::new(
+                accounts.clone(),
+                tokens.clone(),
+                attempts.clone(),
+                factors.clone(),
+            )
+            .login("some mail", "wrong", client(), &mut (), &mut (), &mut ())
+            .await
+        };
+
+        // all logins are in flight before the first one is verified
+        let verifying = accounts.write().await;
+        let (first, second, third, fourth, fifth, _) =
+            tokio::join!(login(), login(), login(), login(), login(), async {
+                tokio::task::yield_now().await;
+                drop(verifying);
+            });
+
+        let results = [first, second, third, fourth, fifth];
+        let failed = results
+            .iter()
+            .filter(|result| matches!(result, Err(AuthError::Credentials)))
+            .count();
+        let refused = results
+            .iter()
+            .filter(|result| matches!(result, Err(AuthError::TooManyAttempts(_))))
+            .count();
+        assert_eq!((failed, refused), (3, 2));
+    }
+
+    #[tokio::test]
+    async fn test_login_second_factor_required() {
+        let mut accounts = MockAccountIO::new();
+        let mut tokens = MockTokenIO::new();
+        let mut attempts = MockAttemptIO::new();
+        let mut factors = MockFactorIO::new();
+        attempts.expect_reserve().returning(|_, _| Ok(None));
+        attempts.expect_reset().returning(|_, _| None);
+        attempts.expect_release().returning(|_, _| None);
+        accounts.expect_get_by_external().returning(|_, _| {
+            Ok(Account {
+                id: Default::default(),
+                id_external: "some mail".to_string(),
+                hash: "some hash".to_string(),
+                status: AccountStatus::Active,
+            })
+        });
+        accounts
+            .expect_verify_credentials()
+            .returning(|account, _, _| Ok(account.id));
+        // given the account confirmed its factor
+        factors.expect_get().returning(|_, _| {
+            Ok(TotpFactor {
+                secret: b"12345678901234567890".to_vec(),
+                confirmed: true,
+                last_step: None,
+            })
+        });
+        tokens
+            .expect_create_challenge()
+            .returning(|_, _| Ok("challenge".to_string()));
+        // no tokens before the second factor
+        tokens.expect_create().never();
+        tokens.expect_create_refresh().never();
+
+        let mut auth = AuthenticationClient::new(
+            Arc::new(RwLock::new(accounts)),
+            Arc::new(RwLock::new(tokens)),
+            Arc::new(RwLock::new(attempts)),
+            Arc::new(RwLock::new(factors)),
+        );
+
+        assert!(matches!(
+            auth.login("some mail", "test1234", client(), &mut (), &mut (), &mut ())
+                .await,
+            Ok(Login::SecondFactor { challenge }) if challenge == "challenge"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_login_second_factor_recovery_code() {
+        let accounts = MockAccountIO::new();
+        let mut tokens = MockTokenIO::new();
+        let mut attempts = MockAttemptIO::new();
+        let mut factors = MockFactorIO::new();
+        let account = Uuid::new_v4();
+        tokens
+            .expect_verify_challenge()
+            .withf(|token, _| token == "challenge")
+            .returning(move |_, _| Ok(account));
+        attempts
+            .expect_reserve()
+            .times(2)
+            .returning(|_, _| Ok(None));
+        attempts
+            .expect_reset()
+            .withf(move |key, _| *key == AttemptKey::SecondFactor(account))
+            .times(1)
+            .returning(|_, _| None);
+        attempts
+            .expect_release()
+            .withf(|key, _| *key == AttemptKey::Client(client()))
+            .times(1)
+            .returning(|_, _| None);
+        factors.expect_get().returning(|_, _| {
+            Ok(TotpFactor {
+                secret: b"12345678901234567890".to_vec(),
+                confirmed: true,
+                last_step: None,
+            })
+        });
+        // recovery codes are matched case insensitive
+        factors
+            .expect_consume_recovery_code()
+            .withf(|_, code, _| code == "MZXW6YTBOI")
+            .times(1)
+            .returning(|_, _, _| None);
+        factors.expect_use_step().never();
+        tokens
+            .expect_consume_challenge()
+            .withf(|token, _| token == "challenge")
+            .times(1)
+            .returning(move |_, _| Ok(account));
+        tokens
+            .expect_create()
+            .withf(move |user, _| *user == CurrentUser::without_user(account))
+            .returning(|_, _| Ok("access".to_string()));
+        tokens
+            .expect_create_refresh()
+            .returning(|_, _| Ok("family.secret".to_string()));
+
+        let mut auth = AuthenticationClient::new(
+            Arc::new(RwLock::new(accounts)),
+            Arc::new(RwLock::new(tokens)),
+            Arc::new(RwLock::new(attempts)),
+            Arc::new(RwLock::new(factors)),
+        );
+
+        let login = auth
+            .login_second_factor(
+                "challenge",
+                " mzxw6ytboi ",
+                client(),
+                &mut (),
+                &mut (),
+                &mut (),
+            )
+            .await
+            .expect("Expected LoginTokens");
+        assert_eq!(login.access_token, "access");
+    }
+
+    #[tokio::test]
+    async fn test_login_second_factor_failure_counts_attempts() {
+        let accounts = MockAccountIO::new();
+        let mut tokens = MockTokenIO::new();
+        let mut attempts = MockAttemptIO::new();
+        let mut factors = MockFactorIO::new();
+        let account = Uuid::new_v4();
+        tokens
+            .expect_verify_challenge()
+            .returning(move |_, _| Ok(account));
+        factors.expect_get().returning(|_, _| {
+            Ok(TotpFactor {
+                secret: b"12345678901234567890".to_vec(),
+                confirmed: true,
+                last_step: None,
+            })
+        });
+        // given the recovery code has been used before
+        factors
+            .expect_consume_recovery_code()
+            .returning(|_, _, _| Some(FactorError::Invalid));
+        attempts
+            .expect_reserve()
+            .withf(move |key, _| *key == AttemptKey::SecondFactor(account))
+            .times(1)
+            .returning(|_, _| Ok(None));
+        attempts
+            .expect_reserve()
+            .withf(|key, _| *key == AttemptKey::Client(client()))
+            .times(1)
+            .returning(|_, _| Ok(None));
+        attempts.expect_release().never();
+        attempts.expect_reset().never();
+        // the challenge can be used again with the right code
+        tokens.expect_consume_challenge().never();
+        tokens.expect_create().never();
+
+        let mut auth = AuthenticationClient::new(
+            Arc::new(RwLock::new(accounts)),
+            Arc::new(RwLock::new(tokens)),
+            Arc::new(RwLock::new(attempts)),
+            Arc::new(RwLock::new(factors)),
+        );
+
+        assert!(matches!(
+            auth.login_second_factor("challenge", "used", client(), &mut (), &mut (), &mut ())
+                .await,
+            Err(AuthError::Credentials)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_login_second_factor_consumed_challenge() {
+        let accounts = MockAccountIO::new();
+        let mut tokens = MockTokenIO::new();
+        let mut attempts = MockAttemptIO::new();
+        let mut factors = MockFactorIO::new();
+        let account = Uuid::new_v4();
+        tokens
+            .expect_verify_challenge()
+            .returning(move |_, _| Ok(account));
+        attempts.expect_reserve().returning(|_, _| Ok(None));
+        attempts.expect_reset().returning(|_, _| None);
+        attempts.expect_release().returning(|_, _| None);
+        factors.expect_get().returning(|_, _| {
+            Ok(TotpFactor {
+                secret: b"12345678901234567890".to_vec(),
+                confirmed: true,
+                last_step: None,
+            })
+        });
+        factors
+            .expect_consume_recovery_code()
+            .returning(|_, _, _| None);
+        // given another login completed with the challenge
+        tokens
+            .expect_consume_challenge()
+            .returning(|_, _| Err(TokenError::Invalid));
+        tokens.expect_create().never();
+
+        let mut auth = AuthenticationClient::new(
+            Arc::new(RwLock::new(accounts)),
+            Arc::new(RwLock::new(tokens)),
+            Arc::new(RwLock::new(attempts)),
+            Arc::new(RwLock::new(factors)),
+        );
+
+        assert!(matches!(
+            auth.login_second_factor(
+                "challenge",
+                "MZXW6YTBOI",
+                client(),
+                &mut (),
+                &mut (),
+                &mut ()
+            )
+            .await,
+            Err(AuthError::Credentials)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_confirm_totp_invalid_code() {
+        let accounts = MockAccountIO::new();
+        let tokens = MockTokenIO::new();
+        let attempts = MockAttemptIO::new();
+        let mut factors = MockFactorIO::new();
+        factors.expect_get().returning(|_, _| {
+            Ok(TotpFactor {
+                secret: b"12345678901234567890".to_vec(),
+                confirmed: false,
+                last_step: None,
+            })
+        });
+        // the factor stays unconfirmed
+        factors.expect_confirm().never();
+
+        let mut auth = AuthenticationClient::new(
+            Arc::new(RwLock::new(accounts)),
+            Arc::new(RwLock::new(tokens)),
+            Arc::new(RwLock::new(attempts)),
+            Arc::new(RwLock::new(factors)),
+        );
+
+        assert!(matches!(
+            auth.confirm_totp(&Uuid::new_v4(), "12345", &mut ()).await,
+            Err(AuthError::Credentials)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_refresh() {
+        let accounts = MockAccountIO::new();
+        let mut tokens = MockTokenIO::new();
+        let attempts = MockAttemptIO::new();
+        let factors = MockFactorIO::new();
+        let user_id = Uuid::new_v4();
+        tokens
+            .expect_rotate_refresh()
+            .withf(|token, _| token == "family.old")
+            .returning(move |_, _| {
+                Ok((CurrentUser::without_user(user_id), "family.new".to_string()))
+            });
+        tokens
+            .expect_create()
+            .withf(move |user, _| user.account == user_id)
+            .returning(|_, _| Ok("access".to_string()));
+
+        let mut auth = AuthenticationClient::new(
+            Arc::new(RwLock::new(accounts)),
+            Arc::new(RwLock::new(tokens)),
+            Arc::new(RwLock::new(attempts)),
+            Arc::new(RwLock::new(factors)),
+        );
+
+        let login = auth
+            .refresh("family.old", &mut ())
+            .await
+            .expect("Expected LoginTokens");
+        assert_eq!(login.access_token, "access");
+        assert_eq!(login.refresh_token, "family.new");
+    }
+
+    #[tokio::test]
+    async fn test_refresh_reused() {
+        let accounts = MockAccountIO::new();
+        let mut tokens = MockTokenIO::new();
+        let attempts = MockAttemptIO::new();
+        let factors = MockFactorIO::new();
+        // given the refresh token has already been rotated
+        tokens
+            .expect_rotate_refresh()
+            .returning(|_, _| Err(TokenError::Invalid));
+        tokens.expect_create().never();
+
+        let mut auth = AuthenticationClient::new(
+            Arc::new(RwLock::new(accounts)),
+            Arc::new(RwLock::new(tokens)),
+            Arc::new(RwLock::new(attempts)),
+            Arc::new(RwLock::new(factors)),
+        );
+
+        assert!(matches!(
+            auth.refresh("family.old", &mut ()).await,
+            Err(AuthError::Credentials)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_switch() {
+        let mut accounts = MockAccountIO::new();
+        let mut tokens = MockTokenIO::new();
+        let attempts = MockAttemptIO::new();
+        let factors = MockFactorIO::new();
+        let current = CurrentUser::without_user(Uuid::new_v4());
+        let tenant = Uuid::new_v4();
+        let user = Uuid::new_v4();
+        accounts
+            .expect_get_user()
+            .withf(move |id, t, _| *id == current.account && *t == tenant)
+            .returning(move |_, _, _| Ok(user));
+        tokens
+            .expect_revoke_refresh()
+            .withf(|token, _| token == "family.secret")
+            .times(1)
+            .returning(|_, _| None);
+        tokens
+            .expect_revoke()
+            .withf(|token, _| token == "access")
+            .times(1)
+            .returning(|_, _| None);
+        let switched = CurrentUser {
+            account: current.account,
+            user: Some(user),
+            tenant: Some(tenant),
+        };
+        tokens
+            .expect_create()
+            .withf(move |u, _| *u == switched)
+            .returning(|_, _| Ok("new access".to_string()));
+        tokens
+            .expect_create_refresh()
+            .withf(move |u, _| *u == switched)
+            .returning(|_, _| Ok("new family.secret".to_string()));
+
+        let mut auth = AuthenticationClient::new(
+            Arc::new(RwLock::new(accounts)),
+            Arc::new(RwLock::new(tokens)),
+            Arc::new(RwLock::new(attempts)),
+            Arc::new(RwLock::new(factors)),
+        );
+
+        let login = auth
+            .switch(
+                &current,
+                "access",
+                "family.secret",
+                &tenant,
+                &mut (),
+                &mut (),
+            )
+            .await
+            .expect("Expected LoginTokens");
+        assert_eq!(login.access_token, "new access");
+    }
+
+    #[tokio::test]
+    async fn test_switch_not_a_member() {
+        let mut accounts = MockAccountIO::new();
+        let mut tokens = MockTokenIO::new();
+        let attempts = MockAttemptIO::new();
+        let factors = MockFactorIO::new();
+        // given the account has no user in the tenant
+        accounts
+            .expect_get_user()
+            .returning(|_, _, _| Err(AccountError::NotFound));
+        // the current tokens stay valid
+        tokens.expect_revoke().never();
+        tokens.expect_revoke_refresh().never();
+
+        let mut auth = AuthenticationClient::new(
+            Arc::new(RwLock::new(accounts)),
+            Arc::new(RwLock::new(tokens)),
+            Arc::new(RwLock::new(attempts)),
+            Arc::new(RwLock::new(factors)),
+        );
+
+        assert!(matches!(
+            auth.switch(
+                &CurrentUser::without_user(Uuid::new_v4()),
+                "access",
+                "family.secret",
+                &Uuid::new_v4(),
+                &mut (),
+                &mut (),
+            )
+            .await,
+            Err(AuthError::Credentials)
+        ));
+    }
+
+    fn account_with_hash(id: &Uuid, hash: &str) -> Account {
+        Account {
+            id: *id,
+            id_external: "some mail".to_string(),
+            hash: hash.to_string(),
+            status: AccountStatus::Active,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_change_password() {
+        let mut accounts = MockAccountIO::new();
+        let tokens = MockTokenIO::new();
+        let attempts = MockAttemptIO::new();
+        let factors = MockFactorIO::new();
+        let user_id = Uuid::new_v4();
+        accounts
+            .expect_get_by_internal()
+            .returning(|id, _| Ok(account_with_hash(id, "old hash")));
+        accounts
+            .expect_verify_credentials()
+            .returning(|account, _, _| Ok(account.id));
+        accounts
+            .expect_create_password_hash()
+            .returning(|_, _| Ok("new hash".to_string()));
+        accounts
+            .expect_update()
+            .withf(|account, _| account.hash == "new hash")
+            .times(1)
+            .returning(|account, _| Ok(account));
+
+        let mut auth = AuthenticationClient::new(
+            Arc::new(RwLock::new(accounts)),
+            Arc::new(RwLock::new(tokens)),
+            Arc::new(RwLock::new(attempts)),
+            Arc::new(RwLock::new(factors)),
+        );
+
+        auth.change_password(&user_id, "test1234", "test5678", &mut ())
+            .await
+            .expect("Expected password to be changed");
+    }
+
+    #[tokio::test]
+    async fn test_change_password_wrong_password() {
+        let mut accounts = MockAccountIO::new();
+        let tokens = MockTokenIO::new();
+        let attempts = MockAttemptIO::new();
+        let factors = MockFactorIO::new();
+        accounts
+            .expect_get_by_internal()
+            .returning(|id, _| Ok(account_with_hash(id, "old hash")));
+        accounts
+            .expect_verify_credentials()
+            .returning(|_, _, _| Err(AccountError::Invalid));
+        // the account is not touched
+        accounts.expect_update().never();
+
+        let mut auth = AuthenticationClient::new(
+            Arc::new(RwLock::new(accounts)),
+            Arc::new(RwLock::new(tokens)),
+            Arc::new(RwLock::new(attempts)),
+            Arc::new(RwLock::new(factors)),
+        );
+
+        assert!(matches!(
+            auth.change_password(&Uuid::new_v4(), "wrong", "test5678", &mut ())
+                .await,
+            Err(AuthError::Credentials)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_change_password_io_failure() {
+        let mut accounts = MockAccountIO::new();
+        let tokens = MockTokenIO::new();
+        let attempts = MockAttemptIO::new();
+        let factors = MockFactorIO::new();
+        accounts
+            .expect_get_by_internal()
+            .returning(|id, _| Ok(account_with_hash(id, "unparsable hash")));
+        accounts
+            .expect_verify_credentials()
+            .returning(|_, _, _| Err(AccountError::IO));
+        accounts.expect_update().never();
+
+        let mut auth = AuthenticationClient::new(
+            Arc::new(RwLock::new(accounts)),
+            Arc::new(RwLock::new(tokens)),
+            Arc::new(RwLock::new(attempts)),
+            Arc::new(RwLock::new(factors)),
+        );
+
+        assert!(matches!(
+            auth.change_password(&Uuid::new_v4(), "test1234", "test5678", &mut ())
+                .await,
+            Err(AuthError::IO)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_logout_all() {
+        let accounts = MockAccountIO::new();
+        let mut tokens = MockTokenIO::new();
+        let attempts = MockAttemptIO::new();
+        let factors = MockFactorIO::new();
+        let user_id = Uuid::new_v4();
+        tokens
+            .expect_revoke_all()
+            .withf(move |id, _| *id == user_id)
+            .times(1)
+            .returning(|_, _| None);
+
+        let mut auth = AuthenticationClient::new(
+            Arc::new(RwLock::new(accounts)),
+            Arc::new(RwLock::new(tokens)),
+            Arc::new(RwLock::new(attempts)),
+            Arc::new(RwLock::new(factors)),
+        );
+
+        auth.logout_all(&user_id, &mut ())
+            .await
+            .expect("Expected tokens to be revoked");
     }
 }
@@ -1,4 +1,5 @@
-use crate::services::auth::io::{AccountError, TokenError};
+use crate::services::auth::io::{AccountError, AttemptError, FactorError, TokenError};
+use std::time::Duration;
 use thiserror::Error;
 
 //TODO: make custom messages possible to distinguish? Or more different errors?
@@ -6,6 +7,10 @@ use thiserror::Error;
 pub enum AuthError {
     #[error("Invalid Credentials")]
     Credentials,
+    #[error("Account has not been verified")]
+    Unverified,
+    #[error("Too many failed logins, retry after {0:?}")]
+    TooManyAttempts(Duration),
     #[error("Error during IO Operations")]
     IO,
 }
@@ -28,3 +33,22 @@ impl From<AccountError> for AuthError {
         }
     }
 }
+
+impl From<AttemptError> for AuthError {
+    fn from(value: AttemptError) -> Self {
+        match value {
+            AttemptError::IO => AuthError::IO,
+        }
+    }
+}
+
+impl From<FactorError> for AuthError {
+    fn from(value: FactorError) -> Self {
+        match value {
+            FactorError::IO => AuthError::IO,
+            FactorError::NotFound => AuthError::Credentials,
+            FactorError::Exists => AuthError::Credentials,
+            FactorError::Invalid => AuthError::Credentials,
+        }
+    }
+}
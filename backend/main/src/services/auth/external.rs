@@ -0,0 +1,469 @@
+use crate::services::auth::account::AccountStatus;
+use crate::services::auth::client::{complete_login, Login};
+use crate::services::auth::error::AuthError;
+use crate::services::auth::io::{
+    AccountError, AccountIO, FactorIO, IdentityProviderError, IdentityProviderIO, TokenIO,
+};
+use crate::services::auth::oidc::{IdClaims, PendingLogin};
+use crate::services::auth::ExternalLogin;
+use crate::types::Services;
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use axum::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::http::StatusCode;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+/// a login started at an identity provider
+#[derive(Debug)]
+pub struct Authorization {
+    /// url of the identity provider to redirect the user agent to
+    pub url: String,
+    /// the state the identity provider redirects back with,
+    /// it has to be bound to the user agent so nobody can complete a login started by someone else
+    pub state: String,
+}
+
+/// logs in accounts at an external OpenID Connect provider
+///
+/// subjects of the provider are linked to accounts on their first login,
+/// afterwards they log in with the normal tokens of this service
+pub struct ExternalLoginClient<AccountCtx, TokenCtx, Accounts, Tokens, Factors, Provider> {
+    accounts: Arc<RwLock<Accounts>>,
+    tokens: Arc<RwLock<Tokens>>,
+    factors: Arc<RwLock<Factors>>,
+    provider: Arc<Provider>,
+    ctx: std::marker::PhantomData<(AccountCtx, TokenCtx)>,
+}
+
+impl<AccountCtx, TokenCtx, Accounts, Tokens, Factors, Provider>
+    ExternalLoginClient<AccountCtx, TokenCtx, Accounts, Tokens, Factors, Provider>
+where
+    Accounts: AccountIO<Ctx = AccountCtx>,
+    Tokens: TokenIO<Ctx = TokenCtx>,
+    Factors: FactorIO<Ctx = AccountCtx>,
+    Provider: IdentityProviderIO,
+{
+    pub fn new(
+        accounts: Arc<RwLock<Accounts>>,
+        tokens: Arc<RwLock<Tokens>>,
+        factors: Arc<RwLock<Factors>>,
+        provider: Arc<Provider>,
+    ) -> Self {
+        Self {
+            accounts,
+            tokens,
+            factors,
+            provider,
+            ctx: std::marker::PhantomData,
+        }
+    }
+
+    /// starts a login, returns the url of the identity provider to redirect the user agent to
+    pub async fn authorize(
+        &mut self,
+        state_ctx: &mut TokenCtx,
+    ) -> Result<Authorization, AuthError> {
+        let login = PendingLogin::generate();
+        let state = self
+            .tokens
+            .write()
+            .await
+            .create_login_state(&login, state_ctx)
+            .await?;
+        let url = self
+            .provider
+            .authorization_url(&state, &login)
+            .await
+            .or(Err(AuthError::IO))?;
+        Ok(Authorization { url, state })
+    }
+
+    /// completes a login with the code and state the identity provider redirected back with
+    ///
+    /// unknown subjects are linked to the account of their verified mail address,
+    /// an account is created if none exists
+    /// returns Unverified if that account has not been verified,
+    /// thus nobody can take over accounts by registering the mail address of others beforehand
+    pub async fn login(
+        &mut self,
+        code: &str,
+        state: &str,
+        account_ctx: &mut AccountCtx,
+        token_ctx: &mut TokenCtx,
+        state_ctx: &mut TokenCtx,
+    ) -> Result<Login, AuthError> {
+        let login = self
+            .tokens
+            .write()
+            .await
+            .consume_login_state(state, state_ctx)
+            .await?;
+        let claims = match self.provider.authenticate(code, &login).await {
+            Ok(claims) => claims,
+            Err(IdentityProviderError::IO) => return Err(AuthError::IO),
+            Err(IdentityProviderError::Invalid) => return Err(AuthError::Credentials),
+        };
+
+        let linked = self
+            .accounts
+            .read()
+            .await
+            .get_by_identity(&claims.iss, &claims.sub, account_ctx)
+            .await;
+        let internal_id = match linked {
+            Ok(internal_id) => internal_id,
+            Err(AccountError::NotFound) => self.link(&claims, account_ctx).await?,
+            Err(e) => return Err(e.into()),
+        };
+
+        let account = self
+            .accounts
+            .read()
+            .await
+            .get_by_internal(&internal_id, account_ctx)
+            .await?;
+        if !matches!(account.status, AccountStatus::Active) {
+            return Err(AuthError::Credentials);
+        }
+
+        complete_login(
+            &internal_id,
+            &self.tokens,
+            &self.factors,
+            account_ctx,
+            token_ctx,
+        )
+        .await
+    }
+
+    /// links a subject to the account of its verified mail address, creating it if necessary
+    async fn link(
+        &mut self,
+        claims: &IdClaims,
+        account_ctx: &mut AccountCtx,
+    ) -> Result<Uuid, AuthError> {
+        let Some(email) = claims.verified_email() else {
+            return Err(AuthError::Credentials);
+        };
+
+        let existing = self
+            .accounts
+            .read()
+            .await
+            .get_by_external(email, account_ctx)
+            .await;
+        let internal_id = match existing {
+            Ok(account) => match account.status {
+                AccountStatus::Active => account.id,
+                AccountStatus::Unverified => return Err(AuthError::Unverified),
+                _ => return Err(AuthError::Credentials),
+            },
+            Err(AccountError::NotFound) => {
+                // the password can not be guessed, it can be set with a password reset
+                let mut password = [0u8; 32];
+                OsRng.fill_bytes(&mut password);
+                // hashing is slow, only hold the write lock to insert the account
+                let hash = self
+                    .accounts
+                    .read()
+                    .await
+                    .create_password_hash(&hex::encode(password), account_ctx)
+                    .await?;
+
+                let mut accounts = self.accounts.write().await;
+                let internal_id = accounts.create(email, &hash, account_ctx).await?;
+                // the identity provider verified the mail address
+                let mut account = accounts.get_by_internal(&internal_id, account_ctx).await?;
+                account.status = AccountStatus::Active;
+                accounts.update(account, account_ctx).await?;
+                internal_id
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        match self
+            .accounts
+            .write()
+            .await
+            .link_identity(&internal_id, &claims.iss, &claims.sub, account_ctx)
+            .await
+        {
+            Some(e) => Err(e.into()),
+            None => Ok(internal_id),
+        }
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<Arc<Services>> for ExternalLogin {
+    /// external logins are only available if an identity provider has been configured
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(
+        _parts: &mut Parts,
+        state: &Arc<Services>,
+    ) -> Result<Self, Self::Rejection> {
+        let Some(provider) = &state.identity_provider else {
+            return Err(StatusCode::NOT_FOUND);
+        };
+        Ok(ExternalLoginClient::new(
+            state.account_provider.clone(),
+            state.token_provider.clone(),
+            state.factor_provider.clone(),
+            provider.clone(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::auth::account::{Account, AccountStatus};
+    use crate::services::auth::client::Login;
+    use crate::services::auth::error::AuthError;
+    use crate::services::auth::external::ExternalLoginClient;
+    use crate::services::auth::io::{
+        AccountError, FactorError, MockAccountIO, MockFactorIO, MockIdentityProviderIO,
+        MockTokenIO, TokenError,
+    };
+    use crate::services::auth::oidc::{IdClaims, PendingLogin};
+    use std::sync::Arc;
+    use tokio::sync::RwLock;
+    use uuid::Uuid;
+
+    const ISSUER: &str = "http://idp.test";
+
+    fn client(
+        accounts: MockAccountIO,
+        tokens: MockTokenIO,
+        provider: MockIdentityProviderIO,
+    ) -> ExternalLoginClient<(), (), MockAccountIO, MockTokenIO, MockFactorIO, MockIdentityProviderIO>
+    {
+        let mut factors = MockFactorIO::new();
+        factors
+            .expect_get()
+            .returning(|_, _| Err(FactorError::NotFound));
+        ExternalLoginClient::new(
+            Arc::new(RwLock::new(accounts)),
+            Arc::new(RwLock::new(tokens)),
+            Arc::new(RwLock::new(factors)),
+            Arc::new(provider),
+        )
+    }
+
+    fn account(id: Uuid, status: AccountStatus) -> Account {
+        Account {
+            id,
+            id_external: "some@mail.de".to_string(),
+            hash: "some hash".to_string(),
+            status,
+        }
+    }
+
+    /// tokens knowing the state of a pending login and issuing tokens for it
+    fn tokens() -> MockTokenIO {
+        let mut tokens = MockTokenIO::new();
+        tokens
+            .expect_consume_login_state()
+            .withf(|state, _| state == "some state")
+            .returning(|_, _| {
+                Ok(PendingLogin {
+                    verifier: "verifier".to_string(),
+                    nonce: "nonce".to_string(),
+                })
+            });
+        tokens
+            .expect_create()
+            .returning(|_, _| Ok("access".to_string()));
+        tokens
+            .expect_create_refresh()
+            .returning(|_, _| Ok("family.secret".to_string()));
+        tokens
+    }
+
+    /// provider authenticating a subject with a mail address
+    fn provider(email_verified: bool) -> MockIdentityProviderIO {
+        let mut provider = MockIdentityProviderIO::new();
+        provider
+            .expect_authenticate()
+            .withf(|code, login| code == "some code" && login.verifier == "verifier")
+            .returning(move |_, _| {
+                Ok(IdClaims {
+                    iss: ISSUER.to_string(),
+                    sub: "external subject".to_string(),
+                    nonce: Some("nonce".to_string()),
+                    email: Some("some@mail.de".to_string()),
+                    email_verified,
+                })
+            });
+        provider
+    }
+
+    #[tokio::test]
+    async fn test_authorize_stores_login() {
+        let mut tokens = MockTokenIO::new();
+        let mut provider = MockIdentityProviderIO::new();
+        tokens
+            .expect_create_login_state()
+            .times(1)
+            .returning(|_, _| Ok("some state".to_string()));
+        provider
+            .expect_authorization_url()
+            .withf(|state, _| state == "some state")
+            .returning(|_, _| Ok("http://idp.test/authorize".to_string()));
+
+        let authorization = client(MockAccountIO::new(), tokens, provider)
+            .authorize(&mut ())
+            .await
+            .expect("Expected authorization url");
+        assert_eq!(authorization.url, "http://idp.test/authorize");
+        assert_eq!(authorization.state, "some state");
+    }
+
+    #[tokio::test]
+    async fn test_login_linked_subject() {
+        let mut accounts = MockAccountIO::new();
+        let internal_id = Uuid::new_v4();
+        accounts
+            .expect_get_by_identity()
+            .withf(|issuer, subject, _| issuer == ISSUER && subject == "external subject")
+            .returning(move |_, _, _| Ok(internal_id));
+        accounts
+            .expect_get_by_internal()
+            .returning(|id, _| Ok(account(*id, AccountStatus::Active)));
+        accounts.expect_link_identity().never();
+
+        let login = client(accounts, tokens(), provider(true))
+            .login("some code", "some state", &mut (), &mut (), &mut ())
+            .await
+            .expect("Expected Login");
+        assert!(matches!(login, Login::Tokens(tokens) if tokens.access_token == "access"));
+    }
+
+    #[tokio::test]
+    async fn test_login_links_account_of_verified_mail() {
+        let mut accounts = MockAccountIO::new();
+        let internal_id = Uuid::new_v4();
+        accounts
+            .expect_get_by_identity()
+            .returning(|_, _, _| Err(AccountError::NotFound));
+        accounts
+            .expect_get_by_external()
+            .withf(|id, _| id == "some@mail.de")
+            .returning(move |_, _| Ok(account(internal_id, AccountStatus::Active)));
+        accounts.expect_create().never();
+        accounts
+            .expect_link_identity()
+            .withf(move |id, issuer, subject, _| {
+                *id == internal_id && issuer == ISSUER && subject == "external subject"
+            })
+            .times(1)
+            .returning(|_, _, _, _| None);
+        accounts
+            .expect_get_by_internal()
+            .returning(|id, _| Ok(account(*id, AccountStatus::Active)));
+
+        let login = client(accounts, tokens(), provider(true))
+            .login("some code", "some state", &mut (), &mut (), &mut ())
+            .await
+            .expect("Expected Login");
+        assert!(matches!(login, Login::Tokens(_)));
+    }
+
+    #[tokio::test]
+    async fn test_login_creates_active_account() {
+        let mut accounts = MockAccountIO::new();
+        let internal_id = Uuid::new_v4();
+        accounts
+            .expect_get_by_identity()
+            .returning(|_, _, _| Err(AccountError::NotFound));
+        accounts
+            .expect_get_by_external()
+            .returning(|_, _| Err(AccountError::NotFound));
+        accounts
+            .expect_create_password_hash()
+            .times(1)
+            .returning(|_, _| Ok("some hash".to_string()));
+        accounts
+            .expect_create()
+            .withf(|id, hash, _| id == "some@mail.de" && hash == "some hash")
+            .times(1)
+            .returning(move |_, _, _| Ok(internal_id));
+        accounts
+            .expect_update()
+            .withf(|account, _| matches!(account.status, AccountStatus::Active))
+            .times(1)
+            .returning(|account, _| Ok(account));
+        accounts
+            .expect_link_identity()
+            .times(1)
+            .returning(|_, _, _, _| None);
+        accounts
+            .expect_get_by_internal()
+            .returning(|id, _| Ok(account(*id, AccountStatus::Active)));
+
+        let login = client(accounts, tokens(), provider(true))
+            .login("some code", "some state", &mut (), &mut (), &mut ())
+            .await
+            .expect("Expected Login");
+        assert!(matches!(login, Login::Tokens(_)));
+    }
+
+    #[tokio::test]
+    async fn test_login_does_not_link_unverified_accounts() {
+        let mut accounts = MockAccountIO::new();
+        accounts
+            .expect_get_by_identity()
+            .returning(|_, _, _| Err(AccountError::NotFound));
+        // given somebody registered the mail address without verifying it
+        accounts
+            .expect_get_by_external()
+            .returning(|_, _| Ok(account(Uuid::new_v4(), AccountStatus::Unverified)));
+        accounts.expect_link_identity().never();
+
+        assert!(matches!(
+            client(accounts, tokens(), provider(true))
+                .login("some code", "some state", &mut (), &mut (), &mut ())
+                .await,
+            Err(AuthError::Unverified)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_login_requires_verified_mail_to_link() {
+        let mut accounts = MockAccountIO::new();
+        accounts
+            .expect_get_by_identity()
+            .returning(|_, _, _| Err(AccountError::NotFound));
+        accounts.expect_get_by_external().never();
+        accounts.expect_create().never();
+        accounts.expect_link_identity().never();
+
+        assert!(matches!(
+            client(accounts, tokens(), provider(false))
+                .login("some code", "some state", &mut (), &mut (), &mut ())
+                .await,
+            Err(AuthError::Credentials)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_login_unknown_state() {
+        let mut tokens = MockTokenIO::new();
+        let mut provider = MockIdentityProviderIO::new();
+        tokens
+            .expect_consume_login_state()
+            .returning(|_, _| Err(TokenError::Invalid));
+        // the code is not redeemed without the verifier of its login
+        provider.expect_authenticate().never();
+
+        assert!(matches!(
+            client(MockAccountIO::new(), tokens, provider)
+                .login("some code", "other state", &mut (), &mut (), &mut ())
+                .await,
+            Err(AuthError::Credentials)
+        ));
+    }
+}
@@ -12,13 +12,13 @@ use uuid::Uuid;
 pub trait AccountIO {
     type Ctx;
 
-    /// create a new account identified by an external_id and a password
+    /// create a new account identified by an external_id and the hash of its password
     ///
-    /// password SHOULD BE hashed+salted by implementors
+    /// hash is stored as is, use [create_password_hash](AccountIO::create_password_hash) to hash passwords
     async fn create(
         &mut self,
         id: &str,
-        password: &str,
+        hash: &str,
         ctx: &mut Self::Ctx,
     ) -> Result<Uuid, AccountError>;
 
@@ -36,13 +36,47 @@ pub trait AccountIO {
     /// retrieve account identified by its external_id
     async fn exists(&self, id: &str, ctx: &mut Self::Ctx) -> Result<Uuid, AccountError>;
 
-    /// retrieve account identified by its external_id
+    /// replace all fields of the account identified by its internal_id
+    ///
+    /// hash is stored as is, use [create_password_hash](AccountIO::create_password_hash) to change passwords
     async fn update(
         &mut self,
         account: Account,
         ctx: &mut Self::Ctx,
     ) -> Result<Account, AccountError>;
 
+    /// retrieve the user of an account in a tenant
+    ///
+    /// returns NotFound if the account is not a member of the tenant
+    async fn get_user(
+        &self,
+        id: &Uuid,
+        tenant: &Uuid,
+        ctx: &mut Self::Ctx,
+    ) -> Result<Uuid, AccountError>;
+
+    /// retrieve the tenant of a user
+    ///
+    /// returns NotFound if there is no such user
+    async fn get_tenant(&self, user: &Uuid, ctx: &mut Self::Ctx) -> Result<Uuid, AccountError>;
+
+    /// retrieve the internal_id of the account linked to a subject of an identity provider
+    async fn get_by_identity(
+        &self,
+        issuer: &str,
+        subject: &str,
+        ctx: &mut Self::Ctx,
+    ) -> Result<Uuid, AccountError>;
+
+    /// link a subject of an identity provider to an account, a subject is linked to one account at most
+    async fn link_identity(
+        &mut self,
+        id: &Uuid,
+        issuer: &str,
+        subject: &str,
+        ctx: &mut Self::Ctx,
+    ) -> Option<AccountError>;
+
     /// remove an account identified by its internal_id
     async fn remove(&mut self, id: &Uuid, ctx: &mut Self::Ctx) -> Option<AccountError>;
 
@@ -54,6 +88,8 @@ pub trait AccountIO {
     ) -> Result<String, AccountError>;
 
     /// create a hash of a given password
+    ///
+    /// returns Invalid if the password does not match
     async fn verify_credentials(
         &self,
         acc: &Account,
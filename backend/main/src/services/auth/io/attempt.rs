@@ -0,0 +1,44 @@
+#[cfg(test)]
+use mockall::automock;
+use std::net::IpAddr;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// count failed logins to slow down guessing passwords
+///
+/// counters MUST NOT be stored in the transaction of a request, failed requests are rolled back
+#[cfg_attr(test, automock(type Ctx=();))]
+pub trait AttemptIO {
+    type Ctx;
+
+    /// count an attempt of key as failed before verifying it, locking key for a while after too many
+    ///
+    /// returns the time left without counting the attempt while key is locked,
+    /// counting up front keeps concurrent attempts from exceeding the limit
+    async fn reserve(
+        &mut self,
+        key: &AttemptKey,
+        ctx: &mut Self::Ctx,
+    ) -> Result<Option<Duration>, AttemptError>;
+
+    /// uncount a reserved attempt which did not fail
+    async fn release(&mut self, key: &AttemptKey, ctx: &mut Self::Ctx) -> Option<AttemptError>;
+
+    /// forget the failed logins of key
+    async fn reset(&mut self, key: &AttemptKey, ctx: &mut Self::Ctx) -> Option<AttemptError>;
+}
+
+/// failed logins are counted per account and per client
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttemptKey {
+    /// external id of an account, it does not need to exist
+    Account(String),
+    /// internal id of an account which passed its first factor
+    SecondFactor(Uuid),
+    Client(IpAddr),
+}
+
+#[derive(Debug)]
+pub enum AttemptError {
+    IO,
+}
@@ -0,0 +1,55 @@
+use crate::services::auth::totp::TotpFactor;
+#[cfg(test)]
+use mockall::automock;
+use uuid::Uuid;
+
+/// store second factors of accounts and their recovery codes
+///
+/// implementors SHOULD only store hashes of recovery codes
+#[cfg_attr(test, automock(type Ctx=();))]
+pub trait FactorIO {
+    type Ctx;
+
+    /// retrieve the totp factor of an account identified by its internal_id
+    async fn get(&self, id: &Uuid, ctx: &mut Self::Ctx) -> Result<TotpFactor, FactorError>;
+
+    /// store an unconfirmed totp secret, replacing a previously unconfirmed one
+    ///
+    /// returns Exists if the account already has a confirmed factor
+    async fn enroll(
+        &mut self,
+        id: &Uuid,
+        secret: &[u8],
+        ctx: &mut Self::Ctx,
+    ) -> Option<FactorError>;
+
+    /// confirm the factor with the step of its first code and replace the recovery codes
+    async fn confirm(
+        &mut self,
+        id: &Uuid,
+        step: u64,
+        recovery_codes: &[String],
+        ctx: &mut Self::Ctx,
+    ) -> Option<FactorError>;
+
+    /// record the step of an accepted code
+    ///
+    /// returns Invalid if a code of this or a later step has been accepted before
+    async fn use_step(&mut self, id: &Uuid, step: u64, ctx: &mut Self::Ctx) -> Option<FactorError>;
+
+    /// consume a recovery code, it can not be used again afterwards
+    async fn consume_recovery_code(
+        &mut self,
+        id: &Uuid,
+        code: &str,
+        ctx: &mut Self::Ctx,
+    ) -> Option<FactorError>;
+}
+
+#[derive(Debug)]
+pub enum FactorError {
+    IO,
+    NotFound,
+    Exists,
+    Invalid,
+}
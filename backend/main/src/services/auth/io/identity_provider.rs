@@ -0,0 +1,31 @@
+use crate::services::auth::oidc::{IdClaims, PendingLogin};
+#[cfg(test)]
+use mockall::automock;
+
+/// authenticate accounts at an external OpenID Connect provider
+///
+/// implementors MUST validate id tokens, eg. with [validate_id_token](crate::services::auth::oidc::validate_id_token)
+#[cfg_attr(test, automock)]
+pub trait IdentityProviderIO {
+    /// url the user agent is redirected to for logging in at the identity provider
+    async fn authorization_url(
+        &self,
+        state: &str,
+        login: &PendingLogin,
+    ) -> Result<String, IdentityProviderError>;
+
+    /// redeem an authorization code of the login with the verifier of the login
+    ///
+    /// returns the claims of its id token if it is valid for the login
+    async fn authenticate(
+        &self,
+        code: &str,
+        login: &PendingLogin,
+    ) -> Result<IdClaims, IdentityProviderError>;
+}
+
+#[derive(Debug)]
+pub enum IdentityProviderError {
+    IO,
+    Invalid,
+}
@@ -1,4 +1,12 @@
 mod account;
+mod attempt;
+mod factor;
+mod identity_provider;
+mod one_time_token;
 mod token;
 pub use account::*;
+pub use attempt::*;
+pub use factor::*;
+pub use identity_provider::*;
+pub use one_time_token::*;
 pub use token::*;
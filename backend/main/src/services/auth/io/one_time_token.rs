@@ -0,0 +1,22 @@
+use crate::services::auth::io::TokenError;
+#[cfg(test)]
+use mockall::automock;
+use uuid::Uuid;
+
+/// create and consume single use tokens, eg. to reset passwords or verify accounts
+///
+/// implementors SHOULD only store a hash of the token
+#[cfg_attr(test, automock(type Ctx=();))]
+pub trait OneTimeTokenIO {
+    type Ctx;
+
+    /// create a new token for an account identified by its internal_id
+    ///
+    /// previously created tokens of this account become invalid
+    async fn create(&mut self, id: &Uuid, ctx: &mut Self::Ctx) -> Result<String, TokenError>;
+
+    /// consume a token, it can not be used again afterwards
+    ///
+    /// returns the internal_id it has been created for unless it is unknown or expired
+    async fn consume(&mut self, token: &str, ctx: &mut Self::Ctx) -> Result<Uuid, TokenError>;
+}
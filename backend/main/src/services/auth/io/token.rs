@@ -1,16 +1,84 @@
+use crate::services::auth::oidc::PendingLogin;
+use crate::types::CurrentUser;
 #[cfg(test)]
 use mockall::automock;
 use uuid::Uuid;
 
 /// create, verify and revoke tokens
+///
+/// access tokens are short lived, refresh tokens are used to get new ones without logging in again
 #[cfg_attr(test, automock(type Ctx=();))]
 pub trait TokenIO {
     type Ctx;
 
-    async fn create(&mut self, id: &Uuid, ctx: &mut Self::Ctx) -> Result<String, TokenError>;
+    async fn create(
+        &mut self,
+        user: &CurrentUser,
+        ctx: &mut Self::Ctx,
+    ) -> Result<String, TokenError>;
     async fn revoke(&mut self, token: &str, ctx: &mut Self::Ctx) -> Option<TokenError>;
+    /// revokes all access and refresh tokens of an account
     async fn revoke_all(&mut self, id: &Uuid, ctx: &mut Self::Ctx) -> Option<TokenError>;
-    async fn verify(&self, token: &str, ctx: &mut Self::Ctx) -> Result<Uuid, TokenError>;
+    async fn verify(&self, token: &str, ctx: &mut Self::Ctx) -> Result<CurrentUser, TokenError>;
+
+    /// create a refresh token starting a new family for a user of an account
+    async fn create_refresh(
+        &mut self,
+        user: &CurrentUser,
+        ctx: &mut Self::Ctx,
+    ) -> Result<String, TokenError>;
+
+    /// exchange a refresh token for the next one of its family
+    ///
+    /// every refresh token can only be used once,
+    /// using it again revokes its whole family as it might have been stolen
+    /// returns the user of the family and the next refresh token
+    async fn rotate_refresh(
+        &mut self,
+        token: &str,
+        ctx: &mut Self::Ctx,
+    ) -> Result<(CurrentUser, String), TokenError>;
+
+    /// revoke the family of a refresh token, eg. when switching to another user
+    async fn revoke_refresh(&mut self, token: &str, ctx: &mut Self::Ctx) -> Option<TokenError>;
+
+    /// create a short lived token proving that an account passed its first factor
+    ///
+    /// it MUST NOT be accepted as access token
+    /// it is valid until it expired or has been consumed
+    async fn create_challenge(
+        &mut self,
+        id: &Uuid,
+        ctx: &mut Self::Ctx,
+    ) -> Result<String, TokenError>;
+
+    /// returns the account a challenge has been created for
+    async fn verify_challenge(&self, token: &str, ctx: &mut Self::Ctx) -> Result<Uuid, TokenError>;
+
+    /// consume a challenge once its second factor has been verified, it can not be used again afterwards
+    ///
+    /// returns Invalid if it has been consumed before
+    async fn consume_challenge(
+        &mut self,
+        token: &str,
+        ctx: &mut Self::Ctx,
+    ) -> Result<Uuid, TokenError>;
+
+    /// store a login started at an identity provider
+    ///
+    /// returns the state identifying it in the callback of the identity provider
+    async fn create_login_state(
+        &mut self,
+        login: &PendingLogin,
+        ctx: &mut Self::Ctx,
+    ) -> Result<String, TokenError>;
+
+    /// consume a login state, it can not be used again afterwards
+    async fn consume_login_state(
+        &mut self,
+        state: &str,
+        ctx: &mut Self::Ctx,
+    ) -> Result<PendingLogin, TokenError>;
 }
 
 #[derive(Debug)]
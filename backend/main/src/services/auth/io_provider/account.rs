@@ -27,11 +27,9 @@ impl AccountIO for LocalAccountIO {
     async fn create(
         &mut self,
         id: &str,
-        password: &str,
+        hash: &str,
         ctx: &mut Self::Ctx,
     ) -> Result<Uuid, AccountError> {
-        let hash = self.create_password_hash(password, ctx).await?;
-
         if let Ok(rec) = sqlx::query!(
             "INSERT INTO public.account (id_external, hash) VALUES ($1, $2) RETURNING id",
             id,
@@ -93,10 +91,82 @@ impl AccountIO for LocalAccountIO {
 
     async fn update(
         &mut self,
-        _account: Account,
-        _ctx: &mut Self::Ctx,
+        account: Account,
+        ctx: &mut Self::Ctx,
     ) -> Result<Account, AccountError> {
-        todo!()
+        sqlx::query_as::<_, Account>(
+            r#"UPDATE public.account SET id_external = $2, hash = $3, status = $4 WHERE id = $1 RETURNING id, id_external, hash, status"#,
+        )
+        .bind(account.id)
+        .bind(&account.id_external)
+        .bind(&account.hash)
+        .bind(account.status)
+        .fetch_optional(ctx)
+        .await
+        .or(Err(AccountError::IO))?
+        .ok_or(AccountError::NotFound)
+    }
+
+    async fn get_user(
+        &self,
+        internal_id: &Uuid,
+        tenant: &Uuid,
+        ctx: &mut Self::Ctx,
+    ) -> Result<Uuid, AccountError> {
+        sqlx::query_scalar::<_, Uuid>(
+            r#"SELECT id FROM public.tenant_user WHERE account_id = $1 AND tenant_id = $2"#,
+        )
+        .bind(internal_id)
+        .bind(tenant)
+        .fetch_optional(ctx)
+        .await
+        .or(Err(AccountError::IO))?
+        .ok_or(AccountError::NotFound)
+    }
+
+    async fn get_tenant(&self, user: &Uuid, ctx: &mut Self::Ctx) -> Result<Uuid, AccountError> {
+        sqlx::query_scalar::<_, Uuid>(r#"SELECT tenant_id FROM public.tenant_user WHERE id = $1"#)
+            .bind(user)
+            .fetch_optional(ctx)
+            .await
+            .or(Err(AccountError::IO))?
+            .ok_or(AccountError::NotFound)
+    }
+
+    async fn get_by_identity(
+        &self,
+        issuer: &str,
+        subject: &str,
+        ctx: &mut Self::Ctx,
+    ) -> Result<Uuid, AccountError> {
+        sqlx::query_scalar::<_, Uuid>(
+            r#"SELECT account_id FROM public.account_identity WHERE issuer = $1 AND subject = $2"#,
+        )
+        .bind(issuer)
+        .bind(subject)
+        .fetch_optional(ctx)
+        .await
+        .or(Err(AccountError::IO))?
+        .ok_or(AccountError::NotFound)
+    }
+
+    async fn link_identity(
+        &mut self,
+        internal_id: &Uuid,
+        issuer: &str,
+        subject: &str,
+        ctx: &mut Self::Ctx,
+    ) -> Option<AccountError> {
+        sqlx::query(
+            r#"INSERT INTO public.account_identity (issuer, subject, account_id) VALUES ($1, $2, $3)"#,
+        )
+        .bind(issuer)
+        .bind(subject)
+        .bind(internal_id)
+        .execute(ctx)
+        .await
+        .err()
+        .map(|_| AccountError::IO)
     }
 
     async fn remove(&mut self, internal_id: &Uuid, ctx: &mut Self::Ctx) -> Option<AccountError> {
@@ -142,7 +212,7 @@ impl AccountIO for LocalAccountIO {
         {
             Ok(acc.id)
         } else {
-            Err(AccountError::IO)
+            Err(AccountError::Invalid)
         }
     }
 }
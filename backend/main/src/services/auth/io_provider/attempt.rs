@@ -0,0 +1,206 @@
+use crate::services::auth::io::{AttemptError, AttemptIO, AttemptKey};
+use crate::types::RedisConnection;
+use deadpool_redis::redis::AsyncCommands;
+use std::time::Duration;
+
+/// number of failed logins of a key, expires once the key stopped failing for a window
+const ATTEMPTS_PREFIX: &str = "login_attempts:";
+/// exists while a key is locked, expires with the lock
+const LOCK_PREFIX: &str = "login_lock:";
+/// atomically counts an attempt unless the key is locked, then locks it if it exceeded its free attempts
+///
+/// KEYS[1] lock, KEYS[2] attempts, ARGV[1] window in seconds, ARGV[2] free attempts,
+/// ARGV[3..] delays in milliseconds after exceeding the free attempts once, twice, ..., the last one repeats
+/// returns the milliseconds left while locked, 0 if the attempt has been counted
+const RESERVE_SCRIPT: &str = r#"
+local ttl = redis.call('PTTL', KEYS[1])
+if ttl > 0 then
+    return ttl
+end
+local failures = redis.call('INCR', KEYS[2])
+redis.call('EXPIRE', KEYS[2], ARGV[1])
+local exceeded = failures - tonumber(ARGV[2])
+if exceeded > 0 and #ARGV > 2 then
+    local delay = tonumber(ARGV[math.min(exceeded, #ARGV - 2) + 2])
+    if delay > 0 then
+        redis.call('SET', KEYS[1], failures, 'PX', delay)
+    end
+end
+return 0
+"#;
+/// uncounts an attempt and lifts the lock once the key is back within its free attempts
+///
+/// KEYS[1] lock, KEYS[2] attempts, ARGV[1] free attempts
+const RELEASE_SCRIPT: &str = r#"
+local failures = redis.call('DECR', KEYS[2])
+if failures <= 0 then
+    redis.call('DEL', KEYS[2])
+end
+if failures <= tonumber(ARGV[1]) then
+    redis.call('DEL', KEYS[1])
+end
+return failures
+"#;
+
+/// exponential backoff after a number of free attempts
+///
+/// every further failure doubles the time a key is locked for, up to lockout
+#[derive(Debug, Clone, Copy)]
+pub struct Backoff {
+    /// failures before a key gets locked
+    pub free_attempts: u32,
+    /// lock after the first failure exceeding free_attempts
+    pub base: Duration,
+    /// longest time a key is locked for
+    pub lockout: Duration,
+    /// failures are forgotten once a key did not fail for window
+    pub window: Duration,
+}
+
+impl Backoff {
+    /// time a key is locked for after failing failures times
+    fn delay(&self, failures: u32) -> Option<Duration> {
+        let exceeded = failures.checked_sub(self.free_attempts)?.checked_sub(1)?;
+        let factor = 2u32.checked_pow(exceeded).unwrap_or(u32::MAX);
+        Some(self.base.saturating_mul(factor).min(self.lockout))
+    }
+
+    /// delays after exceeding the free attempts once, twice, ... until they stop growing
+    fn delays(&self) -> Vec<Duration> {
+        let mut delays: Vec<Duration> = vec![];
+        let mut failures = self.free_attempts;
+        while let Some(next) = failures.checked_add(1) {
+            failures = next;
+            let Some(delay) = self.delay(failures) else {
+                break;
+            };
+            if delays.last() == Some(&delay) {
+                break;
+            }
+            delays.push(delay);
+        }
+        delays
+    }
+}
+
+/// failed login counters stored in redis
+///
+/// clients share their ip behind NATs, they should be allowed more failures than accounts
+#[derive(Clone)]
+pub struct LocalAttemptIO {
+    account: Backoff,
+    client: Backoff,
+}
+
+impl LocalAttemptIO {
+    pub fn new(account: Backoff, client: Backoff) -> Self {
+        Self { account, client }
+    }
+
+    fn backoff(&self, key: &AttemptKey) -> &Backoff {
+        match key {
+            AttemptKey::Account(_) | AttemptKey::SecondFactor(_) => &self.account,
+            AttemptKey::Client(_) => &self.client,
+        }
+    }
+}
+
+/// redis key of an attempt key, external ids are arbitrary strings so they come last
+fn redis_key(prefix: &str, key: &AttemptKey) -> String {
+    match key {
+        AttemptKey::Account(id) => format!("{prefix}account:{id}"),
+        AttemptKey::SecondFactor(id) => format!("{prefix}second_factor:{id}"),
+        AttemptKey::Client(ip) => format!("{prefix}client:{ip}"),
+    }
+}
+
+impl AttemptIO for LocalAttemptIO {
+    type Ctx = RedisConnection;
+
+    async fn reserve(
+        &mut self,
+        key: &AttemptKey,
+        ctx: &mut Self::Ctx,
+    ) -> Result<Option<Duration>, AttemptError> {
+        let backoff = self.backoff(key);
+        let script = redis::Script::new(RESERVE_SCRIPT);
+        let mut invocation = script.prepare_invoke();
+        invocation
+            .key(redis_key(LOCK_PREFIX, key))
+            .key(redis_key(ATTEMPTS_PREFIX, key))
+            .arg(backoff.window.as_secs())
+            .arg(backoff.free_attempts);
+        for delay in backoff.delays() {
+            invocation.arg(delay.as_millis() as u64);
+        }
+
+        let ttl = invocation
+            .invoke_async::<Self::Ctx, u64>(ctx)
+            .await
+            .or(Err(AttemptError::IO))?;
+        Ok((ttl > 0).then(|| Duration::from_millis(ttl)))
+    }
+
+    async fn release(&mut self, key: &AttemptKey, ctx: &mut Self::Ctx) -> Option<AttemptError> {
+        redis::Script::new(RELEASE_SCRIPT)
+            .key(redis_key(LOCK_PREFIX, key))
+            .key(redis_key(ATTEMPTS_PREFIX, key))
+            .arg(self.backoff(key).free_attempts)
+            .invoke_async::<Self::Ctx, i64>(ctx)
+            .await
+            .err()
+            .map(|_| AttemptError::IO)
+    }
+
+    async fn reset(&mut self, key: &AttemptKey, ctx: &mut Self::Ctx) -> Option<AttemptError> {
+        ctx.del::<_, ()>(&[redis_key(ATTEMPTS_PREFIX, key), redis_key(LOCK_PREFIX, key)])
+            .await
+            .err()
+            .map(|_| AttemptError::IO)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Backoff;
+    use std::time::Duration;
+
+    fn backoff() -> Backoff {
+        Backoff {
+            free_attempts: 3,
+            base: Duration::from_secs(1),
+            lockout: Duration::from_secs(60),
+            window: Duration::from_secs(60 * 60),
+        }
+    }
+
+    #[test]
+    fn test_free_attempts_are_not_delayed() {
+        for failures in 0..=3 {
+            assert_eq!(backoff().delay(failures), None);
+        }
+    }
+
+    #[test]
+    fn test_delay_doubles_up_to_lockout() {
+        assert_eq!(backoff().delay(4), Some(Duration::from_secs(1)));
+        assert_eq!(backoff().delay(5), Some(Duration::from_secs(2)));
+        assert_eq!(backoff().delay(9), Some(Duration::from_secs(32)));
+        assert_eq!(backoff().delay(10), Some(Duration::from_secs(60)));
+        assert_eq!(backoff().delay(u32::MAX), Some(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn test_delays_end_at_lockout() {
+        let delays = backoff().delays();
+        assert_eq!(delays.first(), Some(&Duration::from_secs(1)));
+        assert_eq!(delays.last(), Some(&Duration::from_secs(60)));
+        assert_eq!(delays.len(), 7);
+        assert!(Backoff {
+            free_attempts: u32::MAX,
+            ..backoff()
+        }
+        .delays()
+        .is_empty());
+    }
+}
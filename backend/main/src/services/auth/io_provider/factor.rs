@@ -0,0 +1,141 @@
+use super::one_time_token::hash_token;
+use crate::services::auth::io::{FactorError, FactorIO};
+use crate::services::auth::totp::TotpFactor;
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+/// totp factors and recovery codes stored in postgres
+#[derive(Clone, Default)]
+pub struct LocalFactorIO;
+
+impl LocalFactorIO {
+    pub fn new() -> Self {
+        LocalFactorIO
+    }
+}
+
+impl FactorIO for LocalFactorIO {
+    type Ctx = PgConnection;
+
+    async fn get(&self, id: &Uuid, ctx: &mut Self::Ctx) -> Result<TotpFactor, FactorError> {
+        let (secret, confirmed, last_step) = sqlx::query_as::<_, (Vec<u8>, bool, Option<i64>)>(
+            r#"SELECT secret, confirmed_at IS NOT NULL, last_step FROM public.account_totp WHERE account_id = $1"#,
+        )
+        .bind(id)
+        .fetch_optional(ctx)
+        .await
+        .or(Err(FactorError::IO))?
+        .ok_or(FactorError::NotFound)?;
+
+        Ok(TotpFactor {
+            secret,
+            confirmed,
+            last_step: last_step.map(|step| step as u64),
+        })
+    }
+
+    async fn enroll(
+        &mut self,
+        id: &Uuid,
+        secret: &[u8],
+        ctx: &mut Self::Ctx,
+    ) -> Option<FactorError> {
+        match sqlx::query(
+            r#"INSERT INTO public.account_totp (account_id, secret) VALUES ($1, $2)
+            ON CONFLICT (account_id) DO UPDATE SET secret = excluded.secret, last_step = NULL
+            WHERE account_totp.confirmed_at IS NULL"#,
+        )
+        .bind(id)
+        .bind(secret)
+        .execute(ctx)
+        .await
+        {
+            Ok(res) if res.rows_affected() == 1 => None,
+            // the conflicting factor has been confirmed
+            Ok(_) => Some(FactorError::Exists),
+            Err(_) => Some(FactorError::IO),
+        }
+    }
+
+    async fn confirm(
+        &mut self,
+        id: &Uuid,
+        step: u64,
+        recovery_codes: &[String],
+        ctx: &mut Self::Ctx,
+    ) -> Option<FactorError> {
+        match sqlx::query(
+            r#"UPDATE public.account_totp SET confirmed_at = NOW(), last_step = $2
+            WHERE account_id = $1 AND confirmed_at IS NULL"#,
+        )
+        .bind(id)
+        .bind(step as i64)
+        .execute(&mut *ctx)
+        .await
+        {
+            Ok(res) if res.rows_affected() == 1 => {}
+            Ok(_) => return Some(FactorError::NotFound),
+            Err(_) => return Some(FactorError::IO),
+        }
+
+        if sqlx::query(r#"DELETE FROM public.account_recovery_code WHERE account_id = $1"#)
+            .bind(id)
+            .execute(&mut *ctx)
+            .await
+            .is_err()
+        {
+            return Some(FactorError::IO);
+        }
+        sqlx::query(
+            r#"INSERT INTO public.account_recovery_code (account_id, code_hash)
+            SELECT $1, UNNEST($2::varchar[])"#,
+        )
+        .bind(id)
+        .bind(
+            recovery_codes
+                .iter()
+                .map(|code| hash_token(code))
+                .collect::<Vec<_>>(),
+        )
+        .execute(ctx)
+        .await
+        .err()
+        .map(|_| FactorError::IO)
+    }
+
+    async fn use_step(&mut self, id: &Uuid, step: u64, ctx: &mut Self::Ctx) -> Option<FactorError> {
+        match sqlx::query(
+            r#"UPDATE public.account_totp SET last_step = $2
+            WHERE account_id = $1 AND confirmed_at IS NOT NULL AND (last_step IS NULL OR last_step < $2)"#,
+        )
+        .bind(id)
+        .bind(step as i64)
+        .execute(ctx)
+        .await
+        {
+            Ok(res) if res.rows_affected() == 1 => None,
+            Ok(_) => Some(FactorError::Invalid),
+            Err(_) => Some(FactorError::IO),
+        }
+    }
+
+    async fn consume_recovery_code(
+        &mut self,
+        id: &Uuid,
+        code: &str,
+        ctx: &mut Self::Ctx,
+    ) -> Option<FactorError> {
+        match sqlx::query(
+            r#"DELETE FROM public.account_recovery_code WHERE account_id = $1 AND code_hash = $2"#,
+        )
+        .bind(id)
+        .bind(hash_token(code))
+        .execute(ctx)
+        .await
+        {
+            Ok(res) if res.rows_affected() == 1 => None,
+            Ok(_) => Some(FactorError::Invalid),
+            Err(_) => Some(FactorError::IO),
+        }
+    }
+}
@@ -0,0 +1,348 @@
+use crate::services::auth::io::{IdentityProviderError, IdentityProviderIO};
+use crate::services::auth::oidc::{
+    authorization_url, validate_id_token, IdClaims, PendingLogin, ProviderMetadata,
+};
+use jsonwebtoken::jwk::JwkSet;
+use serde::Deserialize;
+use std::time::Duration;
+use tokio::sync::OnceCell;
+
+/// a login waits for the identity provider, requests must not hang it
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// client registered at an identity provider
+#[derive(Debug, Clone)]
+pub struct OidcConfig {
+    /// the discovery document is expected at `<issuer>/.well-known/openid-configuration`
+    pub issuer: String,
+    pub client_id: String,
+    /// confidential clients authenticate at the token endpoint, public clients only use PKCE
+    pub client_secret: Option<String>,
+    /// callback of this instance, it has to be registered at the identity provider
+    pub redirect_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// OpenID Connect provider reached over http
+///
+/// its endpoints are discovered on first use, its keys are fetched on every login as they may rotate
+pub struct LocalIdentityProviderIO {
+    config: OidcConfig,
+    http: reqwest::Client,
+    metadata: OnceCell<ProviderMetadata>,
+}
+
+impl LocalIdentityProviderIO {
+    pub fn new(config: OidcConfig) -> Self {
+        Self {
+            config,
+            http: reqwest::Client::builder()
+                .timeout(HTTP_TIMEOUT)
+                .build()
+                .expect("Expected to build http client"),
+            metadata: OnceCell::new(),
+        }
+    }
+
+    async fn metadata(&self) -> Result<&ProviderMetadata, IdentityProviderError> {
+        self.metadata
+            .get_or_try_init(|| async {
+                let url = format!(
+                    "{}/.well-known/openid-configuration",
+                    self.config.issuer.trim_end_matches('/')
+                );
+                let metadata = self.get::<ProviderMetadata>(&url).await?;
+                // a provider may only publish metadata of its own issuer
+                if metadata.issuer != self.config.issuer {
+                    return Err(IdentityProviderError::Invalid);
+                }
+                Ok(metadata)
+            })
+            .await
+    }
+
+    async fn get<T: for<'de> Deserialize<'de>>(
+        &self,
+        url: &str,
+    ) -> Result<T, IdentityProviderError> {
+        self.http
+            .get(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .or(Err(IdentityProviderError::IO))?
+            .json::<T>()
+            .await
+            .or(Err(IdentityProviderError::Invalid))
+    }
+}
+
+impl IdentityProviderIO for LocalIdentityProviderIO {
+    async fn authorization_url(
+        &self,
+        state: &str,
+        login: &PendingLogin,
+    ) -> Result<String, IdentityProviderError> {
+        Ok(authorization_url(
+            self.metadata().await?,
+            &self.config.client_id,
+            &self.config.redirect_uri,
+            state,
+            login,
+        ))
+    }
+
+    async fn authenticate(
+        &self,
+        code: &str,
+        login: &PendingLogin,
+    ) -> Result<IdClaims, IdentityProviderError> {
+        let metadata = self.metadata().await?;
+
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &self.config.redirect_uri),
+            ("client_id", &self.config.client_id),
+            ("code_verifier", &login.verifier),
+        ];
+        if let Some(secret) = &self.config.client_secret {
+            form.push(("client_secret", secret));
+        }
+        let response = self
+            .http
+            .post(&metadata.token_endpoint)
+            .form(&form)
+            .send()
+            .await
+            .or(Err(IdentityProviderError::IO))?;
+        // unknown or already redeemed codes and wrong verifiers are rejected by the provider
+        if response.status().is_client_error() {
+            return Err(IdentityProviderError::Invalid);
+        }
+        let tokens = response
+            .error_for_status()
+            .or(Err(IdentityProviderError::IO))?
+            .json::<TokenResponse>()
+            .await
+            .or(Err(IdentityProviderError::Invalid))?;
+
+        let jwks = self.get::<JwkSet>(&metadata.jwks_uri).await?;
+        validate_id_token(
+            &tokens.id_token,
+            &jwks,
+            &metadata.issuer,
+            &self.config.client_id,
+            &login.nonce,
+        )
+        .ok_or(IdentityProviderError::Invalid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LocalIdentityProviderIO, OidcConfig};
+    use crate::services::auth::io::{IdentityProviderError, IdentityProviderIO};
+    use crate::services::auth::io_provider::{KeyRing, SigningKey};
+    use crate::services::auth::oidc::PendingLogin;
+    use axum::extract::{Query, State};
+    use axum::http::{header, StatusCode};
+    use axum::response::{IntoResponse, Redirect, Response};
+    use axum::routing::{get, post};
+    use axum::{Form, Json, Router};
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+    use ring::digest;
+    use serde_json::json;
+    use std::collections::HashMap;
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    const REDIRECT_URI: &str = "http://127.0.0.1:3000/auth/oidc/callback";
+
+    /// minimal identity provider logging in every user agent as the same subject
+    struct StandInProvider {
+        issuer: String,
+        keys: KeyRing,
+        /// authorization requests by the codes issued for them
+        codes: Mutex<HashMap<String, HashMap<String, String>>>,
+    }
+
+    async fn discovery(State(idp): State<Arc<StandInProvider>>) -> Response {
+        Json(json!({
+            "issuer": idp.issuer,
+            "authorization_endpoint": format!("{}/authorize", idp.issuer),
+            "token_endpoint": format!("{}/token", idp.issuer),
+            "jwks_uri": format!("{}/jwks", idp.issuer),
+        }))
+        .into_response()
+    }
+
+    async fn authorize(
+        State(idp): State<Arc<StandInProvider>>,
+        Query(request): Query<HashMap<String, String>>,
+    ) -> Response {
+        let code = uuid::Uuid::new_v4().to_string();
+        let redirect = format!(
+            "{}?code={code}&state={}",
+            request["redirect_uri"], request["state"]
+        );
+        idp.codes.lock().unwrap().insert(code, request);
+        Redirect::to(&redirect).into_response()
+    }
+
+    async fn token(
+        State(idp): State<Arc<StandInProvider>>,
+        Form(form): Form<HashMap<String, String>>,
+    ) -> Response {
+        let Some(request) = idp.codes.lock().unwrap().remove(&form["code"]) else {
+            return StatusCode::BAD_REQUEST.into_response();
+        };
+        let challenge = URL_SAFE_NO_PAD.encode(digest::digest(
+            &digest::SHA256,
+            form["code_verifier"].as_bytes(),
+        ));
+        if challenge != request["code_challenge"]
+            || form["client_id"] != request["client_id"]
+            || form["redirect_uri"] != request["redirect_uri"]
+        {
+            return StatusCode::BAD_REQUEST.into_response();
+        }
+
+        let exp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            + 60;
+        let id_token = idp
+            .keys
+            .encode(&json!({
+                "iss": idp.issuer,
+                "sub": "external subject",
+                "aud": request["client_id"],
+                "exp": exp,
+                "nonce": request["nonce"],
+                "email": "some@mail.de",
+                "email_verified": true,
+            }))
+            .expect("Expected id token");
+        Json(json!({"access_token": "unused", "token_type": "Bearer", "id_token": id_token}))
+            .into_response()
+    }
+
+    /// serves a stand-in provider on a random local port, returns its issuer
+    fn stand_in_provider() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Expected local port");
+        let issuer = format!("http://{}", listener.local_addr().unwrap());
+        let idp = Arc::new(StandInProvider {
+            issuer: issuer.clone(),
+            keys: KeyRing::new(SigningKey::generate("idp").expect("Expected key")),
+            codes: Mutex::new(HashMap::new()),
+        });
+        let app = Router::new()
+            .route("/.well-known/openid-configuration", get(discovery))
+            .route("/authorize", get(authorize))
+            .route("/token", post(token))
+            .route(
+                "/jwks",
+                get(|State(idp): State<Arc<StandInProvider>>| async move { Json(idp.keys.jwks()) }),
+            )
+            .with_state(idp);
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .expect("Expected server")
+                .serve(app.into_make_service()),
+        );
+        issuer
+    }
+
+    fn provider(issuer: &str) -> LocalIdentityProviderIO {
+        LocalIdentityProviderIO::new(OidcConfig {
+            issuer: issuer.to_string(),
+            client_id: "hygge".to_string(),
+            client_secret: None,
+            redirect_uri: REDIRECT_URI.to_string(),
+        })
+    }
+
+    /// follows the authorization url like a user agent, returns the code of the callback
+    async fn log_in_at(url: &str) -> String {
+        let response = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap()
+            .get(url)
+            .send()
+            .await
+            .expect("Expected redirect");
+        let location = response.headers()[header::LOCATION].to_str().unwrap();
+        let callback = location
+            .strip_prefix(REDIRECT_URI)
+            .expect("Expected redirect to the callback");
+        assert!(callback.ends_with("&state=some-state"));
+        callback["?code=".len()..callback.find('&').unwrap()].to_string()
+    }
+
+    #[tokio::test]
+    async fn test_authenticate_with_stand_in_provider() {
+        let provider = provider(&stand_in_provider());
+        let login = PendingLogin::generate();
+
+        let url = provider
+            .authorization_url("some-state", &login)
+            .await
+            .expect("Expected authorization url");
+        let code = log_in_at(&url).await;
+
+        let claims = provider
+            .authenticate(&code, &login)
+            .await
+            .expect("Expected id token claims");
+        assert_eq!(claims.sub, "external subject");
+        assert_eq!(claims.verified_email(), Some("some@mail.de"));
+
+        // codes can only be redeemed once
+        assert!(matches!(
+            provider.authenticate(&code, &login).await,
+            Err(IdentityProviderError::Invalid)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_authenticate_requires_verifier_of_login() {
+        let provider = provider(&stand_in_provider());
+        let login = PendingLogin::generate();
+
+        let url = provider
+            .authorization_url("some-state", &login)
+            .await
+            .expect("Expected authorization url");
+        let code = log_in_at(&url).await;
+
+        // an intercepted code is useless without the verifier
+        assert!(matches!(
+            provider
+                .authenticate(&code, &PendingLogin::generate())
+                .await,
+            Err(IdentityProviderError::Invalid)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_discovery_of_other_issuer_is_rejected() {
+        let issuer = stand_in_provider();
+        let provider = provider(&format!("{issuer}/"));
+
+        assert!(matches!(
+            provider
+                .authorization_url("some-state", &PendingLogin::generate())
+                .await,
+            Err(IdentityProviderError::Invalid)
+        ));
+    }
+}
@@ -0,0 +1,455 @@
+use crate::services::auth::io::TokenError;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use jsonwebtoken::jwk::{
+    AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, OctetKeyPairParameters,
+    OctetKeyPairType, PublicKeyUse, RSAKeyParameters, RSAKeyType,
+};
+use jsonwebtoken::{decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header};
+use jsonwebtoken::{TokenData, Validation};
+use ring::rand::SystemRandom;
+use ring::signature::{Ed25519KeyPair, KeyPair, RsaKeyPair};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+/// a key signing and verifying tokens, identified by the kid header of its tokens
+#[derive(Clone)]
+pub struct SigningKey {
+    kid: String,
+    algorithm: Algorithm,
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    // public part of asymmetric keys, published to let other services verify tokens
+    jwk: Option<Jwk>,
+}
+
+impl SigningKey {
+    /// load a private EdDSA (PKCS#8) or RS256 (PKCS#1 or PKCS#8) key
+    pub fn from_pem(kid: &str, pem: &[u8]) -> Result<Self, KeyError> {
+        let parsed = pem::parse(pem).or(Err(KeyError::Invalid))?;
+        match parsed.tag.as_str() {
+            "PRIVATE KEY" => {
+                if let Ok(pair) = Ed25519KeyPair::from_pkcs8_maybe_unchecked(&parsed.contents) {
+                    Ok(Self::ed25519(kid, &parsed.contents, &pair))
+                } else {
+                    let pair =
+                        RsaKeyPair::from_pkcs8(&parsed.contents).or(Err(KeyError::Invalid))?;
+                    Self::rsa(kid, pem, &pair)
+                }
+            }
+            "RSA PRIVATE KEY" => {
+                let pair = RsaKeyPair::from_der(&parsed.contents).or(Err(KeyError::Invalid))?;
+                Self::rsa(kid, pem, &pair)
+            }
+            _ => Err(KeyError::Invalid),
+        }
+    }
+
+    /// generate a new EdDSA key, tokens signed by it can not be verified after a restart
+    pub fn generate(kid: &str) -> Result<Self, KeyError> {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).or(Err(KeyError::IO))?;
+        let pair =
+            Ed25519KeyPair::from_pkcs8_maybe_unchecked(pkcs8.as_ref()).or(Err(KeyError::IO))?;
+        Ok(Self::ed25519(kid, pkcs8.as_ref(), &pair))
+    }
+
+    /// a HS256 key, it is not published as other services would need the secret to verify tokens
+    pub fn from_secret(kid: &str, secret: &[u8]) -> Self {
+        Self {
+            kid: kid.to_string(),
+            algorithm: Algorithm::HS256,
+            encoding: EncodingKey::from_secret(secret),
+            decoding: DecodingKey::from_secret(secret),
+            jwk: None,
+        }
+    }
+
+    pub fn kid(&self) -> &str {
+        &self.kid
+    }
+
+    fn ed25519(kid: &str, pkcs8: &[u8], pair: &Ed25519KeyPair) -> Self {
+        let x = URL_SAFE_NO_PAD.encode(pair.public_key().as_ref());
+        Self {
+            kid: kid.to_string(),
+            algorithm: Algorithm::EdDSA,
+            encoding: EncodingKey::from_ed_der(pkcs8),
+            // the raw public key, ring does not expect it to be wrapped in der
+            decoding: DecodingKey::from_ed_der(pair.public_key().as_ref()),
+            jwk: Some(Jwk {
+                common: common_parameters(kid, Algorithm::EdDSA),
+                algorithm: AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                    key_type: OctetKeyPairType::OctetKeyPair,
+                    curve: EllipticCurve::Ed25519,
+                    x,
+                }),
+            }),
+        }
+    }
+
+    fn rsa(kid: &str, pem: &[u8], pair: &RsaKeyPair) -> Result<Self, KeyError> {
+        let n = URL_SAFE_NO_PAD.encode(
+            pair.public_key()
+                .modulus()
+                .big_endian_without_leading_zero(),
+        );
+        let e = URL_SAFE_NO_PAD.encode(
+            pair.public_key()
+                .exponent()
+                .big_endian_without_leading_zero(),
+        );
+        Ok(Self {
+            kid: kid.to_string(),
+            algorithm: Algorithm::RS256,
+            encoding: EncodingKey::from_rsa_pem(pem).or(Err(KeyError::Invalid))?,
+            decoding: DecodingKey::from_rsa_components(&n, &e).or(Err(KeyError::Invalid))?,
+            jwk: Some(Jwk {
+                common: common_parameters(kid, Algorithm::RS256),
+                algorithm: AlgorithmParameters::RSA(RSAKeyParameters {
+                    key_type: RSAKeyType::RSA,
+                    n,
+                    e,
+                }),
+            }),
+        })
+    }
+}
+
+fn common_parameters(kid: &str, algorithm: Algorithm) -> CommonParameters {
+    CommonParameters {
+        public_key_use: Some(PublicKeyUse::Signature),
+        algorithm: Some(algorithm),
+        key_id: Some(kid.to_string()),
+        ..Default::default()
+    }
+}
+
+#[derive(Clone)]
+struct RingKey {
+    key: SigningKey,
+    // None while the key is in use, set once it has been rotated out
+    valid_until: Option<SystemTime>,
+}
+
+impl RingKey {
+    fn is_valid(&self, now: SystemTime) -> bool {
+        !matches!(self.valid_until, Some(until) if until <= now)
+    }
+}
+
+/// signs tokens with one key and verifies them with any key of the ring
+///
+/// rotating keys without invalidating tokens:
+/// 1. add the next key, it is published but does not sign yet
+/// 2. sign with the next key, keep verifying with the previous one until its tokens expired
+/// 3. remove the previous key
+#[derive(Clone)]
+pub struct KeyRing {
+    signing: String,
+    keys: HashMap<String, RingKey>,
+}
+
+impl KeyRing {
+    pub fn new(signing: SigningKey) -> Self {
+        let mut ring = Self {
+            signing: signing.kid.clone(),
+            keys: HashMap::new(),
+        };
+        ring.insert(signing);
+        ring
+    }
+
+    /// load all `<kid>.pem` files of a directory, signing with the key of signing_kid
+    pub fn load(dir: impl AsRef<Path>, signing_kid: &str) -> Result<Self, KeyError> {
+        let mut keys = HashMap::new();
+        for entry in std::fs::read_dir(dir).or(Err(KeyError::IO))? {
+            let path = entry.or(Err(KeyError::IO))?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("pem") {
+                continue;
+            }
+            let Some(kid) = path.file_stem().and_then(|s| s.to_str()) else {
+                continue;
+            };
+            let pem = std::fs::read(&path).or(Err(KeyError::IO))?;
+            keys.insert(
+                kid.to_string(),
+                RingKey {
+                    key: SigningKey::from_pem(kid, &pem)?,
+                    valid_until: None,
+                },
+            );
+        }
+
+        if !keys.contains_key(signing_kid) {
+            return Err(KeyError::MissingSigningKey);
+        }
+        Ok(Self {
+            signing: signing_kid.to_string(),
+            keys,
+        })
+    }
+
+    /// adds the keys of a directory loaded like [load](Self::load) which are not part of the ring yet
+    ///
+    /// rotates to the key of signing_kid if it is not the signing key already,
+    /// see [rotate](Self::rotate), the ring is left unchanged if the directory can not be loaded
+    pub fn reload(
+        &mut self,
+        dir: impl AsRef<Path>,
+        signing_kid: &str,
+        overlap: Duration,
+    ) -> Result<(), KeyError> {
+        let mut loaded = Self::load(dir, signing_kid)?.keys;
+        let signing = loaded
+            .remove(signing_kid)
+            .expect("Expected load to contain the signing key");
+        for (kid, ring_key) in loaded {
+            if !self.keys.contains_key(&kid) {
+                self.insert(ring_key.key);
+            }
+        }
+
+        if self.signing != signing_kid {
+            self.rotate(signing.key, overlap);
+        }
+        Ok(())
+    }
+
+    /// add a key only verifying tokens, replaces keys with the same kid
+    pub fn insert(&mut self, key: SigningKey) {
+        self.keys.insert(
+            key.kid.clone(),
+            RingKey {
+                key,
+                valid_until: None,
+            },
+        );
+    }
+
+    /// sign new tokens with key
+    ///
+    /// the previous signing key keeps verifying tokens for overlap,
+    /// which should be at least the ttl of the tokens it signed
+    pub fn rotate(&mut self, key: SigningKey, overlap: Duration) {
+        let now = SystemTime::now();
+        self.keys.retain(|_, key| key.is_valid(now));
+        if let Some(previous) = self.keys.get_mut(&self.signing) {
+            previous.valid_until = Some(now + overlap);
+        }
+        self.signing = key.kid.clone();
+        self.insert(key);
+    }
+
+    /// public keys of the ring, the signing key and keys still verifying tokens
+    pub fn jwks(&self) -> JwkSet {
+        let now = SystemTime::now();
+        JwkSet {
+            keys: self
+                .keys
+                .values()
+                .filter(|key| key.is_valid(now))
+                .filter_map(|key| key.key.jwk.clone())
+                .collect(),
+        }
+    }
+
+    pub(super) fn encode<T: Serialize>(&self, claims: &T) -> Result<String, TokenError> {
+        let Some(RingKey { key, .. }) = self.keys.get(&self.signing) else {
+            return Err(TokenError::Invalid);
+        };
+        let mut header = Header::new(key.algorithm);
+        header.kid = Some(key.kid.clone());
+        encode(&header, claims, &key.encoding).or(Err(TokenError::Invalid))
+    }
+
+    /// decodes tokens signed by a valid key of the ring
+    ///
+    /// the algorithm is taken from the key, never from the token
+    pub(super) fn decode<T: DeserializeOwned>(
+        &self,
+        token: &str,
+    ) -> Result<TokenData<T>, TokenError> {
+        let Ok(Header { kid: Some(kid), .. }) = decode_header(token) else {
+            return Err(TokenError::Invalid);
+        };
+        let Some(ring_key) = self.keys.get(&kid) else {
+            return Err(TokenError::Invalid);
+        };
+        if !ring_key.is_valid(SystemTime::now()) {
+            return Err(TokenError::Invalid);
+        }
+        decode::<T>(
+            token,
+            &ring_key.key.decoding,
+            &Validation::new(ring_key.key.algorithm),
+        )
+        .or(Err(TokenError::Invalid))
+    }
+}
+
+#[derive(Debug)]
+pub enum KeyError {
+    IO,
+    Invalid,
+    MissingSigningKey,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{KeyError, KeyRing, SigningKey};
+    use jsonwebtoken::jwk::AlgorithmParameters;
+    use jsonwebtoken::{decode, DecodingKey, Validation};
+    use ring::rand::SystemRandom;
+    use ring::signature::Ed25519KeyPair;
+    use serde::{Deserialize, Serialize};
+    use std::path::Path;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Claims {
+        sub: String,
+        exp: u64,
+    }
+
+    fn claims() -> Claims {
+        let exp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap() + Duration::from_secs(60);
+        Claims {
+            sub: "some account".to_string(),
+            exp: exp.as_secs(),
+        }
+    }
+
+    #[test]
+    fn test_rotate_keeps_previous_key_for_overlap() {
+        let mut ring = KeyRing::new(SigningKey::from_secret("first", b"test1234"));
+        let previous = ring.encode(&claims()).expect("Expected token");
+
+        ring.rotate(
+            SigningKey::generate("second").expect("Expected key"),
+            Duration::from_secs(60),
+        );
+        let next = ring.encode(&claims()).expect("Expected token");
+
+        assert!(ring.decode::<Claims>(&previous).is_ok());
+        assert_eq!(
+            jsonwebtoken::decode_header(&next)
+                .expect("Expected header")
+                .kid
+                .as_deref(),
+            Some("second")
+        );
+        assert!(ring.decode::<Claims>(&next).is_ok());
+    }
+
+    #[test]
+    fn test_rotate_rejects_previous_key_after_overlap() {
+        let mut ring = KeyRing::new(SigningKey::from_secret("first", b"test1234"));
+        let previous = ring.encode(&claims()).expect("Expected token");
+
+        ring.rotate(
+            SigningKey::generate("second").expect("Expected key"),
+            Duration::ZERO,
+        );
+
+        assert!(ring.decode::<Claims>(&previous).is_err());
+    }
+
+    fn write_key(dir: &Path, kid: &str) {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).expect("Expected key");
+        let pem = pem::encode(&pem::Pem {
+            tag: "PRIVATE KEY".to_string(),
+            contents: pkcs8.as_ref().to_vec(),
+        });
+        std::fs::write(dir.join(format!("{kid}.pem")), pem).expect("Expected to write key");
+    }
+
+    #[test]
+    fn test_reload_rotates_to_the_next_key() {
+        let dir = tempfile::tempdir().unwrap();
+        write_key(dir.path(), "first");
+        let mut ring = KeyRing::load(dir.path(), "first").expect("Expected keys");
+        let previous = ring.encode(&claims()).expect("Expected token");
+
+        // the next key is published before it signs
+        write_key(dir.path(), "second");
+        ring.reload(dir.path(), "first", Duration::from_secs(60))
+            .expect("Expected keys");
+        assert!(ring.jwks().find("second").is_some());
+        assert_eq!(
+            header_kid(&ring.encode(&claims()).expect("Expected token")),
+            "first"
+        );
+
+        ring.reload(dir.path(), "second", Duration::from_secs(60))
+            .expect("Expected keys");
+        assert_eq!(
+            header_kid(&ring.encode(&claims()).expect("Expected token")),
+            "second"
+        );
+        assert!(ring.decode::<Claims>(&previous).is_ok());
+    }
+
+    #[test]
+    fn test_reload_keeps_ring_without_signing_key() {
+        let dir = tempfile::tempdir().unwrap();
+        write_key(dir.path(), "first");
+        let mut ring = KeyRing::load(dir.path(), "first").expect("Expected keys");
+
+        assert!(matches!(
+            ring.reload(dir.path(), "missing", Duration::from_secs(60)),
+            Err(KeyError::MissingSigningKey)
+        ));
+        assert_eq!(
+            header_kid(&ring.encode(&claims()).expect("Expected token")),
+            "first"
+        );
+    }
+
+    fn header_kid(token: &str) -> String {
+        jsonwebtoken::decode_header(token)
+            .expect("Expected header")
+            .kid
+            .expect("Expected kid")
+    }
+
+    #[test]
+    fn test_unknown_kid_is_rejected() {
+        let ring = KeyRing::new(SigningKey::generate("first").expect("Expected key"));
+        let other = KeyRing::new(SigningKey::generate("second").expect("Expected key"));
+        let token = other.encode(&claims()).expect("Expected token");
+
+        assert!(ring.decode::<Claims>(&token).is_err());
+    }
+
+    #[test]
+    fn test_jwks_verifies_tokens() {
+        let mut ring = KeyRing::new(SigningKey::from_secret("secret", b"test1234"));
+        ring.rotate(
+            SigningKey::generate("public").expect("Expected key"),
+            Duration::from_secs(60),
+        );
+        let token = ring.encode(&claims()).expect("Expected token");
+
+        // secrets are never published
+        let jwks = ring.jwks();
+        assert_eq!(jwks.keys.len(), 1);
+        let jwk = jwks.find("public").expect("Expected published key");
+        assert!(matches!(
+            jwk.algorithm,
+            AlgorithmParameters::OctetKeyPair(_)
+        ));
+
+        // other services only need the jwks
+        let key = DecodingKey::from_jwk(jwk).expect("Expected decoding key");
+        let decoded = decode::<Claims>(
+            &token,
+            &key,
+            &Validation::new(jsonwebtoken::Algorithm::EdDSA),
+        )
+        .expect("Expected valid token");
+        assert_eq!(decoded.claims.sub, "some account");
+    }
+}
@@ -1,5 +1,15 @@
 mod account;
+mod attempt;
+mod factor;
+mod identity_provider;
+mod key_ring;
+mod one_time_token;
 mod token;
 
 pub use account::*;
+pub use attempt::*;
+pub use factor::*;
+pub use identity_provider::*;
+pub use key_ring::*;
+pub use one_time_token::*;
 pub use token::*;
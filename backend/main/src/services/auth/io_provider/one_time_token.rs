@@ -0,0 +1,125 @@
+use crate::services::auth::io::{OneTimeTokenIO, TokenError};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use sqlx::types::time::OffsetDateTime;
+use sqlx::PgConnection;
+use std::time::{Duration, SystemTime};
+use uuid::Uuid;
+
+/// number of random bytes of a token
+const TOKEN_LEN: usize = 32;
+
+/// single use tokens stored in a postgres table per purpose
+///
+/// tokens are random and only their sha256 is stored,
+/// a slow password hash is not needed as they can not be guessed
+#[derive(Clone)]
+pub struct LocalOneTimeTokenIO {
+    table: &'static str,
+    ttl: Duration,
+}
+
+impl LocalOneTimeTokenIO {
+    /// tokens to reset forgotten passwords
+    pub fn password_reset(ttl: Duration) -> Self {
+        Self {
+            table: "password_reset",
+            ttl,
+        }
+    }
+
+    /// tokens to verify the external_id of new accounts
+    pub fn verification(ttl: Duration) -> Self {
+        Self {
+            table: "account_verification",
+            ttl,
+        }
+    }
+
+    /// time a token stays valid after it has been created
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+}
+
+impl OneTimeTokenIO for LocalOneTimeTokenIO {
+    type Ctx = PgConnection;
+
+    async fn create(&mut self, id: &Uuid, ctx: &mut Self::Ctx) -> Result<String, TokenError> {
+        let token = generate_token();
+        let expires_at = OffsetDateTime::from(SystemTime::now() + self.ttl);
+
+        sqlx::query(&format!(
+            r#"DELETE FROM public.{} WHERE account_id = $1"#,
+            self.table
+        ))
+        .bind(id)
+        .execute(&mut *ctx)
+        .await
+        .or(Err(TokenError::IO))?;
+        sqlx::query(&format!(
+            r#"INSERT INTO public.{} (token_hash, account_id, expires_at) VALUES ($1, $2, $3)"#,
+            self.table
+        ))
+        .bind(hash_token(&token))
+        .bind(id)
+        .bind(expires_at)
+        .execute(&mut *ctx)
+        .await
+        .or(Err(TokenError::IO))?;
+
+        Ok(token)
+    }
+
+    async fn consume(&mut self, token: &str, ctx: &mut Self::Ctx) -> Result<Uuid, TokenError> {
+        // deleting and returning in one statement prevents a token from being used twice
+        let Some((account_id, expires_at)) = sqlx::query_as::<_, (Uuid, OffsetDateTime)>(&format!(
+            r#"DELETE FROM public.{} WHERE token_hash = $1 RETURNING account_id, expires_at"#,
+            self.table
+        ))
+        .bind(hash_token(token))
+        .fetch_optional(ctx)
+        .await
+        .or(Err(TokenError::IO))?
+        else {
+            return Err(TokenError::Invalid);
+        };
+
+        if SystemTime::from(expires_at) < SystemTime::now() {
+            return Err(TokenError::Invalid);
+        }
+        Ok(account_id)
+    }
+}
+
+/// returns a hex encoded random token
+pub(super) fn generate_token() -> String {
+    let mut bytes = [0u8; TOKEN_LEN];
+    OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+/// returns the hex encoded sha256 of a token
+pub(super) fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate_token, hash_token, TOKEN_LEN};
+
+    #[test]
+    fn tokens_are_random() {
+        let token = generate_token();
+        assert_eq!(token.len(), TOKEN_LEN * 2);
+        assert_ne!(token, generate_token());
+    }
+
+    #[test]
+    fn hashes_do_not_contain_the_token() {
+        let token = generate_token();
+        assert_eq!(hash_token(&token), hash_token(&token));
+        assert_ne!(hash_token(&token), token);
+        assert_ne!(hash_token(&token), hash_token(&generate_token()));
+    }
+}
@@ -1,27 +1,97 @@
+use super::one_time_token::{generate_token, hash_token};
+use super::{KeyError, KeyRing};
 use crate::services::auth::io::{TokenError, TokenIO};
+use crate::services::auth::oidc::PendingLogin;
 use deadpool_redis::redis::AsyncCommands;
-use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use jsonwebtoken::jwk::JwkSet;
 use serde::{Deserialize, Serialize};
 use std::ops::Add;
+use std::path::Path;
+use std::sync::{Arc, RwLock, RwLockReadGuard};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use uuid::Uuid;
 
-use crate::types::RedisConnection;
+use crate::types::{CurrentUser, RedisConnection};
 #[derive(Debug, Serialize, Deserialize)]
 struct Claims {
     id: Uuid,
-    user_id: Uuid,
-    //TODO: extend according to docs
+    account: Uuid,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    user: Option<Uuid>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tenant: Option<Uuid>,
     exp: u64,
 }
 
+/// claims of a challenge, they lack the fields of access tokens so neither is accepted as the other
+#[derive(Debug, Serialize, Deserialize)]
+struct ChallengeClaims {
+    id: Uuid,
+    challenge: Uuid,
+    exp: u64,
+}
+
+/// time an account has to enter its second factor after its password
+const CHALLENGE_TTL: Duration = Duration::from_secs(5 * 60);
+/// a challenge which has not been consumed yet, stored under its id
+const CHALLENGE_PREFIX: &str = "challenge:";
+
+/// a login started at an identity provider, stored as json under the hash of its state
+const LOGIN_STATE_PREFIX: &str = "login_state:";
+/// time an account has to log in at an identity provider
+const LOGIN_STATE_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// a refresh token family is a redis hash of its account, user, tenant and the hash of its current token
+const FAMILY_PREFIX: &str = "refresh_family:";
+/// set of all refresh token families of an account
+const ACCOUNT_FAMILIES_PREFIX: &str = "refresh_families:";
+/// atomically replaces the current token of a family and extends its lifetime
+///
+/// KEYS[1] family, ARGV[1] hash of the presented token, ARGV[2] hash of the next token, ARGV[3] ttl,
+/// ARGV[4] [ACCOUNT_FAMILIES_PREFIX]
+/// returns nil for unknown families, an empty array if a rotated token has been replayed
+/// and the account, user and tenant of the family otherwise
+const ROTATE_SCRIPT: &str = r#"
+local current = redis.call('HGET', KEYS[1], 'current')
+if not current then
+    return nil
+end
+local account = redis.call('HGET', KEYS[1], 'account')
+if current ~= ARGV[1] then
+    redis.call('DEL', KEYS[1])
+    redis.call('SREM', ARGV[4] .. account, KEYS[1])
+    return {}
+end
+redis.call('HSET', KEYS[1], 'current', ARGV[2])
+redis.call('EXPIRE', KEYS[1], ARGV[3])
+redis.call('EXPIRE', ARGV[4] .. account, ARGV[3])
+return redis.call('HMGET', KEYS[1], 'account', 'user', 'tenant')
+"#;
+
+/// removes a family if the presented token is its current one
+///
+/// KEYS[1] family, ARGV[1] hash of the presented token, ARGV[2] [ACCOUNT_FAMILIES_PREFIX]
+/// returns 1 if the family has been removed
+const REVOKE_SCRIPT: &str = r#"
+if redis.call('HGET', KEYS[1], 'current') ~= ARGV[1] then
+    return 0
+end
+local account = redis.call('HGET', KEYS[1], 'account')
+redis.call('DEL', KEYS[1])
+redis.call('SREM', ARGV[2] .. account, KEYS[1])
+return 1
+"#;
+
+/// signed access tokens and opaque refresh tokens stored in redis
+///
+/// a refresh token is `<family>.<secret>`, only a hash of the current secret of a family is stored
+/// families expire if they have not been used within refresh_ttl
 #[derive(Clone)]
 pub struct LocalTokenIO {
     ttl: Duration,
-    enc_key: EncodingKey,
-    dec_key: DecodingKey,
-    header: Header,
-    validation: Validation,
+    refresh_ttl: Duration,
+    // shared by all clones, a rotation applies to every one of them
+    keys: Arc<RwLock<KeyRing>>,
 }
 
 impl TokenIO for LocalTokenIO {
@@ -29,7 +99,7 @@ impl TokenIO for LocalTokenIO {
 
     async fn create(
         &mut self,
-        internal_id: &Uuid,
+        user: &CurrentUser,
         _ctx: &mut Self::Ctx, //TODO: remove from trait because we only ever use it without?
     ) -> Result<String, TokenError> {
         let Ok(expiry) = SystemTime::now().add(self.ttl).duration_since(UNIX_EPOCH) else {
@@ -37,15 +107,16 @@ impl TokenIO for LocalTokenIO {
         };
         let claim = Claims {
             id: Uuid::new_v4(),
-            user_id: *internal_id,
+            account: user.account,
+            user: user.user,
+            tenant: user.tenant,
             exp: expiry.as_secs(),
         };
-        encode(&self.header, &claim, &self.enc_key).or(Err(TokenError::Invalid))
+        self.keys().encode(&claim)
     }
 
     async fn revoke(&mut self, token: &str, ctx: &mut Self::Ctx) -> Option<TokenError> {
-        //TODO move decode and encode into own functions
-        let Ok(token) = decode::<Claims>(token, &self.dec_key, &self.validation) else {
+        let Ok(token) = self.keys().decode::<Claims>(token) else {
             // Token is invalid
             // - corrupt
             // - unable to deserialize claim
@@ -75,6 +146,23 @@ impl TokenIO for LocalTokenIO {
             return Some(TokenError::IO);
         };
 
+        let families_key = format!("{ACCOUNT_FAMILIES_PREFIX}{id}");
+        let Ok(families) = ctx.smembers::<_, Vec<String>>(&families_key).await else {
+            return Some(TokenError::IO);
+        };
+        if ctx
+            .del::<_, ()>(
+                families
+                    .into_iter()
+                    .chain([families_key])
+                    .collect::<Vec<_>>(),
+            )
+            .await
+            .is_err()
+        {
+            return Some(TokenError::IO);
+        }
+
         if redis::cmd("SET")
             .arg(id.to_string())
             .arg(expiry.as_secs())
@@ -92,8 +180,8 @@ impl TokenIO for LocalTokenIO {
         }
     }
 
-    async fn verify(&self, token: &str, ctx: &mut Self::Ctx) -> Result<Uuid, TokenError> {
-        let Ok(token) = decode::<Claims>(token, &self.dec_key, &self.validation) else {
+    async fn verify(&self, token: &str, ctx: &mut Self::Ctx) -> Result<CurrentUser, TokenError> {
+        let Ok(token) = self.keys().decode::<Claims>(token) else {
             // Token is invalid
             // - corrupt
             // - unable to deserialize claim
@@ -101,13 +189,13 @@ impl TokenIO for LocalTokenIO {
             return Err(TokenError::Invalid);
         };
 
-        if let Ok(a) = ctx.exists::<String, i32>(token.claims.id.to_string()).await && a == 1 {
+        if let Ok(1) = ctx.exists::<String, i32>(token.claims.id.to_string()).await {
             // token with id has been revoked 
             return Err(TokenError::Invalid);
         }
 
         if let Ok(revoked_at) = ctx
-            .get::<String, usize>(token.claims.user_id.to_string())
+            .get::<String, usize>(token.claims.account.to_string())
             .await
         {
             // all tokens for this user issued before revoked_at have been revoked
@@ -121,25 +209,280 @@ impl TokenIO for LocalTokenIO {
             }
         }
 
-        Ok(token.claims.user_id)
+        Ok(CurrentUser {
+            account: token.claims.account,
+            user: token.claims.user,
+            tenant: token.claims.tenant,
+        })
+    }
+
+    async fn create_refresh(
+        &mut self,
+        user: &CurrentUser,
+        ctx: &mut Self::Ctx,
+    ) -> Result<String, TokenError> {
+        let family = format!("{FAMILY_PREFIX}{}", Uuid::new_v4());
+        let families_key = format!("{ACCOUNT_FAMILIES_PREFIX}{}", user.account);
+        let secret = generate_token();
+        let ttl = self.refresh_ttl.as_secs() as usize;
+
+        let mut fields = vec![
+            ("account", user.account.to_string()),
+            ("current", hash_token(&secret)),
+        ];
+        if let (Some(user), Some(tenant)) = (user.user, user.tenant) {
+            fields.push(("user", user.to_string()));
+            fields.push(("tenant", tenant.to_string()));
+        }
+
+        redis::pipe()
+            .atomic()
+            .hset_multiple(&family, &fields)
+            .ignore()
+            .expire(&family, ttl)
+            .ignore()
+            .sadd(&families_key, &family)
+            .ignore()
+            .expire(&families_key, ttl)
+            .ignore()
+            .query_async::<Self::Ctx, ()>(ctx)
+            .await
+            .or(Err(TokenError::IO))?;
+
+        Ok(format!("{}.{secret}", &family[FAMILY_PREFIX.len()..]))
+    }
+
+    async fn rotate_refresh(
+        &mut self,
+        token: &str,
+        ctx: &mut Self::Ctx,
+    ) -> Result<(CurrentUser, String), TokenError> {
+        let (family, secret) = parse_refresh(token)?;
+        let next = generate_token();
+
+        let family_user = redis::Script::new(ROTATE_SCRIPT)
+            .key(format!("{FAMILY_PREFIX}{family}"))
+            .arg(hash_token(secret))
+            .arg(hash_token(&next))
+            .arg(self.refresh_ttl.as_secs())
+            .arg(ACCOUNT_FAMILIES_PREFIX)
+            .invoke_async::<Self::Ctx, Option<Vec<Option<String>>>>(ctx)
+            .await
+            .or(Err(TokenError::IO))?;
+
+        match family_user.as_deref() {
+            // unknown or expired family
+            None => Err(TokenError::Invalid),
+            Some([]) => {
+                tracing::warn!("revoked refresh token family {family} after reuse");
+                Err(TokenError::Invalid)
+            }
+            Some([Some(account), user, tenant]) => {
+                let parse = |id: &Option<String>| {
+                    id.as_deref()
+                        .map(Uuid::parse_str)
+                        .transpose()
+                        .or(Err(TokenError::IO))
+                };
+                let user = CurrentUser {
+                    account: Uuid::parse_str(account).or(Err(TokenError::IO))?,
+                    user: parse(user)?,
+                    tenant: parse(tenant)?,
+                };
+                Ok((user, format!("{family}.{next}")))
+            }
+            Some(_) => Err(TokenError::IO),
+        }
+    }
+
+    async fn revoke_refresh(&mut self, token: &str, ctx: &mut Self::Ctx) -> Option<TokenError> {
+        let (family, secret) = match parse_refresh(token) {
+            Ok(parsed) => parsed,
+            Err(e) => return Some(e),
+        };
+
+        match redis::Script::new(REVOKE_SCRIPT)
+            .key(format!("{FAMILY_PREFIX}{family}"))
+            .arg(hash_token(secret))
+            .arg(ACCOUNT_FAMILIES_PREFIX)
+            .invoke_async::<Self::Ctx, i32>(ctx)
+            .await
+        {
+            Ok(1) => None,
+            Ok(_) => Some(TokenError::Invalid),
+            Err(_) => Some(TokenError::IO),
+        }
+    }
+
+    async fn create_challenge(
+        &mut self,
+        id: &Uuid,
+        ctx: &mut Self::Ctx,
+    ) -> Result<String, TokenError> {
+        let Ok(expiry) = SystemTime::now()
+            .add(CHALLENGE_TTL)
+            .duration_since(UNIX_EPOCH)
+        else {
+            return Err(TokenError::Invalid);
+        };
+        let claims = ChallengeClaims {
+            id: Uuid::new_v4(),
+            challenge: *id,
+            exp: expiry.as_secs(),
+        };
+        ctx.set_ex::<_, _, ()>(
+            format!("{CHALLENGE_PREFIX}{}", claims.id),
+            1,
+            CHALLENGE_TTL.as_secs() as usize,
+        )
+        .await
+        .or(Err(TokenError::IO))?;
+        self.keys().encode(&claims)
+    }
+
+    async fn verify_challenge(&self, token: &str, _ctx: &mut Self::Ctx) -> Result<Uuid, TokenError> {
+        Ok(self.keys().decode::<ChallengeClaims>(token)?.claims.challenge)
+    }
+
+    async fn consume_challenge(
+        &mut self,
+        token: &str,
+        ctx: &mut Self::Ctx,
+    ) -> Result<Uuid, TokenError> {
+        let claims = self.keys().decode::<ChallengeClaims>(token)?.claims;
+        // only one of concurrent logins with the same challenge deletes it
+        match ctx
+            .del::<_, i32>(format!("{CHALLENGE_PREFIX}{}", claims.id))
+            .await
+        {
+            Ok(1) => Ok(claims.challenge),
+            Ok(_) => Err(TokenError::Invalid),
+            Err(_) => Err(TokenError::IO),
+        }
+    }
+
+    async fn create_login_state(
+        &mut self,
+        login: &PendingLogin,
+        ctx: &mut Self::Ctx,
+    ) -> Result<String, TokenError> {
+        let state = generate_token();
+        let login = serde_json::to_string(login).or(Err(TokenError::Invalid))?;
+        ctx.set_ex::<_, _, ()>(
+            format!("{LOGIN_STATE_PREFIX}{}", hash_token(&state)),
+            login,
+            LOGIN_STATE_TTL.as_secs() as usize,
+        )
+        .await
+        .or(Err(TokenError::IO))?;
+        Ok(state)
+    }
+
+    async fn consume_login_state(
+        &mut self,
+        state: &str,
+        ctx: &mut Self::Ctx,
+    ) -> Result<PendingLogin, TokenError> {
+        // getting and deleting in one command prevents a state from being used twice
+        let login = redis::cmd("GETDEL")
+            .arg(format!("{LOGIN_STATE_PREFIX}{}", hash_token(state)))
+            .query_async::<Self::Ctx, Option<String>>(ctx)
+            .await
+            .or(Err(TokenError::IO))?
+            .ok_or(TokenError::Invalid)?;
+        serde_json::from_str(&login).or(Err(TokenError::Invalid))
     }
 }
 
 impl LocalTokenIO {
-    pub fn new(ttl: Duration, key: &str) -> Self {
+    /// ttl of access tokens should be short as they can not be revoked one by one cheaply,
+    /// refresh_ttl is the time a client stays logged in without using it
+    pub fn new(ttl: Duration, refresh_ttl: Duration, keys: KeyRing) -> Self {
         Self {
             ttl,
-            enc_key: EncodingKey::from_secret(key.as_ref()),
-            dec_key: DecodingKey::from_secret(key.as_ref()),
-            header: Header::new(Algorithm::HS256),
-            validation: Validation::default(),
+            refresh_ttl,
+            keys: Arc::new(RwLock::new(keys)),
         }
     }
+
+    /// public keys verifying access tokens
+    pub fn jwks(&self) -> JwkSet {
+        self.keys().jwks()
+    }
+
+    /// reloads the keys of a directory, see [KeyRing::reload]
+    ///
+    /// the previous signing key keeps verifying until the access tokens it signed expired
+    pub fn reload_keys(&self, dir: impl AsRef<Path>, signing_kid: &str) -> Result<(), KeyError> {
+        self.keys
+            .write()
+            .expect("Expected to lock key ring")
+            .reload(dir, signing_kid, self.ttl)
+    }
+
+    fn keys(&self) -> RwLockReadGuard<'_, KeyRing> {
+        self.keys.read().expect("Expected to lock key ring")
+    }
+}
+
+/// splits a refresh token into its family and secret
+fn parse_refresh(token: &str) -> Result<(Uuid, &str), TokenError> {
+    let Some((family, secret)) = token.split_once('.') else {
+        return Err(TokenError::Invalid);
+    };
+    let Ok(family) = Uuid::parse_str(family) else {
+        return Err(TokenError::Invalid);
+    };
+    Ok((family, secret))
 }
 
 #[cfg(test)]
 mod tests {
+    use super::{parse_refresh, ChallengeClaims, Claims};
+    use crate::services::auth::io_provider::{KeyRing, SigningKey};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+    use uuid::Uuid;
 
     #[tokio::test]
     async fn test_create_token() {}
+
+    #[test]
+    fn test_parse_refresh() {
+        let family = Uuid::new_v4();
+        let token = format!("{family}.some.secret");
+
+        let (parsed, secret) = parse_refresh(&token).expect("Expected valid refresh token");
+        assert_eq!(parsed, family);
+        assert_eq!(secret, "some.secret");
+
+        assert!(parse_refresh("no family").is_err());
+        assert!(parse_refresh("not a uuid.secret").is_err());
+    }
+
+    #[test]
+    fn test_challenges_are_no_access_tokens() {
+        let keys = KeyRing::new(SigningKey::from_secret("test", b"test1234"));
+        let exp = (SystemTime::now().duration_since(UNIX_EPOCH).unwrap() + Duration::from_secs(60))
+            .as_secs();
+
+        let challenge = keys
+            .encode(&ChallengeClaims {
+                id: Uuid::new_v4(),
+                challenge: Uuid::new_v4(),
+                exp,
+            })
+            .expect("Expected challenge");
+        assert!(keys.decode::<Claims>(&challenge).is_err());
+
+        let access = keys
+            .encode(&Claims {
+                id: Uuid::new_v4(),
+                account: Uuid::new_v4(),
+                user: None,
+                tenant: None,
+                exp,
+            })
+            .expect("Expected access token");
+        assert!(keys.decode::<ChallengeClaims>(&access).is_err());
+    }
 }
@@ -1,18 +1,52 @@
 mod account;
 mod client;
 mod error;
+mod external;
 pub(crate) mod io;
 pub mod io_provider;
+mod oidc;
+mod reset;
+mod totp;
+mod verification;
 
 use crate::services::auth::client::AuthenticationClient;
-use crate::services::auth::io_provider::{LocalAccountIO, LocalTokenIO};
-use sqlx::{Postgres, Transaction};
+use crate::services::auth::external::ExternalLoginClient;
+use crate::services::auth::io_provider::{
+    LocalAccountIO, LocalAttemptIO, LocalFactorIO, LocalIdentityProviderIO, LocalOneTimeTokenIO,
+    LocalTokenIO,
+};
+use crate::services::auth::reset::PasswordResetClient;
+use crate::services::auth::verification::VerificationClient;
+use crate::services::mail::FileMailSender;
 
 pub type Auth = AuthenticationClient<
-    Transaction<'static, Postgres>,
+    sqlx::PgConnection,
+    RedisConnection,
+    LocalAccountIO,
+    LocalTokenIO,
+    LocalAttemptIO,
+    LocalFactorIO,
+>;
+pub type PasswordReset = PasswordResetClient<
+    sqlx::PgConnection,
+    RedisConnection,
+    LocalAccountIO,
+    LocalTokenIO,
+    LocalOneTimeTokenIO,
+    FileMailSender,
+>;
+pub type ExternalLogin = ExternalLoginClient<
+    sqlx::PgConnection,
     RedisConnection,
     LocalAccountIO,
     LocalTokenIO,
+    LocalFactorIO,
+    LocalIdentityProviderIO,
 >;
+pub type Verification =
+    VerificationClient<sqlx::PgConnection, LocalAccountIO, LocalOneTimeTokenIO, FileMailSender>;
 use crate::types::RedisConnection;
+pub use client::{Login, LoginTokens, TotpEnrollment};
+pub use error::AuthError;
 pub use io_provider::*;
+pub use verification::is_mail_address;
@@ -0,0 +1,326 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use jsonwebtoken::jwk::{AlgorithmParameters, JwkSet};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use ring::digest;
+use serde::{Deserialize, Serialize};
+
+/// random bytes of verifiers and nonces, a verifier needs at least 43 characters (RFC 7636)
+const RANDOM_LEN: usize = 32;
+
+/// unreserved characters of RFC 3986 stay as they are
+const QUERY: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// id tokens have to be signed asymmetrically, a client secret must not be enough to forge them
+const ALGORITHMS: [Algorithm; 8] = [
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::EdDSA,
+];
+
+/// endpoints of an identity provider, published at `<issuer>/.well-known/openid-configuration`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProviderMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+/// login started at an identity provider, stored until its callback
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PendingLogin {
+    /// PKCE code verifier, only its challenge is sent to the identity provider
+    pub verifier: String,
+    /// has to be returned in the id token, thus it can not be replayed into another login
+    pub nonce: String,
+}
+
+impl PendingLogin {
+    pub fn generate() -> Self {
+        Self {
+            verifier: random(),
+            nonce: random(),
+        }
+    }
+
+    /// S256 code challenge of the verifier
+    pub fn challenge(&self) -> String {
+        URL_SAFE_NO_PAD.encode(digest::digest(&digest::SHA256, self.verifier.as_bytes()))
+    }
+}
+
+/// validated claims of an id token
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IdClaims {
+    pub iss: String,
+    /// identifies the account at the issuer, it is only unique together with the issuer
+    pub sub: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+}
+
+impl IdClaims {
+    /// mail address of the account if the identity provider verified it
+    pub fn verified_email(&self) -> Option<&str> {
+        self.email.as_deref().filter(|_| self.email_verified)
+    }
+}
+
+/// url the user agent is redirected to for an authorization code flow with PKCE
+pub fn authorization_url(
+    metadata: &ProviderMetadata,
+    client_id: &str,
+    redirect_uri: &str,
+    state: &str,
+    login: &PendingLogin,
+) -> String {
+    let params = [
+        ("response_type", "code"),
+        ("scope", "openid email"),
+        ("client_id", client_id),
+        ("redirect_uri", redirect_uri),
+        ("state", state),
+        ("nonce", &login.nonce),
+        ("code_challenge", &login.challenge()),
+        ("code_challenge_method", "S256"),
+    ]
+    .iter()
+    .map(|(key, value)| format!("{key}={}", utf8_percent_encode(value, QUERY)))
+    .collect::<Vec<_>>()
+    .join("&");
+
+    // endpoints may already contain a query
+    let separator = if metadata.authorization_endpoint.contains('?') {
+        '&'
+    } else {
+        '?'
+    };
+    format!("{}{separator}{params}", metadata.authorization_endpoint)
+}
+
+/// validates the signature, issuer, audience, expiry and nonce of an id token
+///
+/// returns None if any of them is invalid
+pub fn validate_id_token(
+    id_token: &str,
+    jwks: &JwkSet,
+    issuer: &str,
+    client_id: &str,
+    nonce: &str,
+) -> Option<IdClaims> {
+    let header = jsonwebtoken::decode_header(id_token).ok()?;
+    if !ALGORITHMS.contains(&header.alg) {
+        return None;
+    }
+    // providers with a single key may omit the kid
+    let jwk = match &header.kid {
+        Some(kid) => jwks.find(kid)?,
+        None if jwks.keys.len() == 1 => &jwks.keys[0],
+        None => return None,
+    };
+    if matches!(jwk.algorithm, AlgorithmParameters::OctetKey(_)) {
+        return None;
+    }
+    let key = DecodingKey::from_jwk(jwk).ok()?;
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_issuer(&[issuer]);
+    validation.set_audience(&[client_id]);
+    validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+    let claims = jsonwebtoken::decode::<IdClaims>(id_token, &key, &validation)
+        .ok()?
+        .claims;
+
+    (claims.nonce.as_deref() == Some(nonce)).then_some(claims)
+}
+
+/// url safe random string
+fn random() -> String {
+    let mut bytes = [0u8; RANDOM_LEN];
+    OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{authorization_url, validate_id_token, IdClaims, PendingLogin, ProviderMetadata};
+    use jsonwebtoken::jwk::JwkSet;
+    use jsonwebtoken::{Algorithm, EncodingKey, Header};
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+    use serde_json::json;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    const ISSUER: &str = "http://idp.test";
+
+    /// signing key of the identity provider and its published key set
+    fn provider_keys() -> (EncodingKey, JwkSet) {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&ring::rand::SystemRandom::new())
+            .expect("Expected Ed25519 key");
+        let pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).expect("Expected Ed25519 key");
+        let jwks = serde_json::from_value(json!({"keys": [{
+            "kty": "OKP",
+            "crv": "Ed25519",
+            "kid": "idp",
+            "x": base64::Engine::encode(
+                &base64::engine::general_purpose::URL_SAFE_NO_PAD,
+                pair.public_key().as_ref()
+            ),
+        }]}))
+        .expect("Expected JwkSet");
+        (EncodingKey::from_ed_der(pkcs8.as_ref()), jwks)
+    }
+
+    fn id_token(key: &EncodingKey, claims: serde_json::Value) -> String {
+        let mut header = Header::new(Algorithm::EdDSA);
+        header.kid = Some("idp".to_string());
+        jsonwebtoken::encode(&header, &claims, key).expect("Expected id token")
+    }
+
+    fn claims(nonce: &str) -> serde_json::Value {
+        let exp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            + 60;
+        json!({
+            "iss": ISSUER,
+            "sub": "external subject",
+            "aud": "hygge",
+            "exp": exp,
+            "nonce": nonce,
+            "email": "some@mail.de",
+            "email_verified": true,
+        })
+    }
+
+    #[test]
+    fn test_challenge() {
+        // example of RFC 7636 appendix B
+        let login = PendingLogin {
+            verifier: "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk".to_string(),
+            nonce: String::new(),
+        };
+        assert_eq!(
+            login.challenge(),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+
+    #[test]
+    fn test_authorization_url() {
+        let metadata = ProviderMetadata {
+            issuer: ISSUER.to_string(),
+            authorization_endpoint: "http://idp.test/authorize?tenant=a".to_string(),
+            token_endpoint: "http://idp.test/token".to_string(),
+            jwks_uri: "http://idp.test/jwks".to_string(),
+        };
+        let login = PendingLogin {
+            verifier: "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk".to_string(),
+            nonce: "n-0S6_WzA2Mj".to_string(),
+        };
+
+        assert_eq!(
+            authorization_url(
+                &metadata,
+                "hygge",
+                "http://127.0.0.1:3000/cb",
+                "xyz",
+                &login
+            ),
+            "http://idp.test/authorize?tenant=a&response_type=code&scope=openid%20email\
+            &client_id=hygge&redirect_uri=http%3A%2F%2F127.0.0.1%3A3000%2Fcb&state=xyz\
+            &nonce=n-0S6_WzA2Mj&code_challenge=E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM\
+            &code_challenge_method=S256"
+        );
+    }
+
+    #[test]
+    fn test_validate_id_token() {
+        let (key, jwks) = provider_keys();
+        let token = id_token(&key, claims("nonce"));
+
+        let claims = validate_id_token(&token, &jwks, ISSUER, "hygge", "nonce")
+            .expect("Expected valid id token");
+        assert_eq!(claims.sub, "external subject");
+        assert_eq!(claims.verified_email(), Some("some@mail.de"));
+    }
+
+    #[test]
+    fn test_validate_id_token_rejects_other_logins() {
+        let (key, jwks) = provider_keys();
+        let token = id_token(&key, claims("nonce"));
+
+        // nonce of another login
+        assert_eq!(
+            validate_id_token(&token, &jwks, ISSUER, "hygge", "other nonce"),
+            None
+        );
+        // issued to another client
+        assert_eq!(
+            validate_id_token(&token, &jwks, ISSUER, "other client", "nonce"),
+            None
+        );
+        // issued by another provider
+        assert_eq!(
+            validate_id_token(&token, &jwks, "http://other.test", "hygge", "nonce"),
+            None
+        );
+        // signed by another key
+        let (_, other_jwks) = provider_keys();
+        assert_eq!(
+            validate_id_token(&token, &other_jwks, ISSUER, "hygge", "nonce"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_validate_id_token_rejects_symmetric_keys() {
+        let jwks = serde_json::from_value(json!({"keys": [{
+            "kty": "oct",
+            "kid": "idp",
+            "k": "dGVzdDEyMzQ",
+        }]}))
+        .expect("Expected JwkSet");
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some("idp".to_string());
+        let token = jsonwebtoken::encode(
+            &header,
+            &claims("nonce"),
+            &EncodingKey::from_secret(b"test1234"),
+        )
+        .expect("Expected id token");
+
+        assert_eq!(
+            validate_id_token(&token, &jwks, ISSUER, "hygge", "nonce"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_unverified_emails_are_ignored() {
+        let claims = IdClaims {
+            iss: ISSUER.to_string(),
+            sub: "external subject".to_string(),
+            nonce: None,
+            email: Some("some@mail.de".to_string()),
+            email_verified: false,
+        };
+        assert_eq!(claims.verified_email(), None);
+    }
+}
@@ -0,0 +1,277 @@
+use crate::services::auth::error::AuthError;
+use crate::services::auth::io::{AccountError, AccountIO, OneTimeTokenIO, TokenIO};
+use crate::services::auth::PasswordReset;
+use crate::services::mail::{Mail, MailSender};
+use crate::types::Services;
+use axum::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use std::convert::Infallible;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// resets forgotten passwords using single use tokens sent by mail
+///
+/// accounts and reset tokens share a context, thus a confirmed reset is stored atomically
+pub struct PasswordResetClient<AccountCtx, TokenCtx, Accounts, Tokens, Resets, Mails> {
+    accounts: Arc<RwLock<Accounts>>,
+    tokens: Arc<RwLock<Tokens>>,
+    resets: Arc<RwLock<Resets>>,
+    mails: Arc<Mails>,
+    ctx: std::marker::PhantomData<(AccountCtx, TokenCtx)>,
+}
+
+impl<AccountCtx, TokenCtx, Accounts, Tokens, Resets, Mails>
+    PasswordResetClient<AccountCtx, TokenCtx, Accounts, Tokens, Resets, Mails>
+where
+    Accounts: AccountIO<Ctx = AccountCtx>,
+    Tokens: TokenIO<Ctx = TokenCtx>,
+    Resets: OneTimeTokenIO<Ctx = AccountCtx>,
+    Mails: MailSender,
+{
+    pub fn new(
+        accounts: Arc<RwLock<Accounts>>,
+        tokens: Arc<RwLock<Tokens>>,
+        resets: Arc<RwLock<Resets>>,
+        mails: Arc<Mails>,
+    ) -> Self {
+        Self {
+            accounts,
+            tokens,
+            resets,
+            mails,
+            ctx: std::marker::PhantomData,
+        }
+    }
+
+    /// sends a reset token to the account identified by an external_id
+    ///
+    /// succeeds without sending anything if the account does not exist,
+    /// thus callers can not find out which accounts exist
+    pub async fn request(
+        &mut self,
+        external_id: &str,
+        account_ctx: &mut AccountCtx,
+    ) -> Result<(), AuthError> {
+        let account = match self
+            .accounts
+            .read()
+            .await
+            .get_by_external(external_id, account_ctx)
+            .await
+        {
+            Ok(account) => account,
+            Err(AccountError::NotFound) => return Ok(()),
+            Err(error) => return Err(error.into()),
+        };
+
+        let token = self
+            .resets
+            .write()
+            .await
+            .create(&account.id, account_ctx)
+            .await?;
+        self.mails
+            .send(&Mail {
+                to: account.id_external,
+                subject: "Reset your password".to_string(),
+                body: format!(
+                    "Use the following token to choose a new password:\n\n{token}\n\n\
+                    It can only be used once. If you did not request it, you can ignore this mail."
+                ),
+            })
+            .await
+            .or(Err(AuthError::IO))
+    }
+
+    /// sets a new password using a reset token
+    ///
+    /// consumes the token and revokes all login_tokens of the account
+    pub async fn confirm(
+        &mut self,
+        token: &str,
+        new_password: &str,
+        account_ctx: &mut AccountCtx,
+        token_ctx: &mut TokenCtx,
+    ) -> Result<(), AuthError> {
+        let user_id = self
+            .resets
+            .write()
+            .await
+            .consume(token, account_ctx)
+            .await?;
+
+        // hashing is slow, only hold the write lock to store the new hash
+        let hash = self
+            .accounts
+            .read()
+            .await
+            .create_password_hash(new_password, account_ctx)
+            .await?;
+
+        let mut accounts = self.accounts.write().await;
+        let mut account = accounts.get_by_internal(&user_id, account_ctx).await?;
+        account.hash = hash;
+        accounts.update(account, account_ctx).await?;
+        drop(accounts);
+
+        match self
+            .tokens
+            .write()
+            .await
+            .revoke_all(&user_id, token_ctx)
+            .await
+        {
+            Some(error) => Err(error.into()),
+            None => Ok(()),
+        }
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<Arc<Services>> for PasswordReset {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        _parts: &mut Parts,
+        state: &Arc<Services>,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(PasswordResetClient::new(
+            state.account_provider.clone(),
+            state.token_provider.clone(),
+            state.reset_provider.clone(),
+            state.mail_sender.clone(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::auth::account::{Account, AccountStatus};
+    use crate::services::auth::error::AuthError;
+    use crate::services::auth::io::{
+        AccountError, MockAccountIO, MockOneTimeTokenIO, MockTokenIO, TokenError,
+    };
+    use crate::services::auth::reset::PasswordResetClient;
+    use crate::services::mail::MemoryMailSender;
+    use std::sync::Arc;
+    use tokio::sync::RwLock;
+    use uuid::Uuid;
+
+    fn account(id: Uuid) -> Account {
+        Account {
+            id,
+            id_external: "some mail".to_string(),
+            hash: "old hash".to_string(),
+            status: AccountStatus::Active,
+        }
+    }
+
+    fn client(
+        accounts: MockAccountIO,
+        tokens: MockTokenIO,
+        resets: MockOneTimeTokenIO,
+        mails: MemoryMailSender,
+    ) -> PasswordResetClient<(), (), MockAccountIO, MockTokenIO, MockOneTimeTokenIO, MemoryMailSender>
+    {
+        PasswordResetClient::new(
+            Arc::new(RwLock::new(accounts)),
+            Arc::new(RwLock::new(tokens)),
+            Arc::new(RwLock::new(resets)),
+            Arc::new(mails),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_request_sends_token() {
+        let mut accounts = MockAccountIO::new();
+        let mut resets = MockOneTimeTokenIO::new();
+        let mails = MemoryMailSender::new();
+        accounts
+            .expect_get_by_external()
+            .returning(|_, _| Ok(account(Uuid::new_v4())));
+        resets
+            .expect_create()
+            .times(1)
+            .returning(|_, _| Ok("some token".to_string()));
+
+        client(accounts, MockTokenIO::new(), resets, mails.clone())
+            .request("some mail", &mut ())
+            .await
+            .expect("Expected reset to be requested");
+
+        let sent = mails.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].to, "some mail");
+        assert!(sent[0].body.contains("some token"));
+    }
+
+    #[tokio::test]
+    async fn test_request_unknown_account() {
+        let mut accounts = MockAccountIO::new();
+        let mut resets = MockOneTimeTokenIO::new();
+        let mails = MemoryMailSender::new();
+        accounts
+            .expect_get_by_external()
+            .returning(|_, _| Err(AccountError::NotFound));
+        resets.expect_create().never();
+
+        client(accounts, MockTokenIO::new(), resets, mails.clone())
+            .request("unknown mail", &mut ())
+            .await
+            .expect("Expected unknown accounts not to be revealed");
+        assert!(mails.sent().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_confirm() {
+        let user_id = Uuid::new_v4();
+        let mut accounts = MockAccountIO::new();
+        let mut tokens = MockTokenIO::new();
+        let mut resets = MockOneTimeTokenIO::new();
+        resets.expect_consume().returning(move |_, _| Ok(user_id));
+        accounts
+            .expect_get_by_internal()
+            .returning(|id, _| Ok(account(*id)));
+        accounts
+            .expect_create_password_hash()
+            .returning(|_, _| Ok("new hash".to_string()));
+        accounts
+            .expect_update()
+            .withf(move |account, _| account.id == user_id && account.hash == "new hash")
+            .times(1)
+            .returning(|account, _| Ok(account));
+        tokens
+            .expect_revoke_all()
+            .withf(move |id, _| *id == user_id)
+            .times(1)
+            .returning(|_, _| None);
+
+        client(accounts, tokens, resets, MemoryMailSender::new())
+            .confirm("some token", "test5678", &mut (), &mut ())
+            .await
+            .expect("Expected password to be reset");
+    }
+
+    #[tokio::test]
+    async fn test_confirm_invalid_token() {
+        let mut accounts = MockAccountIO::new();
+        let mut resets = MockOneTimeTokenIO::new();
+        resets
+            .expect_consume()
+            .returning(|_, _| Err(TokenError::Invalid));
+        accounts.expect_update().never();
+
+        assert!(matches!(
+            client(
+                accounts,
+                MockTokenIO::new(),
+                resets,
+                MemoryMailSender::new()
+            )
+            .confirm("used token", "test5678", &mut (), &mut ())
+            .await,
+            Err(AuthError::Credentials)
+        ));
+    }
+}
@@ -0,0 +1,163 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use ring::hmac;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// issuer shown next to codes in authenticator apps
+pub const ISSUER: &str = "hygge";
+
+/// 160 bit secrets as recommended for HMAC-SHA1
+const SECRET_LEN: usize = 20;
+const DIGITS: u32 = 6;
+/// seconds a code is valid for
+const PERIOD: u64 = 30;
+/// accepted steps before and after the current one as clocks of clients drift
+const SKEW: u64 = 1;
+/// number of recovery codes handed out on confirmation
+const RECOVERY_CODES: usize = 10;
+/// random bytes of a recovery code, enough to store only a fast hash like for tokens
+const RECOVERY_CODE_LEN: usize = 10;
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// time based one time password factor of an account (RFC 6238)
+#[derive(Debug, Clone)]
+pub struct TotpFactor {
+    pub secret: Vec<u8>,
+    /// unconfirmed factors are not required on login
+    pub confirmed: bool,
+    /// time step of the last accepted code, codes can not be used twice
+    pub last_step: Option<u64>,
+}
+
+pub fn generate_secret() -> Vec<u8> {
+    let mut secret = vec![0u8; SECRET_LEN];
+    OsRng.fill_bytes(&mut secret);
+    secret
+}
+
+/// recovery codes replace a code once each, eg. after losing the authenticator
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODES)
+        .map(|_| {
+            let mut code = [0u8; RECOVERY_CODE_LEN];
+            OsRng.fill_bytes(&mut code);
+            base32(&code)
+        })
+        .collect()
+}
+
+/// uri to enroll the secret in an authenticator app, usually shown as qr code
+pub fn otpauth_uri(issuer: &str, account: &str, secret: &[u8]) -> String {
+    let issuer = utf8_percent_encode(issuer, NON_ALPHANUMERIC);
+    let account = utf8_percent_encode(account, NON_ALPHANUMERIC);
+    format!(
+        "otpauth://totp/{issuer}:{account}?secret={}&issuer={issuer}&algorithm=SHA1&digits={DIGITS}&period={PERIOD}",
+        base32(secret)
+    )
+}
+
+/// checks a code against the steps around time
+///
+/// returns the time step of the code if it is valid
+pub fn verify(secret: &[u8], code: &str, time: SystemTime) -> Option<u64> {
+    if code.len() != DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let code = code.parse::<u32>().ok()?;
+    let now = time.duration_since(UNIX_EPOCH).ok()?.as_secs() / PERIOD;
+
+    (now.saturating_sub(SKEW)..=now + SKEW).find(|step| hotp(secret, *step) == code)
+}
+
+/// HMAC-based one time password of a counter (RFC 4226)
+fn hotp(secret: &[u8], counter: u64) -> u32 {
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, secret);
+    let tag = hmac::sign(&key, &counter.to_be_bytes());
+    let digest = tag.as_ref();
+
+    // dynamic truncation
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+    binary % 10u32.pow(DIGITS)
+}
+
+/// unpadded base32 (RFC 4648) as expected by authenticator apps
+pub fn base32(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity((bytes.len() * 8).div_ceil(5));
+    let mut buffer = 0u16;
+    let mut bits = 0;
+    for byte in bytes {
+        buffer = (buffer << 8) | *byte as u16;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{base32, hotp, otpauth_uri, verify, PERIOD};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    // test vectors of RFC 6238 for SHA1, truncated to six digits
+    const SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn test_hotp() {
+        assert_eq!(hotp(SECRET, 59 / PERIOD), 287082);
+        assert_eq!(hotp(SECRET, 1111111109 / PERIOD), 81804);
+        assert_eq!(hotp(SECRET, 1234567890 / PERIOD), 5924);
+        assert_eq!(hotp(SECRET, 2000000000 / PERIOD), 279037);
+    }
+
+    #[test]
+    fn test_verify_accepts_skew() {
+        let time = UNIX_EPOCH + Duration::from_secs(1111111109);
+        let step = 1111111109 / PERIOD;
+
+        assert_eq!(verify(SECRET, "081804", time), Some(step));
+        // code of the previous step
+        let previous = format!("{:06}", hotp(SECRET, step - 1));
+        assert_eq!(verify(SECRET, &previous, time), Some(step - 1));
+        // code of two steps ago
+        let expired = format!("{:06}", hotp(SECRET, step - 2));
+        assert_eq!(verify(SECRET, &expired, time), None);
+    }
+
+    #[test]
+    fn test_verify_rejects_malformed_codes() {
+        let time = UNIX_EPOCH + Duration::from_secs(1111111109);
+
+        assert_eq!(verify(SECRET, "81804", time), None);
+        assert_eq!(verify(SECRET, "+81804", time), None);
+        assert_eq!(verify(SECRET, "0818045", time), None);
+    }
+
+    #[test]
+    fn test_base32() {
+        assert_eq!(base32(b""), "");
+        assert_eq!(base32(b"f"), "MY");
+        assert_eq!(base32(b"fooba"), "MZXW6YTB");
+        assert_eq!(base32(b"foobar"), "MZXW6YTBOI");
+    }
+
+    #[test]
+    fn test_otpauth_uri() {
+        assert_eq!(
+            otpauth_uri("hygge", "some@mail.de", b"foobar"),
+            "otpauth://totp/hygge:some%40mail%2Ede?secret=MZXW6YTBOI&issuer=hygge&algorithm=SHA1&digits=6&period=30"
+        );
+    }
+}
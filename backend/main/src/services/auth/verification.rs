@@ -0,0 +1,287 @@
+use crate::services::auth::account::AccountStatus;
+use crate::services::auth::error::AuthError;
+use crate::services::auth::io::{AccountError, AccountIO, OneTimeTokenIO};
+use crate::services::auth::Verification;
+use crate::services::mail::{Mail, MailSender};
+use crate::types::Services;
+use axum::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use std::convert::Infallible;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+/// verifies the external_id of new accounts by mailing them a single use link
+///
+/// accounts can not log in until they have been verified
+pub struct VerificationClient<AccountCtx, Accounts, Verifications, Mails> {
+    accounts: Arc<RwLock<Accounts>>,
+    verifications: Arc<RwLock<Verifications>>,
+    mails: Arc<Mails>,
+    // the token is appended to this url in mails
+    verify_url: Arc<str>,
+    ctx: std::marker::PhantomData<AccountCtx>,
+}
+
+impl<AccountCtx, Accounts, Verifications, Mails>
+    VerificationClient<AccountCtx, Accounts, Verifications, Mails>
+where
+    Accounts: AccountIO<Ctx = AccountCtx>,
+    Verifications: OneTimeTokenIO<Ctx = AccountCtx>,
+    Mails: MailSender,
+{
+    pub fn new(
+        accounts: Arc<RwLock<Accounts>>,
+        verifications: Arc<RwLock<Verifications>>,
+        mails: Arc<Mails>,
+        verify_url: Arc<str>,
+    ) -> Self {
+        Self {
+            accounts,
+            verifications,
+            mails,
+            verify_url,
+            ctx: std::marker::PhantomData,
+        }
+    }
+
+    /// sends a verification link to a newly created account
+    ///
+    /// previously sent links of this account become invalid
+    pub async fn send(
+        &mut self,
+        user_id: &Uuid,
+        external_id: &str,
+        account_ctx: &mut AccountCtx,
+    ) -> Result<(), AuthError> {
+        let token = self
+            .verifications
+            .write()
+            .await
+            .create(user_id, account_ctx)
+            .await?;
+        self.mails
+            .send(&Mail {
+                to: external_id.to_string(),
+                subject: "Verify your account".to_string(),
+                body: format!(
+                    "Open the following link to verify your account:\n\n{}?token={token}\n\n\
+                    If you did not create an account, you can ignore this mail.",
+                    self.verify_url
+                ),
+            })
+            .await
+            .or(Err(AuthError::IO))
+    }
+
+    /// sends a new verification link to an unverified account identified by an external_id
+    ///
+    /// succeeds without sending anything if the account does not exist or has been verified,
+    /// thus callers can not find out which accounts exist
+    pub async fn resend(
+        &mut self,
+        external_id: &str,
+        account_ctx: &mut AccountCtx,
+    ) -> Result<(), AuthError> {
+        let account = match self
+            .accounts
+            .read()
+            .await
+            .get_by_external(external_id, account_ctx)
+            .await
+        {
+            Ok(account) => account,
+            Err(AccountError::NotFound) => return Ok(()),
+            Err(error) => return Err(error.into()),
+        };
+        if !matches!(account.status, AccountStatus::Unverified) {
+            return Ok(());
+        }
+
+        self.send(&account.id, &account.id_external, account_ctx)
+            .await
+    }
+
+    /// activates the account a verification token has been sent to
+    ///
+    /// consumes the token, expired tokens are rejected and a new link has to be requested
+    pub async fn confirm(
+        &mut self,
+        token: &str,
+        account_ctx: &mut AccountCtx,
+    ) -> Result<(), AuthError> {
+        let user_id = self
+            .verifications
+            .write()
+            .await
+            .consume(token, account_ctx)
+            .await?;
+
+        let mut accounts = self.accounts.write().await;
+        let mut account = accounts.get_by_internal(&user_id, account_ctx).await?;
+        // removed accounts stay removed
+        if !matches!(account.status, AccountStatus::Unverified) {
+            return Err(AuthError::Credentials);
+        }
+        account.status = AccountStatus::Active;
+        accounts.update(account, account_ctx).await?;
+        Ok(())
+    }
+}
+
+/// checks the external_id of a new account looks like a mail address
+///
+/// only catches obvious mistakes, the verification mail proves it is valid
+pub fn is_mail_address(external_id: &str) -> bool {
+    let Some((local, domain)) = external_id.split_once('@') else {
+        return false;
+    };
+    !local.is_empty()
+        && !domain.contains('@')
+        && !external_id.chars().any(char::is_whitespace)
+        && domain
+            .split_once('.')
+            .is_some_and(|(name, tld)| !name.is_empty() && !tld.is_empty())
+}
+
+#[async_trait]
+impl FromRequestParts<Arc<Services>> for Verification {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        _parts: &mut Parts,
+        state: &Arc<Services>,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(VerificationClient::new(
+            state.account_provider.clone(),
+            state.verification_provider.clone(),
+            state.mail_sender.clone(),
+            format!("{}/auth/verify", state.public_url).into(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::auth::account::{Account, AccountStatus};
+    use crate::services::auth::error::AuthError;
+    use crate::services::auth::io::{MockAccountIO, MockOneTimeTokenIO, TokenError};
+    use crate::services::auth::verification::{is_mail_address, VerificationClient};
+    use crate::services::mail::MemoryMailSender;
+    use std::sync::Arc;
+    use tokio::sync::RwLock;
+    use uuid::Uuid;
+
+    fn account(id: Uuid, status: AccountStatus) -> Account {
+        Account {
+            id,
+            id_external: "some@mail.org".to_string(),
+            hash: "some hash".to_string(),
+            status,
+        }
+    }
+
+    fn client(
+        accounts: MockAccountIO,
+        verifications: MockOneTimeTokenIO,
+        mails: MemoryMailSender,
+    ) -> VerificationClient<(), MockAccountIO, MockOneTimeTokenIO, MemoryMailSender> {
+        VerificationClient::new(
+            Arc::new(RwLock::new(accounts)),
+            Arc::new(RwLock::new(verifications)),
+            Arc::new(mails),
+            "https://hygge.test/auth/verify".into(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_send_link() {
+        let mut verifications = MockOneTimeTokenIO::new();
+        let mails = MemoryMailSender::new();
+        verifications
+            .expect_create()
+            .returning(|_, _| Ok("some token".to_string()));
+
+        client(MockAccountIO::new(), verifications, mails.clone())
+            .send(&Uuid::new_v4(), "some@mail.org", &mut ())
+            .await
+            .expect("Expected verification to be sent");
+
+        let sent = mails.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].to, "some@mail.org");
+        assert!(sent[0]
+            .body
+            .contains("https://hygge.test/auth/verify?token=some token"));
+    }
+
+    #[tokio::test]
+    async fn test_resend_only_to_unverified() {
+        let mut accounts = MockAccountIO::new();
+        let mut verifications = MockOneTimeTokenIO::new();
+        let mails = MemoryMailSender::new();
+        accounts
+            .expect_get_by_external()
+            .returning(|_, _| Ok(account(Uuid::new_v4(), AccountStatus::Active)));
+        verifications.expect_create().never();
+
+        client(accounts, verifications, mails.clone())
+            .resend("some@mail.org", &mut ())
+            .await
+            .expect("Expected verified accounts not to be revealed");
+        assert!(mails.sent().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_confirm_activates() {
+        let user_id = Uuid::new_v4();
+        let mut accounts = MockAccountIO::new();
+        let mut verifications = MockOneTimeTokenIO::new();
+        verifications
+            .expect_consume()
+            .returning(move |_, _| Ok(user_id));
+        accounts
+            .expect_get_by_internal()
+            .returning(|id, _| Ok(account(*id, AccountStatus::Unverified)));
+        accounts
+            .expect_update()
+            .withf(move |account, _| {
+                account.id == user_id && matches!(account.status, AccountStatus::Active)
+            })
+            .times(1)
+            .returning(|account, _| Ok(account));
+
+        client(accounts, verifications, MemoryMailSender::new())
+            .confirm("some token", &mut ())
+            .await
+            .expect("Expected account to be verified");
+    }
+
+    #[tokio::test]
+    async fn test_confirm_expired_token() {
+        let mut accounts = MockAccountIO::new();
+        let mut verifications = MockOneTimeTokenIO::new();
+        verifications
+            .expect_consume()
+            .returning(|_, _| Err(TokenError::Invalid));
+        accounts.expect_update().never();
+
+        assert!(matches!(
+            client(accounts, verifications, MemoryMailSender::new())
+                .confirm("expired token", &mut ())
+                .await,
+            Err(AuthError::Credentials)
+        ));
+    }
+
+    #[test]
+    fn test_is_mail_address() {
+        assert!(is_mail_address("some@mail.org"));
+        assert!(!is_mail_address("some mail"));
+        assert!(!is_mail_address("@mail.org"));
+        assert!(!is_mail_address("some@mail"));
+        assert!(!is_mail_address("some@@mail.org"));
+        assert!(!is_mail_address("so me@mail.org"));
+    }
+}
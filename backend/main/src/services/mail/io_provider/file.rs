@@ -0,0 +1,71 @@
+use crate::services::mail::{Mail, MailError, MailSender};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+/// appends every mail as a json line to a spool file instead of delivering it
+///
+/// stand-in for a real transport in development, another process may deliver the spool
+#[derive(Clone)]
+pub struct FileMailSender {
+    path: PathBuf,
+    // serializes appends of concurrent requests
+    lock: Arc<Mutex<()>>,
+}
+
+impl FileMailSender {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            lock: Arc::new(Mutex::new(())),
+        }
+    }
+}
+
+impl MailSender for FileMailSender {
+    async fn send(&self, mail: &Mail) -> Result<(), MailError> {
+        let Ok(mut line) = serde_json::to_vec(mail) else {
+            return Err(MailError::IO);
+        };
+        line.push(b'\n');
+
+        let _guard = self.lock.lock().await;
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await
+            .or(Err(MailError::IO))?;
+        file.write_all(&line).await.or(Err(MailError::IO))?;
+        file.flush().await.or(Err(MailError::IO))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FileMailSender;
+    use crate::services::mail::{Mail, MailSender};
+
+    #[tokio::test]
+    async fn mails_are_appended_as_json_lines() {
+        let dir = tempfile::tempdir().expect("Expected temp dir");
+        let path = dir.path().join("mail.spool");
+        let sender = FileMailSender::new(&path);
+        let mail = Mail {
+            to: "some mail".to_string(),
+            subject: "subject".to_string(),
+            body: "body".to_string(),
+        };
+
+        sender.send(&mail).await.expect("Expected mail to be sent");
+        sender.send(&mail).await.expect("Expected mail to be sent");
+
+        let spool = std::fs::read_to_string(&path).expect("Expected spool file");
+        let mails: Vec<Mail> = spool
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("Expected mail"))
+            .collect();
+        assert_eq!(mails, vec![mail.clone(), mail]);
+    }
+}
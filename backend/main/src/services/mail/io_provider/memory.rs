@@ -0,0 +1,32 @@
+use crate::services::mail::{Mail, MailError, MailSender};
+use std::sync::{Arc, Mutex};
+
+/// keeps every mail in memory, used by tests to read the mails sent
+#[derive(Clone, Default)]
+pub struct MemoryMailSender {
+    sent: Arc<Mutex<Vec<Mail>>>,
+}
+
+impl MemoryMailSender {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// returns every mail sent so far, oldest first
+    pub fn sent(&self) -> Vec<Mail> {
+        self.sent
+            .lock()
+            .expect("Expected sent mails not to be poisoned")
+            .clone()
+    }
+}
+
+impl MailSender for MemoryMailSender {
+    async fn send(&self, mail: &Mail) -> Result<(), MailError> {
+        self.sent
+            .lock()
+            .expect("Expected sent mails not to be poisoned")
+            .push(mail.clone());
+        Ok(())
+    }
+}
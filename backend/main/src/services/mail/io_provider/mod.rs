@@ -0,0 +1,8 @@
+mod file;
+// stand-in for tests, mails are never delivered
+#[cfg(test)]
+mod memory;
+
+pub use file::*;
+#[cfg(test)]
+pub use memory::*;
@@ -0,0 +1,28 @@
+pub mod io_provider;
+
+#[cfg(test)]
+use mockall::automock;
+use serde::{Deserialize, Serialize};
+
+pub use io_provider::*;
+
+/// an outbound plain text mail
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// delivers outbound mails
+///
+/// implementors choose their own transport, eg. smtp or a spool file
+#[cfg_attr(test, automock)]
+pub trait MailSender {
+    async fn send(&self, mail: &Mail) -> Result<(), MailError>;
+}
+
+#[derive(Debug)]
+pub enum MailError {
+    IO,
+}
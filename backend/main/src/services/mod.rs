@@ -1 +1,3 @@
 pub mod auth;
+pub mod mail;
+pub mod permission;
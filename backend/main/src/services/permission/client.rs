@@ -0,0 +1,44 @@
+use crate::types::Services;
+use axum::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use permission::{Permission, IO};
+use std::convert::Infallible;
+use std::hash::Hash;
+use std::ops::Deref;
+use std::sync::Arc;
+
+/// per request handle to the shared [Permission]
+///
+/// extracting it only clones the shared handle, checks are lock free
+/// and changes are serialized across all handlers
+pub struct PermissionClient<GranteeId, ActionId, Io>(pub Permission<GranteeId, ActionId, Io>)
+where
+    GranteeId: Hash + Eq + Copy + Send + Sync,
+    ActionId: Hash + Eq + Copy + Send + Sync,
+    Io: IO<GranteeId, ActionId>;
+
+impl<GranteeId, ActionId, Io> Deref for PermissionClient<GranteeId, ActionId, Io>
+where
+    GranteeId: Hash + Eq + Copy + Send + Sync,
+    ActionId: Hash + Eq + Copy + Send + Sync,
+    Io: IO<GranteeId, ActionId>,
+{
+    type Target = Permission<GranteeId, ActionId, Io>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<Arc<Services>> for super::Permissions {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        _parts: &mut Parts,
+        state: &Arc<Services>,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(PermissionClient(state.permission.clone()))
+    }
+}
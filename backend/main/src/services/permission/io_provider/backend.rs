@@ -0,0 +1,84 @@
+use super::{FilePermissionIO, PostgresPermissionIO};
+use permission::{Audit, AuditQuery, AuditedBatch, Change, IOError, IO};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sqlx::PgConnection;
+
+/// storage of permissions chosen on startup
+///
+/// small self hosted installs may keep their permissions in a file,
+/// changes are then neither committed with the request nor propagated to other instances
+pub enum PermissionBackend<GranteeId, ActionId> {
+    Postgres(PostgresPermissionIO<GranteeId, ActionId>),
+    File(Box<FilePermissionIO<GranteeId, ActionId>>),
+}
+
+impl<GranteeId, ActionId> IO<GranteeId, ActionId> for PermissionBackend<GranteeId, ActionId>
+where
+    GranteeId: Serialize + DeserializeOwned + PartialEq,
+    ActionId: Serialize + DeserializeOwned + PartialEq,
+{
+    type Ctx = PgConnection;
+
+    async fn read_all(
+        &mut self,
+        ctx: &mut Self::Ctx,
+    ) -> Result<Vec<Change<GranteeId, ActionId>>, IOError> {
+        match self {
+            Self::Postgres(io) => io.read_all(ctx).await,
+            Self::File(io) => io.read_all(&mut ()).await,
+        }
+    }
+
+    async fn write(
+        &mut self,
+        change: &Change<GranteeId, ActionId>,
+        ctx: &mut Self::Ctx,
+    ) -> Result<(), IOError> {
+        match self {
+            Self::Postgres(io) => io.write(change, ctx).await,
+            Self::File(io) => io.write(change, &mut ()).await,
+        }
+    }
+
+    async fn flush(
+        &mut self,
+        audit: &Audit<GranteeId>,
+        inverse: &[Change<GranteeId, ActionId>],
+        ctx: &mut Self::Ctx,
+    ) -> Result<(), IOError> {
+        match self {
+            Self::Postgres(io) => io.flush(audit, inverse, ctx).await,
+            Self::File(io) => io.flush(audit, inverse, &mut ()).await,
+        }
+    }
+
+    async fn clear(&mut self, ctx: &mut Self::Ctx) -> Result<(), IOError> {
+        match self {
+            Self::Postgres(io) => io.clear(ctx).await,
+            Self::File(io) => io.clear(&mut ()).await,
+        }
+    }
+
+    async fn checkpoint(
+        &mut self,
+        snapshot: &[Change<GranteeId, ActionId>],
+        ctx: &mut Self::Ctx,
+    ) -> Result<(), IOError> {
+        match self {
+            Self::Postgres(io) => io.checkpoint(snapshot, ctx).await,
+            Self::File(io) => io.checkpoint(snapshot, &mut ()).await,
+        }
+    }
+
+    async fn history(
+        &mut self,
+        query: &AuditQuery<GranteeId, ActionId>,
+        ctx: &mut Self::Ctx,
+    ) -> Result<Vec<AuditedBatch<GranteeId, ActionId>>, IOError> {
+        match self {
+            Self::Postgres(io) => io.history(query, ctx).await,
+            Self::File(io) => io.history(query, &mut ()).await,
+        }
+    }
+}
@@ -0,0 +1,620 @@
+use super::record::{AuditRecord, ChangeRecord};
+use crc::{Crc, CRC_32_ISO_HDLC};
+use permission::{Audit, AuditQuery, AuditedBatch, Change, IOError, IO};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::io::SeekFrom;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+const CHANGES_MAGIC: &[u8; 4] = b"HYGP";
+const AUDIT_MAGIC: &[u8; 4] = b"HYGA";
+const VERSION: u32 = 1;
+const HEADER_LEN: usize = 8;
+// u32 payload length + u32 checksum
+const FRAME_HEADER_LEN: usize = 8;
+const CHECKSUM: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+/// append-only event log of permission changes stored in a single file
+///
+/// ## format
+/// the file starts with a header of `HYGP` followed by the format version as u32 LE
+/// every [flush](IO::flush) appends one frame holding the whole batch:
+/// - payload length as u32 LE
+/// - CRC-32 of the payload as u32 LE
+/// - payload: json array of changes
+///
+/// a batch is either read completely or not at all
+///
+/// ## audit
+/// audited batches are appended to a sibling file with the extension `audit`
+/// it has the same format but starts with `HYGA` and every payload holds a batch with its audit
+/// the batch is appended to the log first, a crash in between only loses its audit
+///
+/// ## checkpoints
+/// a [checkpoint](IO::checkpoint) writes a new log containing only the snapshot as its first frame
+/// it then atomically replaces the old log by renaming the new one
+/// the audit file is left untouched
+///
+/// ## recovery
+/// a crash while appending leaves an incomplete or corrupt frame at the end of the file
+/// it is truncated when [opening](FilePermissionIO::open) the log
+/// as well as on [clear](IO::clear)
+///
+/// a corrupt frame followed by other frames can not have been left over by a crash,
+/// the log is rejected instead of dropping every batch behind it
+pub struct FilePermissionIO<GranteeId, ActionId> {
+    path: PathBuf,
+    changes: LogFile,
+    // set once a checkpoint moved a new log in place of the one changes still points to
+    stale: bool,
+    audit: LogFile,
+    pending: Vec<ChangeRecord>,
+    ids: PhantomData<(GranteeId, ActionId)>,
+}
+
+impl<GranteeId, ActionId> FilePermissionIO<GranteeId, ActionId> {
+    /// opens the log at path or creates it if it does not exist
+    ///
+    /// truncates an incomplete or corrupt frame at the end of the log and its audit
+    /// returns InvalidData if a frame within them is corrupt
+    pub async fn open(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let changes = LogFile::open(&path, CHANGES_MAGIC).await?;
+        let audit = LogFile::open(&path.with_extension("audit"), AUDIT_MAGIC).await?;
+
+        Ok(Self {
+            path,
+            changes,
+            stale: false,
+            audit,
+            pending: vec![],
+            ids: PhantomData,
+        })
+    }
+
+    /// reopens the changes log if a checkpoint replaced it without being able to do so
+    async fn reopen(&mut self) -> std::io::Result<()> {
+        if self.stale {
+            self.changes = LogFile::open(&self.path, CHANGES_MAGIC).await?;
+            self.stale = false;
+        }
+        Ok(())
+    }
+}
+
+/// a file of checksummed frames following a header
+struct LogFile {
+    file: File,
+    // length of the file up to the end of the last complete frame
+    len: u64,
+}
+
+impl LogFile {
+    /// opens or creates the file and truncates an incomplete or corrupt frame at its end
+    async fn open(path: &Path, magic: &[u8; 4]) -> std::io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .await?;
+
+        let mut buf = vec![];
+        file.read_to_end(&mut buf).await?;
+
+        if buf.is_empty() {
+            file.write_all(&header(magic)).await?;
+            file.flush().await?;
+            file.sync_all().await?;
+            buf.extend_from_slice(&header(magic));
+        }
+
+        if buf.len() < HEADER_LEN || &buf[..4] != magic {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "not a permission log",
+            ));
+        }
+        if buf[4..HEADER_LEN] != VERSION.to_le_bytes() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "unsupported permission log version",
+            ));
+        }
+
+        let Some((_, len)) = frames(&buf) else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "corrupt frame within permission log",
+            ));
+        };
+        if len < buf.len() {
+            // drop whatever has been left over by an interrupted write
+            file.set_len(len as u64).await?;
+            file.sync_all().await?;
+        }
+
+        Ok(Self {
+            file,
+            len: len as u64,
+        })
+    }
+
+    /// reads the file up to the end of the last complete frame
+    async fn read(&mut self) -> std::io::Result<Vec<u8>> {
+        let mut buf = vec![0; self.len as usize];
+        self.file.seek(SeekFrom::Start(0)).await?;
+        self.file.read_exact(&mut buf).await?;
+        Ok(buf)
+    }
+
+    /// writes a frame behind the last complete frame
+    ///
+    /// the frame only counts as complete once [commit](Self::commit) has been called
+    async fn append(&mut self, frame: &[u8]) -> std::io::Result<()> {
+        self.file.seek(SeekFrom::Start(self.len)).await?;
+        self.file.write_all(frame).await?;
+        self.file.flush().await?;
+        self.file.sync_data().await
+    }
+
+    fn commit(&mut self, frame: &[u8]) {
+        self.len += frame.len() as u64;
+    }
+
+    /// drops everything behind the last complete frame
+    async fn truncate(&mut self) -> std::io::Result<()> {
+        self.file.set_len(self.len).await?;
+        self.file.sync_data().await
+    }
+}
+
+fn header(magic: &[u8; 4]) -> [u8; HEADER_LEN] {
+    let mut header = [0; HEADER_LEN];
+    header[..4].copy_from_slice(magic);
+    header[4..].copy_from_slice(&VERSION.to_le_bytes());
+    header
+}
+
+/// encodes a payload into a single frame
+fn frame(payload: &impl Serialize) -> Option<Vec<u8>> {
+    let payload = serde_json::to_vec(payload).ok()?;
+    let len = u32::try_from(payload.len()).ok()?;
+
+    let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + payload.len());
+    frame.extend_from_slice(&len.to_le_bytes());
+    frame.extend_from_slice(&CHECKSUM.checksum(&payload).to_le_bytes());
+    frame.extend_from_slice(&payload);
+    Some(frame)
+}
+
+/// splits the log into the payloads of all complete frames
+///
+/// returns the payloads and the length of the log up to the end of the last valid frame
+/// only the last frame may be incomplete or corrupt, returns None if others follow a corrupt one
+fn frames(buf: &[u8]) -> Option<(Vec<&[u8]>, usize)> {
+    let mut payloads = vec![];
+    let mut offset = HEADER_LEN;
+
+    while offset + FRAME_HEADER_LEN <= buf.len() {
+        let len = u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap()) as usize;
+        let checksum = u32::from_le_bytes(buf[offset + 4..offset + 8].try_into().unwrap());
+
+        let start = offset + FRAME_HEADER_LEN;
+        let Some(payload) = buf.get(start..start + len) else {
+            // incomplete frame
+            break;
+        };
+        if CHECKSUM.checksum(payload) != checksum {
+            if start + len < buf.len() {
+                // corrupt frame within the log
+                return None;
+            }
+            // the last frame might have been written partially
+            break;
+        }
+
+        payloads.push(payload);
+        offset = start + len;
+    }
+
+    Some((payloads, offset))
+}
+
+impl<GranteeId, ActionId> IO<GranteeId, ActionId> for FilePermissionIO<GranteeId, ActionId>
+where
+    GranteeId: Serialize + DeserializeOwned + PartialEq,
+    ActionId: Serialize + DeserializeOwned + PartialEq,
+{
+    type Ctx = ();
+
+    async fn read_all(
+        &mut self,
+        _ctx: &mut Self::Ctx,
+    ) -> Result<Vec<Change<GranteeId, ActionId>>, IOError> {
+        self.reopen().await.or(Err(IOError::Read))?;
+        let buf = self.changes.read().await.or(Err(IOError::Read))?;
+
+        let mut changes = vec![];
+        for payload in frames(&buf).ok_or(IOError::Read)?.0 {
+            let records: Vec<ChangeRecord> =
+                serde_json::from_slice(payload).or(Err(IOError::Read))?;
+            for record in records {
+                changes.push(record.try_into()?);
+            }
+        }
+        Ok(changes)
+    }
+
+    async fn write(
+        &mut self,
+        change: &Change<GranteeId, ActionId>,
+        _ctx: &mut Self::Ctx,
+    ) -> Result<(), IOError> {
+        self.pending
+            .push(ChangeRecord::try_from(change).or(Err(IOError::Write))?);
+        Ok(())
+    }
+
+    async fn flush(
+        &mut self,
+        audit: &Audit<GranteeId>,
+        inverse: &[Change<GranteeId, ActionId>],
+        _ctx: &mut Self::Ctx,
+    ) -> Result<(), IOError> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let inverse = inverse
+            .iter()
+            .map(ChangeRecord::try_from)
+            .collect::<Result<Vec<_>, _>>()
+            .or(Err(IOError::Flush))?;
+
+        let Some(changes_frame) = frame(&self.pending) else {
+            return Err(IOError::Flush);
+        };
+        let Some(audit_frame) = AuditRecord::new(audit, self.pending.clone(), inverse)
+            .ok()
+            .and_then(|record| frame(&record))
+        else {
+            return Err(IOError::Flush);
+        };
+
+        self.reopen().await.or(Err(IOError::Flush))?;
+        // a failed append leaves frames behind the committed length, clear truncates them
+        self.changes
+            .append(&changes_frame)
+            .await
+            .or(Err(IOError::Flush))?;
+        self.audit
+            .append(&audit_frame)
+            .await
+            .or(Err(IOError::Flush))?;
+
+        self.changes.commit(&changes_frame);
+        self.audit.commit(&audit_frame);
+        self.pending.clear();
+        Ok(())
+    }
+
+    async fn clear(&mut self, _ctx: &mut Self::Ctx) -> Result<(), IOError> {
+        self.pending.clear();
+        self.reopen().await.or(Err(IOError::Clear))?;
+
+        // a failed flush might have left partial frames behind
+        self.changes.truncate().await.or(Err(IOError::Clear))?;
+        self.audit.truncate().await.or(Err(IOError::Clear))
+    }
+
+    async fn checkpoint(
+        &mut self,
+        snapshot: &[Change<GranteeId, ActionId>],
+        _ctx: &mut Self::Ctx,
+    ) -> Result<(), IOError> {
+        let records = snapshot
+            .iter()
+            .map(ChangeRecord::try_from)
+            .collect::<Result<Vec<_>, _>>()
+            .or(Err(IOError::Checkpoint))?;
+
+        let mut log = header(CHANGES_MAGIC).to_vec();
+        if !records.is_empty() {
+            log.extend(frame(&records).ok_or(IOError::Checkpoint)?);
+        }
+
+        let checkpoint_path = self.path.with_extension("checkpoint");
+        let replaced: std::io::Result<()> = async {
+            let mut file = File::create(&checkpoint_path).await?;
+            file.write_all(&log).await?;
+            file.flush().await?;
+            file.sync_all().await?;
+
+            tokio::fs::rename(&checkpoint_path, &self.path).await
+        }
+        .await;
+        // the old log stays untouched if anything fails before the rename
+        replaced.or(Err(IOError::Checkpoint))?;
+
+        // changes still points to the unlinked old log until it has been reopened
+        self.stale = true;
+        // persist the rename itself
+        let synced: std::io::Result<()> = async {
+            if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                File::open(dir).await?.sync_all().await?;
+            }
+            Ok(())
+        }
+        .await;
+
+        self.reopen().await.or(Err(IOError::Checkpoint))?;
+        synced.or(Err(IOError::Checkpoint))
+    }
+
+    async fn history(
+        &mut self,
+        query: &AuditQuery<GranteeId, ActionId>,
+        _ctx: &mut Self::Ctx,
+    ) -> Result<Vec<AuditedBatch<GranteeId, ActionId>>, IOError> {
+        let buf = self.audit.read().await.or(Err(IOError::History))?;
+
+        let mut batches = vec![];
+        for payload in frames(&buf).ok_or(IOError::History)?.0 {
+            let record: AuditRecord = serde_json::from_slice(payload).or(Err(IOError::History))?;
+            let batch: AuditedBatch<GranteeId, ActionId> = record.try_into()?;
+            if batch.changes.iter().any(|change| query.matches(change)) {
+                batches.push(batch);
+            }
+        }
+        Ok(batches)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FilePermissionIO, FRAME_HEADER_LEN, HEADER_LEN};
+    use permission::{Audit, AuditQuery, Change, IO};
+    use std::fs::OpenOptions;
+    use std::io::Write;
+
+    type Log = FilePermissionIO<u32, u32>;
+
+    async fn read_all(log: &mut Log) -> Vec<String> {
+        log.read_all(&mut ())
+            .await
+            .unwrap()
+            .iter()
+            .map(|change| format!("{change:?}"))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn batches_are_read_after_reopening() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("permission.log");
+
+        let mut log = Log::open(&path).await.unwrap();
+        log.write(&Change::AddGrant(1, 2), &mut ()).await.unwrap();
+        log.write(&Change::ConnectGrantees(1, 3), &mut ())
+            .await
+            .unwrap();
+        log.flush(&Audit::system(), &[], &mut ()).await.unwrap();
+        log.write(&Change::RemoveRoot(3), &mut ()).await.unwrap();
+        log.flush(&Audit::system(), &[], &mut ()).await.unwrap();
+        drop(log);
+
+        let mut log = Log::open(&path).await.unwrap();
+        assert_eq!(
+            vec!["AddGrant(1, 2)", "ConnectGrantees(1, 3)", "RemoveRoot(3)"],
+            read_all(&mut log).await
+        );
+    }
+
+    #[tokio::test]
+    async fn cleared_batches_are_not_persisted() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("permission.log");
+
+        let mut log = Log::open(&path).await.unwrap();
+        log.write(&Change::AddGrant(1, 2), &mut ()).await.unwrap();
+        log.clear(&mut ()).await.unwrap();
+        log.flush(&Audit::system(), &[], &mut ()).await.unwrap();
+
+        assert!(read_all(&mut log).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn partial_tails_are_truncated_on_open() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("permission.log");
+
+        let mut log = Log::open(&path).await.unwrap();
+        log.write(&Change::AddGrant(1, 2), &mut ()).await.unwrap();
+        log.flush(&Audit::system(), &[], &mut ()).await.unwrap();
+        let len = log.changes.len;
+        drop(log);
+
+        // simulate a crash in the middle of appending a frame
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[200, 0, 0, 0, 1, 2, 3, 4, b'[']).unwrap();
+        drop(file);
+
+        let mut log = Log::open(&path).await.unwrap();
+        assert_eq!(len, log.changes.len);
+        assert_eq!(len, std::fs::metadata(&path).unwrap().len());
+        assert_eq!(vec!["AddGrant(1, 2)"], read_all(&mut log).await);
+
+        // appending after recovery works as usual
+        log.write(&Change::AddRoot(1), &mut ()).await.unwrap();
+        log.flush(&Audit::system(), &[], &mut ()).await.unwrap();
+        drop(log);
+        let mut log = Log::open(&path).await.unwrap();
+        assert_eq!(
+            vec!["AddGrant(1, 2)", "AddRoot(1)"],
+            read_all(&mut log).await
+        );
+    }
+
+    #[tokio::test]
+    async fn corrupt_tails_are_truncated_on_open() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("permission.log");
+
+        let mut log = Log::open(&path).await.unwrap();
+        log.write(&Change::AddGrant(1, 2), &mut ()).await.unwrap();
+        log.flush(&Audit::system(), &[], &mut ()).await.unwrap();
+        drop(log);
+
+        // flip a byte inside the payload of the last frame
+        let mut buf = std::fs::read(&path).unwrap();
+        let last = buf.len() - 2;
+        buf[last] ^= 0xff;
+        std::fs::write(&path, buf).unwrap();
+
+        let mut log = Log::open(&path).await.unwrap();
+        assert_eq!(HEADER_LEN as u64, log.changes.len);
+        assert!(read_all(&mut log).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn corrupt_frames_within_the_log_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("permission.log");
+
+        let mut log = Log::open(&path).await.unwrap();
+        log.write(&Change::AddGrant(1, 2), &mut ()).await.unwrap();
+        log.flush(&Audit::system(), &[], &mut ()).await.unwrap();
+        log.write(&Change::AddGrant(3, 4), &mut ()).await.unwrap();
+        log.flush(&Audit::system(), &[], &mut ()).await.unwrap();
+        drop(log);
+
+        // flip a byte inside the payload of the first frame
+        let mut buf = std::fs::read(&path).unwrap();
+        buf[HEADER_LEN + FRAME_HEADER_LEN + 1] ^= 0xff;
+        std::fs::write(&path, &buf).unwrap();
+
+        let error = Log::open(&path).await.err().expect("Expected corrupt log");
+        assert_eq!(std::io::ErrorKind::InvalidData, error.kind());
+        // later batches are kept for manual recovery
+        assert_eq!(buf, std::fs::read(&path).unwrap());
+    }
+
+    #[tokio::test]
+    async fn checkpoints_replace_the_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("permission.log");
+
+        let mut log = Log::open(&path).await.unwrap();
+        log.write(&Change::AddGrant(1, 2), &mut ()).await.unwrap();
+        log.write(&Change::RemoveGrant(1, 2), &mut ())
+            .await
+            .unwrap();
+        log.write(&Change::AddGrant(1, 3), &mut ()).await.unwrap();
+        log.flush(&Audit::system(), &[], &mut ()).await.unwrap();
+
+        log.checkpoint(&[Change::AddGrant(1, 3)], &mut ())
+            .await
+            .unwrap();
+        log.write(&Change::AddRoot(1), &mut ()).await.unwrap();
+        log.flush(&Audit::system(), &[], &mut ()).await.unwrap();
+        drop(log);
+
+        // snapshot first, then the tail
+        let mut log = Log::open(&path).await.unwrap();
+        assert_eq!(
+            vec!["AddGrant(1, 3)", "AddRoot(1)"],
+            read_all(&mut log).await
+        );
+        assert!(!path.with_extension("checkpoint").exists());
+    }
+
+    #[tokio::test]
+    async fn stale_logs_are_reopened_before_writing() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("permission.log");
+
+        let mut log = Log::open(&path).await.unwrap();
+        log.write(&Change::AddGrant(1, 2), &mut ()).await.unwrap();
+        log.flush(&Audit::system(), &[], &mut ()).await.unwrap();
+        log.checkpoint(&[Change::AddGrant(1, 2)], &mut ())
+            .await
+            .unwrap();
+
+        // simulate a checkpoint that renamed the log but failed to reopen it
+        let unlinked = dir.path().join("unlinked.log");
+        log.changes = super::LogFile::open(&unlinked, super::CHANGES_MAGIC)
+            .await
+            .unwrap();
+        std::fs::remove_file(&unlinked).unwrap();
+        log.stale = true;
+
+        log.write(&Change::AddRoot(1), &mut ()).await.unwrap();
+        log.flush(&Audit::system(), &[], &mut ()).await.unwrap();
+        drop(log);
+
+        let mut log = Log::open(&path).await.unwrap();
+        assert_eq!(
+            vec!["AddGrant(1, 2)", "AddRoot(1)"],
+            read_all(&mut log).await
+        );
+    }
+
+    #[tokio::test]
+    async fn unknown_versions_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("permission.log");
+        std::fs::write(&path, b"HYGP\x02\x00\x00\x00").unwrap();
+
+        assert!(Log::open(&path).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn audits_are_kept_on_checkpoint() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("permission.log");
+
+        let mut log = Log::open(&path).await.unwrap();
+        log.write(&Change::AddGrant(1, 2), &mut ()).await.unwrap();
+        log.flush(&Audit::new(9).with_reason("elected"), &[], &mut ())
+            .await
+            .unwrap();
+        log.write(&Change::AddGrant(3, 4), &mut ()).await.unwrap();
+        log.flush(&Audit::system(), &[], &mut ()).await.unwrap();
+        log.checkpoint(&[Change::AddGrant(1, 2)], &mut ())
+            .await
+            .unwrap();
+        drop(log);
+
+        let mut log = Log::open(&path).await.unwrap();
+        let history = log.history(&AuditQuery::Grantee(1), &mut ()).await.unwrap();
+        assert_eq!(1, history.len());
+        assert_eq!(Some(9), history[0].audit.actor);
+        assert_eq!(Some("elected"), history[0].audit.reason.as_deref());
+        assert!(log
+            .history(&AuditQuery::Action(5), &mut ())
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn cleared_batches_are_not_audited() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("permission.log");
+
+        let mut log = Log::open(&path).await.unwrap();
+        log.write(&Change::AddGrant(1, 2), &mut ()).await.unwrap();
+        log.clear(&mut ()).await.unwrap();
+        log.flush(&Audit::system(), &[], &mut ()).await.unwrap();
+
+        assert!(log
+            .history(&AuditQuery::Grantee(1), &mut ())
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,385 @@
+use super::postgres::{current_version, ChangeNotification, Origin, CHANNEL};
+use super::record::ChangeRecord;
+use super::PermissionBackend;
+use permission::{Change, Permission, PermissionError, IO};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sqlx::postgres::PgListener;
+use sqlx::PgPool;
+use std::hash::Hash;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+#[derive(Error, Debug)]
+enum ListenerError {
+    #[error("Error while talking to postgres\n\t{0}")]
+    Postgres(#[from] sqlx::Error),
+    #[error("Error while rebuilding permissions\n\t{0}")]
+    Permission(#[from] PermissionError),
+}
+
+/// what to do with an incoming notification
+enum Step {
+    /// already part of the local state
+    Ignore,
+    /// flushed by the local IO and thus already applied
+    Advance,
+    /// committed by another instance right after the local state
+    Apply(Vec<ChangeRecord>),
+    /// batches have been missed or applied out of order, the local state has to be rebuilt
+    Resync,
+}
+
+/// keeps a [Permission] in sync with the batches committed by other instances
+///
+/// batches are announced by [PostgresPermissionIO](super::PostgresPermissionIO) with a gap free version
+/// the next version is applied, a gap, a lost connection or a batch too large
+/// to be announced triggers a full resync from the log
+///
+/// a batch of another instance committed before one already flushed locally
+/// would be applied after it, a resync restores the committed order instead
+/// a resync is also requested by a refused [checkpoint](permission::Permission::checkpoint)
+pub struct PostgresPermissionListener<GranteeId, ActionId>
+where
+    GranteeId: Hash + Eq + Copy + Send + Sync + Serialize + DeserializeOwned,
+    ActionId: Hash + Eq + Copy + Send + Sync + Serialize + DeserializeOwned,
+{
+    permission: Permission<GranteeId, ActionId, PermissionBackend<GranteeId, ActionId>>,
+    pool: PgPool,
+    origin: Origin,
+}
+
+impl<GranteeId, ActionId> PostgresPermissionListener<GranteeId, ActionId>
+where
+    GranteeId: Hash + Eq + Copy + Send + Sync + Serialize + DeserializeOwned + 'static,
+    ActionId: Hash + Eq + Copy + Send + Sync + Serialize + DeserializeOwned + 'static,
+{
+    /// origin has to be the one of the IO used by permission
+    pub fn new(
+        permission: Permission<GranteeId, ActionId, PermissionBackend<GranteeId, ActionId>>,
+        pool: PgPool,
+        origin: Origin,
+    ) -> Self {
+        Self {
+            permission,
+            pool,
+            origin,
+        }
+    }
+
+    /// listens in a background task until it is aborted
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(self.run())
+    }
+
+    async fn run(mut self) {
+        let mut delay = RETRY_DELAY;
+        let mut listener = loop {
+            match self.listen().await {
+                Ok(listener) => break listener,
+                Err(error) => {
+                    tracing::warn!("failed to listen for permission changes: {error}");
+                    tokio::time::sleep(delay).await;
+                    delay = (delay * 2).min(MAX_RETRY_DELAY);
+                }
+            }
+        };
+        // batches committed before listening are only part of the log
+        self.resync().await;
+
+        let origin = self.origin.clone();
+        loop {
+            tokio::select! {
+                received = listener.try_recv() => match received {
+                    Ok(Some(notification)) => self.handle(notification.payload()).await,
+                    // the connection was lost and is reestablished on the next call
+                    Ok(None) => self.resync().await,
+                    Err(error) => {
+                        tracing::warn!("failed to receive permission changes: {error}");
+                        tokio::time::sleep(RETRY_DELAY).await;
+                        self.resync().await;
+                    }
+                },
+                _ = origin.resync_requested() => self.resync().await,
+            }
+        }
+    }
+
+    async fn listen(&self) -> Result<PgListener, sqlx::Error> {
+        let mut listener = PgListener::connect_with(&self.pool).await?;
+        listener.listen(CHANNEL).await?;
+        Ok(listener)
+    }
+
+    async fn handle(&mut self, payload: &str) {
+        let (version, step) = receive(&self.origin, payload);
+        match step {
+            Step::Ignore => {}
+            Step::Advance => self.origin.set_synced(version),
+            Step::Apply(records) => {
+                if !apply_next(&self.permission, &self.origin, version, records).await {
+                    self.resync().await;
+                }
+            }
+            Step::Resync => self.resync().await,
+        }
+    }
+
+    /// rebuilds the permission from the log until it succeeds
+    ///
+    /// also recovers a failed permission
+    async fn resync(&mut self) {
+        let mut delay = RETRY_DELAY;
+        while let Err(error) = self.try_resync().await {
+            tracing::warn!("failed to resync permissions: {error}");
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(MAX_RETRY_DELAY);
+        }
+    }
+
+    async fn try_resync(&mut self) -> Result<(), ListenerError> {
+        let mut tx = self.pool.begin().await?;
+        // the snapshot is taken by the first query, which recover runs while holding the IO,
+        // so the version matches the rebuilt state
+        sqlx::query(r#"SET TRANSACTION ISOLATION LEVEL REPEATABLE READ"#)
+            .execute(&mut *tx)
+            .await?;
+        self.permission.recover(&mut tx).await?;
+        let version = current_version(&mut tx).await?;
+        tx.commit().await?;
+
+        self.origin.set_synced(version);
+        Ok(())
+    }
+}
+
+/// decides on a notification payload given the sync state shared with the IO
+fn receive(origin: &Origin, payload: &str) -> (i64, Step) {
+    let Ok(notification) = serde_json::from_str::<ChangeNotification>(payload) else {
+        return (origin.synced(), Step::Resync);
+    };
+    let version = notification.version;
+    let step = step(
+        origin.synced(),
+        origin.flushed(),
+        origin.current(),
+        notification,
+    );
+    (version, step)
+}
+
+/// applies the batch of another instance with the given version
+///
+/// a local batch might have been flushed since the notification was received,
+/// thus whether it still follows the local state is checked again while holding the IO
+/// returns false if it has not been applied and a resync is needed
+async fn apply_next<GranteeId, ActionId, Io>(
+    permission: &Permission<GranteeId, ActionId, Io>,
+    origin: &Origin,
+    version: i64,
+    records: Vec<ChangeRecord>,
+) -> bool
+where
+    GranteeId: Hash + Eq + Copy + Send + Sync + DeserializeOwned,
+    ActionId: Hash + Eq + Copy + Send + Sync + DeserializeOwned,
+    Io: IO<GranteeId, ActionId>,
+{
+    let Ok(changes) = records
+        .into_iter()
+        .map(Change::try_from)
+        .collect::<Result<Vec<_>, _>>()
+    else {
+        return false;
+    };
+    let next = || origin.synced() == version - 1 && origin.flushed() < version;
+    match permission.apply_if(changes, next).await {
+        Ok(Some(_)) => {
+            origin.set_synced(version);
+            true
+        }
+        _ => false,
+    }
+}
+
+fn step(version: i64, flushed: i64, origin: Uuid, notification: ChangeNotification) -> Step {
+    if notification.version <= version {
+        return Step::Ignore;
+    }
+    if notification.version > version + 1 {
+        return Step::Resync;
+    }
+    if notification.origin == origin {
+        return Step::Advance;
+    }
+    // a later local batch has already been applied
+    if notification.version <= flushed {
+        return Step::Resync;
+    }
+    match notification.changes {
+        Some(records) => Step::Apply(records),
+        None => Step::Resync,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply_next, receive, step, Step};
+    use crate::services::permission::io_provider::postgres::{ChangeNotification, Origin};
+    use crate::services::permission::io_provider::record::ChangeRecord;
+    use permission::{Audit, Change, MemoryIO, Permission};
+    use uuid::Uuid;
+
+    fn notification(origin: Uuid, version: i64, changes: bool) -> ChangeNotification {
+        let change: Change<u32, u32> = Change::AddGrant(1, 2);
+        ChangeNotification {
+            origin,
+            version,
+            changes: changes
+                .then(|| vec![ChangeRecord::try_from(&change).expect("Expected record")]),
+        }
+    }
+
+    #[test]
+    fn next_batch_of_another_instance_is_applied() {
+        let origin = Uuid::new_v4();
+        let step = step(4, 0, origin, notification(Uuid::new_v4(), 5, true));
+        assert!(matches!(step, Step::Apply(records) if records.len() == 1));
+    }
+
+    #[test]
+    fn own_batches_only_advance_the_version() {
+        let origin = Uuid::new_v4();
+        assert!(matches!(
+            step(4, 0, origin, notification(origin, 5, true)),
+            Step::Advance
+        ));
+    }
+
+    #[test]
+    fn known_batches_are_ignored() {
+        let origin = Uuid::new_v4();
+        assert!(matches!(
+            step(5, 0, origin, notification(Uuid::new_v4(), 5, true)),
+            Step::Ignore
+        ));
+        assert!(matches!(
+            step(5, 0, origin, notification(Uuid::new_v4(), 3, true)),
+            Step::Ignore
+        ));
+    }
+
+    #[test]
+    fn gaps_trigger_a_resync() {
+        let origin = Uuid::new_v4();
+        assert!(matches!(
+            step(4, 0, origin, notification(Uuid::new_v4(), 6, true)),
+            Step::Resync
+        ));
+        // own batches can not close a gap either
+        assert!(matches!(
+            step(4, 0, origin, notification(origin, 6, true)),
+            Step::Resync
+        ));
+    }
+
+    #[test]
+    fn batches_without_changes_trigger_a_resync() {
+        let origin = Uuid::new_v4();
+        assert!(matches!(
+            step(4, 0, origin, notification(Uuid::new_v4(), 5, false)),
+            Step::Resync
+        ));
+    }
+
+    #[test]
+    fn batches_committed_before_local_ones_trigger_a_resync() {
+        let origin = Uuid::new_v4();
+        // version 6 has been flushed locally while 5 of another instance was in flight
+        assert!(matches!(
+            step(4, 6, origin, notification(Uuid::new_v4(), 5, true)),
+            Step::Resync
+        ));
+        assert!(matches!(
+            step(4, 4, origin, notification(Uuid::new_v4(), 5, true)),
+            Step::Apply(_)
+        ));
+    }
+
+    #[test]
+    fn payloads_are_decided_on_the_shared_origin() {
+        let origin = Origin::default();
+        origin.set_synced(4);
+        let peer = |version| {
+            serde_json::to_string(&notification(Uuid::new_v4(), version, true))
+                .expect("Expected payload")
+        };
+
+        assert!(matches!(receive(&origin, &peer(5)), (5, Step::Apply(_))));
+        let own = serde_json::to_string(&notification(origin.current(), 5, true))
+            .expect("Expected payload");
+        assert!(matches!(receive(&origin, &own), (5, Step::Advance)));
+        assert!(matches!(receive(&origin, "{"), (_, Step::Resync)));
+
+        // a local batch flushed meanwhile overtook the one of the other instance
+        origin.record_flush(6);
+        assert!(matches!(receive(&origin, &peer(5)), (_, Step::Resync)));
+
+        origin.set_synced(5);
+        assert!(matches!(receive(&origin, &peer(5)), (_, Step::Ignore)));
+    }
+
+    #[tokio::test]
+    async fn batches_are_not_applied_behind_local_ones_flushed_meanwhile() {
+        let permission: Permission<u32, u32, MemoryIO<u32, u32>> =
+            Permission::new(MemoryIO::default(), &mut ())
+                .await
+                .expect("Expected Permission");
+        let origin = Origin::default();
+        origin.set_synced(4);
+        let Step::Apply(records) = step(
+            origin.synced(),
+            origin.flushed(),
+            origin.current(),
+            notification(Uuid::new_v4(), 5, true),
+        ) else {
+            panic!("Expected the batch of the other instance to be applied");
+        };
+
+        // a local batch is flushed as version 5 before the notification is applied
+        let pending = permission
+            .prepare(vec![Change::AddGrant(3, 4)], &Audit::system(), &mut ())
+            .await
+            .expect("Expected change to be persisted");
+        let (applied, _) = tokio::join!(apply_next(&permission, &origin, 5, records), async {
+            origin.record_flush(5);
+            pending.publish()
+        });
+
+        assert!(!applied);
+        assert_eq!(4, origin.synced());
+        assert!(matches!(permission.check(&3, &4), Ok(true)));
+        assert!(permission.check(&1, &2).is_err());
+    }
+
+    #[tokio::test]
+    async fn next_batches_are_applied() {
+        let permission: Permission<u32, u32, MemoryIO<u32, u32>> =
+            Permission::new(MemoryIO::default(), &mut ())
+                .await
+                .expect("Expected Permission");
+        let origin = Origin::default();
+        origin.set_synced(4);
+        let records = notification(Uuid::new_v4(), 5, true)
+            .changes
+            .expect("Expected changes");
+
+        assert!(apply_next(&permission, &origin, 5, records).await);
+        assert_eq!(5, origin.synced());
+        assert!(matches!(permission.check(&1, &2), Ok(true)));
+    }
+}
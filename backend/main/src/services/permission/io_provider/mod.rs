@@ -0,0 +1,10 @@
+mod backend;
+mod file;
+mod listener;
+mod postgres;
+mod record;
+
+pub use backend::*;
+pub use file::*;
+pub use listener::*;
+pub use postgres::*;
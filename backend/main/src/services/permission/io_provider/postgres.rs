@@ -0,0 +1,455 @@
+use super::record::{AuditRecord, ChangeRecord};
+use permission::{Audit, AuditQuery, AuditedBatch, Change, IOError, IO};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::types::time::OffsetDateTime;
+use sqlx::types::Json;
+use sqlx::{Connection, PgConnection};
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+/// postgres channel every committed batch is announced on
+pub(super) const CHANNEL: &str = "permission_change";
+
+/// postgres rejects notification payloads of 8000 bytes or more
+const MAX_PAYLOAD_LEN: usize = 7999;
+
+/// announcement of a committed batch
+///
+/// changes are omitted if they do not fit into a notification,
+/// listeners then have to resync from the log
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct ChangeNotification {
+    pub(super) origin: Uuid,
+    pub(super) version: i64,
+    pub(super) changes: Option<Vec<ChangeRecord>>,
+}
+
+/// event log of permission changes stored in postgres
+///
+/// changes are buffered on [write](IO::write) and inserted in a single transaction on [flush](IO::flush)
+/// together with their audit, which is kept in permission_audit
+/// [clear](IO::clear) discards all buffered changes
+/// [checkpoint](IO::checkpoint) replaces the permission_snapshot table and truncates the log,
+/// it is refused while batches of other instances are committed but not yet applied
+///
+/// if the given connection is already inside a transaction, eg. the one of the current request,
+/// a savepoint is used instead and the changes are committed together with the outer transaction,
+/// use [Permission::prepare](permission::Permission::prepare) to apply them only after that commit
+///
+/// every flushed batch increments permission_version and is announced on [CHANNEL]
+/// once committed, see [PostgresPermissionListener](super::PostgresPermissionListener)
+#[derive(Clone)]
+pub struct PostgresPermissionIO<GranteeId, ActionId> {
+    origin: Origin,
+    pending: Vec<ChangeRecord>,
+    ids: PhantomData<(GranteeId, ActionId)>,
+}
+
+impl<GranteeId, ActionId> PostgresPermissionIO<GranteeId, ActionId> {
+    pub fn new() -> Self {
+        Self {
+            origin: Origin::default(),
+            pending: vec![],
+            ids: PhantomData,
+        }
+    }
+
+    /// identifies the batches announced by this IO
+    pub fn origin(&self) -> Origin {
+        self.origin.clone()
+    }
+}
+
+impl<GranteeId, ActionId> Default for PostgresPermissionIO<GranteeId, ActionId> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// sync state shared by a PostgresPermissionIO and its listener
+///
+/// the id marks the batches flushed since the last read_all,
+/// batches flushed before a read_all are not part of a state rebuilt from it,
+/// renewing the id lets listeners apply them once they are committed
+///
+/// synced is the version up to which every batch is part of the local state,
+/// flushed the highest version flushed by the IO, it may have been rolled back
+#[derive(Clone, Debug)]
+pub struct Origin(Arc<OriginState>);
+
+#[derive(Debug)]
+struct OriginState {
+    id: Mutex<Uuid>,
+    synced: AtomicI64,
+    flushed: AtomicI64,
+    resync: Notify,
+}
+
+impl Origin {
+    pub fn current(&self) -> Uuid {
+        *self.0.id.lock().expect("Expected to lock origin")
+    }
+
+    fn renew(&self) {
+        *self.0.id.lock().expect("Expected to lock origin") = Uuid::new_v4();
+    }
+
+    pub(super) fn synced(&self) -> i64 {
+        self.0.synced.load(Ordering::SeqCst)
+    }
+
+    pub(super) fn set_synced(&self, version: i64) {
+        self.0.synced.store(version, Ordering::SeqCst);
+    }
+
+    pub(super) fn flushed(&self) -> i64 {
+        self.0.flushed.load(Ordering::SeqCst)
+    }
+
+    pub(super) fn record_flush(&self, version: i64) {
+        self.0.flushed.fetch_max(version, Ordering::SeqCst);
+    }
+
+    /// a checkpoint of the local state may only replace the log up to the synced version,
+    /// if a later batch has been committed the listener is asked to resync instead
+    fn can_checkpoint(&self, committed: i64) -> bool {
+        if committed > self.synced() {
+            self.0.resync.notify_one();
+            return false;
+        }
+        true
+    }
+
+    /// completes once a resync has been requested, a request made before waiting is kept
+    pub(super) async fn resync_requested(&self) {
+        self.0.resync.notified().await
+    }
+}
+
+impl Default for Origin {
+    fn default() -> Self {
+        Self(Arc::new(OriginState {
+            id: Mutex::new(Uuid::new_v4()),
+            synced: AtomicI64::new(0),
+            flushed: AtomicI64::new(0),
+            resync: Notify::new(),
+        }))
+    }
+}
+
+impl<GranteeId, ActionId> IO<GranteeId, ActionId> for PostgresPermissionIO<GranteeId, ActionId>
+where
+    GranteeId: Serialize + DeserializeOwned,
+    ActionId: Serialize + DeserializeOwned,
+{
+    type Ctx = PgConnection;
+
+    async fn read_all(
+        &mut self,
+        ctx: &mut Self::Ctx,
+    ) -> Result<Vec<Change<GranteeId, ActionId>>, IOError> {
+        self.origin.renew();
+
+        // snapshot first, then every change written after the last checkpoint
+        sqlx::query_as::<_, ChangeRecord>(
+            r#"SELECT kind, grantee, grantee_of, action, sub_action FROM (
+                SELECT 0 as source, seq, kind, grantee, grantee_of, action, sub_action FROM public.permission_snapshot
+                UNION ALL
+                SELECT 1 as source, seq, kind, grantee, grantee_of, action, sub_action FROM public.permission_change
+            ) changes ORDER BY source, seq"#,
+        )
+        .fetch_all(ctx)
+        .await
+        .or(Err(IOError::Read))?
+        .into_iter()
+        .map(Change::try_from)
+        .collect()
+    }
+
+    async fn write(
+        &mut self,
+        change: &Change<GranteeId, ActionId>,
+        _ctx: &mut Self::Ctx,
+    ) -> Result<(), IOError> {
+        self.pending
+            .push(ChangeRecord::try_from(change).or(Err(IOError::Write))?);
+        Ok(())
+    }
+
+    async fn flush(
+        &mut self,
+        audit: &Audit<GranteeId>,
+        inverse: &[Change<GranteeId, ActionId>],
+        ctx: &mut Self::Ctx,
+    ) -> Result<(), IOError> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let inverse = inverse
+            .iter()
+            .map(ChangeRecord::try_from)
+            .collect::<Result<Vec<_>, _>>()
+            .or(Err(IOError::Flush))?;
+        let audit =
+            AuditRecord::new(audit, self.pending.clone(), inverse).or(Err(IOError::Flush))?;
+
+        let result: Result<i64, sqlx::Error> = async {
+            let mut tx = ctx.begin().await?;
+            let version = increment_version(&mut tx).await?;
+            insert_changes(&mut tx, version, &self.pending).await?;
+            insert_audit(&mut tx, &audit).await?;
+            notify(&mut tx, self.origin.current(), version, &self.pending).await?;
+            tx.commit().await?;
+            Ok(version)
+        }
+        .await;
+        let version = result.or(Err(IOError::Flush))?;
+        // applied locally before any batch of another instance still in flight
+        self.origin.record_flush(version);
+
+        self.pending.clear();
+        Ok(())
+    }
+
+    async fn clear(&mut self, _ctx: &mut Self::Ctx) -> Result<(), IOError> {
+        // nothing has been sent to postgres yet, dropping the batch is a rollback
+        self.pending.clear();
+        Ok(())
+    }
+
+    async fn checkpoint(
+        &mut self,
+        snapshot: &[Change<GranteeId, ActionId>],
+        ctx: &mut Self::Ctx,
+    ) -> Result<(), IOError> {
+        let records = snapshot
+            .iter()
+            .map(ChangeRecord::try_from)
+            .collect::<Result<Vec<_>, _>>()
+            .or(Err(IOError::Checkpoint))?;
+
+        // replace the snapshot and truncate the log in one transaction
+        let result: Result<bool, sqlx::Error> = async {
+            let mut tx = ctx.begin().await?;
+            // batches wait for the snapshot to be committed
+            let version = lock_version(&mut tx).await?;
+            if !self.origin.can_checkpoint(version) {
+                return Ok(false);
+            }
+            sqlx::query(r#"DELETE FROM public.permission_snapshot"#)
+                .execute(&mut *tx)
+                .await?;
+            insert_records(
+                &mut tx,
+                r#"INSERT INTO public.permission_snapshot (kind, grantee, grantee_of, action, sub_action) VALUES ($1, $2, $3, $4, $5)"#,
+                &records,
+            )
+            .await?;
+            sqlx::query(r#"DELETE FROM public.permission_change WHERE version <= $1"#)
+                .bind(version)
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+            Ok(true)
+        }
+        .await;
+        match result {
+            Ok(true) => Ok(()),
+            // the local state is missing committed batches, the snapshot would drop them
+            _ => Err(IOError::Checkpoint),
+        }
+    }
+
+    async fn history(
+        &mut self,
+        query: &AuditQuery<GranteeId, ActionId>,
+        ctx: &mut Self::Ctx,
+    ) -> Result<Vec<AuditedBatch<GranteeId, ActionId>>, IOError> {
+        let (id, fields) = match query {
+            AuditQuery::Grantee(grantee_id) => {
+                (serde_json::to_value(grantee_id), ["grantee", "grantee_of"])
+            }
+            AuditQuery::Action(action_id) => {
+                (serde_json::to_value(action_id), ["action", "sub_action"])
+            }
+        };
+        let id = id.or(Err(IOError::History))?;
+
+        // containment is served by the gin index, a clear touches everything
+        sqlx::query_as::<_, AuditRow>(
+            r#"SELECT actor, changed_at, reason, request_id, changes, inverse FROM public.permission_audit
+            WHERE changes @> jsonb_build_array(jsonb_build_object($1::text, $3::jsonb))
+                OR changes @> jsonb_build_array(jsonb_build_object($2::text, $3::jsonb))
+                OR changes @> '[{"kind": "clear"}]'
+            ORDER BY id"#,
+        )
+        .bind(fields[0])
+        .bind(fields[1])
+        .bind(id)
+        .fetch_all(ctx)
+        .await
+        .or(Err(IOError::History))?
+        .into_iter()
+        .map(|(actor, changed_at, reason, request_id, changes, inverse)| {
+            AuditRecord {
+                actor,
+                at: changed_at.into(),
+                reason,
+                request_id,
+                changes: changes.0,
+                inverse: inverse.0,
+            }
+            .try_into()
+        })
+        .collect()
+    }
+}
+
+async fn insert_audit(conn: &mut PgConnection, audit: &AuditRecord) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"INSERT INTO public.permission_audit (actor, reason, request_id, changes, inverse, changed_at) VALUES ($1, $2, $3, $4, $5, $6)"#,
+    )
+    .bind(&audit.actor)
+    .bind(&audit.reason)
+    .bind(&audit.request_id)
+    .bind(Json(&audit.changes))
+    .bind(Json(&audit.inverse))
+    .bind(OffsetDateTime::from(audit.at))
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+type AuditRow = (
+    Option<Value>,
+    OffsetDateTime,
+    Option<String>,
+    Option<String>,
+    Json<Vec<ChangeRecord>>,
+    Json<Vec<ChangeRecord>>,
+);
+
+/// returns the version of the last committed batch
+pub(super) async fn current_version(conn: &mut PgConnection) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar(r#"SELECT version FROM public.permission_version"#)
+        .fetch_one(conn)
+        .await
+}
+
+/// returns the version of the last committed batch and holds it until the transaction ends
+async fn lock_version(conn: &mut PgConnection) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar(r#"SELECT version FROM public.permission_version FOR UPDATE"#)
+        .fetch_one(conn)
+        .await
+}
+
+/// the row lock serializes concurrent batches until their transaction ends
+async fn increment_version(conn: &mut PgConnection) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar(
+        r#"UPDATE public.permission_version SET version = version + 1 RETURNING version"#,
+    )
+    .fetch_one(conn)
+    .await
+}
+
+/// postgres delivers the notification on commit, in commit order
+async fn notify(
+    conn: &mut PgConnection,
+    origin: Uuid,
+    version: i64,
+    records: &[ChangeRecord],
+) -> Result<(), sqlx::Error> {
+    let mut notification = ChangeNotification {
+        origin,
+        version,
+        changes: Some(records.to_vec()),
+    };
+    let payload = match serde_json::to_string(&notification) {
+        Ok(payload) if payload.len() <= MAX_PAYLOAD_LEN => payload,
+        _ => {
+            notification.changes = None;
+            serde_json::to_string(&notification)
+                .expect("Expected notification without changes to serialize")
+        }
+    };
+
+    sqlx::query(r#"SELECT pg_notify($1, $2)"#)
+        .bind(CHANNEL)
+        .bind(payload)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// inserts the records of a batch in order, tagged with its version
+async fn insert_changes(
+    conn: &mut PgConnection,
+    version: i64,
+    records: &[ChangeRecord],
+) -> Result<(), sqlx::Error> {
+    for record in records {
+        sqlx::query(
+            r#"INSERT INTO public.permission_change (kind, grantee, grantee_of, action, sub_action, version) VALUES ($1, $2, $3, $4, $5, $6)"#,
+        )
+        .bind(record.kind)
+        .bind(&record.grantee)
+        .bind(&record.grantee_of)
+        .bind(&record.action)
+        .bind(&record.sub_action)
+        .bind(version)
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+/// inserts records in order using the given insert query
+async fn insert_records(
+    conn: &mut PgConnection,
+    query: &str,
+    records: &[ChangeRecord],
+) -> Result<(), sqlx::Error> {
+    for record in records {
+        sqlx::query(query)
+            .bind(record.kind)
+            .bind(&record.grantee)
+            .bind(&record.grantee_of)
+            .bind(&record.action)
+            .bind(&record.sub_action)
+            .execute(&mut *conn)
+            .await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Origin;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn checkpoints_wait_for_batches_in_flight() {
+        let origin = Origin::default();
+        origin.set_synced(4);
+
+        // another instance committed version 5, its notification has not been applied yet
+        assert!(!origin.can_checkpoint(5));
+        tokio::time::timeout(Duration::from_secs(1), origin.resync_requested())
+            .await
+            .expect("Expected a resync to be requested");
+
+        // once applied the snapshot contains the batch
+        origin.set_synced(5);
+        assert!(origin.can_checkpoint(5));
+        assert!(
+            tokio::time::timeout(Duration::from_millis(10), origin.resync_requested())
+                .await
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,254 @@
+use permission::{Audit, AuditedBatch, Change, IOError};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time::SystemTime;
+
+#[derive(Debug, Copy, Clone, sqlx::Type, Serialize, Deserialize)]
+#[sqlx(type_name = "permission_change_kind")]
+#[sqlx(rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub(super) enum ChangeKind {
+    Clear,
+    RemoveGrantee,
+    RemoveAction,
+    AddGrant,
+    RemoveGrant,
+    ConnectGrantees,
+    DisconnectGrantees,
+    ConnectActions,
+    DisconnectActions,
+    AddRoot,
+    RemoveRoot,
+}
+
+/// a single persisted permission change
+///
+/// ids are stored as json values so any serializable GranteeId and ActionId can be persisted
+#[derive(Debug, Clone, sqlx::FromRow, Serialize, Deserialize)]
+pub(super) struct ChangeRecord {
+    pub(super) kind: ChangeKind,
+    pub(super) grantee: Option<Value>,
+    pub(super) grantee_of: Option<Value>,
+    pub(super) action: Option<Value>,
+    pub(super) sub_action: Option<Value>,
+}
+
+impl<GranteeId: Serialize, ActionId: Serialize> TryFrom<&Change<GranteeId, ActionId>>
+    for ChangeRecord
+{
+    type Error = serde_json::Error;
+
+    fn try_from(change: &Change<GranteeId, ActionId>) -> Result<Self, Self::Error> {
+        let mut record = ChangeRecord {
+            kind: ChangeKind::Clear,
+            grantee: None,
+            grantee_of: None,
+            action: None,
+            sub_action: None,
+        };
+
+        match change {
+            Change::Clear => {}
+            Change::RemoveGrantee(grantee_id) => {
+                record.kind = ChangeKind::RemoveGrantee;
+                record.grantee = Some(serde_json::to_value(grantee_id)?);
+            }
+            Change::RemoveAction(action_id) => {
+                record.kind = ChangeKind::RemoveAction;
+                record.action = Some(serde_json::to_value(action_id)?);
+            }
+            Change::AddGrant(grantee_id, action_id) => {
+                record.kind = ChangeKind::AddGrant;
+                record.grantee = Some(serde_json::to_value(grantee_id)?);
+                record.action = Some(serde_json::to_value(action_id)?);
+            }
+            Change::RemoveGrant(grantee_id, action_id) => {
+                record.kind = ChangeKind::RemoveGrant;
+                record.grantee = Some(serde_json::to_value(grantee_id)?);
+                record.action = Some(serde_json::to_value(action_id)?);
+            }
+            Change::ConnectGrantees(grantee_id, grantee_of_id) => {
+                record.kind = ChangeKind::ConnectGrantees;
+                record.grantee = Some(serde_json::to_value(grantee_id)?);
+                record.grantee_of = Some(serde_json::to_value(grantee_of_id)?);
+            }
+            Change::DisconnectGrantees(grantee_id, grantee_of_id) => {
+                record.kind = ChangeKind::DisconnectGrantees;
+                record.grantee = Some(serde_json::to_value(grantee_id)?);
+                record.grantee_of = Some(serde_json::to_value(grantee_of_id)?);
+            }
+            Change::ConnectActions(main_action_id, sub_action_id) => {
+                record.kind = ChangeKind::ConnectActions;
+                record.action = Some(serde_json::to_value(main_action_id)?);
+                record.sub_action = Some(serde_json::to_value(sub_action_id)?);
+            }
+            Change::DisconnectActions(main_action_id, sub_action_id) => {
+                record.kind = ChangeKind::DisconnectActions;
+                record.action = Some(serde_json::to_value(main_action_id)?);
+                record.sub_action = Some(serde_json::to_value(sub_action_id)?);
+            }
+            Change::AddRoot(grantee_id) => {
+                record.kind = ChangeKind::AddRoot;
+                record.grantee = Some(serde_json::to_value(grantee_id)?);
+            }
+            Change::RemoveRoot(grantee_id) => {
+                record.kind = ChangeKind::RemoveRoot;
+                record.grantee = Some(serde_json::to_value(grantee_id)?);
+            }
+        }
+
+        Ok(record)
+    }
+}
+
+impl<GranteeId: DeserializeOwned, ActionId: DeserializeOwned> TryFrom<ChangeRecord>
+    for Change<GranteeId, ActionId>
+{
+    type Error = IOError;
+
+    fn try_from(record: ChangeRecord) -> Result<Self, Self::Error> {
+        fn id<T: DeserializeOwned>(value: Option<Value>) -> Result<T, IOError> {
+            value
+                .and_then(|value| serde_json::from_value(value).ok())
+                .ok_or(IOError::Read)
+        }
+
+        Ok(match record.kind {
+            ChangeKind::Clear => Change::Clear,
+            ChangeKind::RemoveGrantee => Change::RemoveGrantee(id(record.grantee)?),
+            ChangeKind::RemoveAction => Change::RemoveAction(id(record.action)?),
+            ChangeKind::AddGrant => Change::AddGrant(id(record.grantee)?, id(record.action)?),
+            ChangeKind::RemoveGrant => Change::RemoveGrant(id(record.grantee)?, id(record.action)?),
+            ChangeKind::ConnectGrantees => {
+                Change::ConnectGrantees(id(record.grantee)?, id(record.grantee_of)?)
+            }
+            ChangeKind::DisconnectGrantees => {
+                Change::DisconnectGrantees(id(record.grantee)?, id(record.grantee_of)?)
+            }
+            ChangeKind::ConnectActions => {
+                Change::ConnectActions(id(record.action)?, id(record.sub_action)?)
+            }
+            ChangeKind::DisconnectActions => {
+                Change::DisconnectActions(id(record.action)?, id(record.sub_action)?)
+            }
+            ChangeKind::AddRoot => Change::AddRoot(id(record.grantee)?),
+            ChangeKind::RemoveRoot => Change::RemoveRoot(id(record.grantee)?),
+        })
+    }
+}
+
+/// a persisted batch of change records together with its audit
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(super) struct AuditRecord {
+    pub(super) actor: Option<Value>,
+    pub(super) at: SystemTime,
+    pub(super) reason: Option<String>,
+    pub(super) request_id: Option<String>,
+    pub(super) changes: Vec<ChangeRecord>,
+    #[serde(default)]
+    pub(super) inverse: Vec<ChangeRecord>,
+}
+
+impl AuditRecord {
+    pub(super) fn new<GranteeId: Serialize>(
+        audit: &Audit<GranteeId>,
+        changes: Vec<ChangeRecord>,
+        inverse: Vec<ChangeRecord>,
+    ) -> Result<Self, serde_json::Error> {
+        Ok(Self {
+            actor: audit.actor.as_ref().map(serde_json::to_value).transpose()?,
+            at: audit.at,
+            reason: audit.reason.clone(),
+            request_id: audit.request_id.clone(),
+            changes,
+            inverse,
+        })
+    }
+}
+
+impl<GranteeId: DeserializeOwned, ActionId: DeserializeOwned> TryFrom<AuditRecord>
+    for AuditedBatch<GranteeId, ActionId>
+{
+    type Error = IOError;
+
+    fn try_from(record: AuditRecord) -> Result<Self, Self::Error> {
+        Ok(AuditedBatch {
+            audit: Audit {
+                actor: record
+                    .actor
+                    .map(serde_json::from_value)
+                    .transpose()
+                    .or(Err(IOError::History))?,
+                at: record.at,
+                reason: record.reason,
+                request_id: record.request_id,
+            },
+            changes: record
+                .changes
+                .into_iter()
+                .map(Change::try_from)
+                .collect::<Result<_, _>>()
+                .or(Err(IOError::History))?,
+            inverse: record
+                .inverse
+                .into_iter()
+                .map(Change::try_from)
+                .collect::<Result<_, _>>()
+                .or(Err(IOError::History))?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AuditRecord, ChangeRecord};
+    use permission::{Audit, AuditedBatch, Change};
+    use uuid::Uuid;
+
+    #[test]
+    fn changes_survive_a_record_roundtrip() {
+        let grantee = Uuid::new_v4();
+        let group = Uuid::new_v4();
+        let changes: Vec<Change<Uuid, (u8, Uuid)>> = vec![
+            Change::Clear,
+            Change::AddGrant(grantee, (1, group)),
+            Change::ConnectGrantees(grantee, group),
+            Change::ConnectActions((1, group), (2, group)),
+            Change::RemoveRoot(group),
+        ];
+
+        for change in changes {
+            let record = ChangeRecord::try_from(&change).expect("Expected record");
+            let restored: Change<Uuid, (u8, Uuid)> = record.try_into().expect("Expected change");
+            assert_eq!(format!("{change:?}"), format!("{restored:?}"));
+        }
+    }
+
+    #[test]
+    fn audits_survive_a_record_roundtrip() {
+        let actor = Uuid::new_v4();
+        let audit = Audit::new(actor)
+            .with_reason("elected to the board")
+            .with_request_id("request");
+        let change: Change<Uuid, u8> = Change::AddGrant(actor, 1);
+        let inverse: Change<Uuid, u8> = Change::RemoveGrant(actor, 1);
+
+        let record = AuditRecord::new(
+            &audit,
+            vec![ChangeRecord::try_from(&change).expect("Expected record")],
+            vec![ChangeRecord::try_from(&inverse).expect("Expected record")],
+        )
+        .expect("Expected audit record");
+        let restored: AuditedBatch<Uuid, u8> = record.try_into().expect("Expected batch");
+
+        assert_eq!(restored.audit.actor, Some(actor));
+        assert_eq!(restored.audit.at, audit.at);
+        assert_eq!(restored.audit.reason, audit.reason);
+        assert_eq!(restored.audit.request_id, audit.request_id);
+        assert!(matches!(restored.changes[..], [Change::AddGrant(grantee, 1)] if grantee == actor));
+        assert!(
+            matches!(restored.inverse[..], [Change::RemoveGrant(grantee, 1)] if grantee == actor)
+        );
+    }
+}
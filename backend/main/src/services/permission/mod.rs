@@ -0,0 +1,44 @@
+mod client;
+pub mod io_provider;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+pub use client::PermissionClient;
+pub use io_provider::*;
+
+/// everything permissions can be granted to
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Grantee {
+    User(Uuid),
+    Role(Uuid),
+    /// grants to a tenant apply to all of its users
+    Tenant(Uuid),
+}
+
+/// everything a grantee can be permitted to do
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Action {
+    ManagePermissions,
+    /// changing grants of grantees outside the own tenant, eg. roles shared by all tenants
+    ManageAllPermissions,
+}
+
+/// type level [Action], used to declare the action required by a route
+///
+/// see [RequirePermission](crate::web::permission_guard::RequirePermission)
+pub trait RequiredAction {
+    const ACTION: Action;
+}
+
+pub mod actions {
+    use super::{Action, RequiredAction};
+
+    pub struct ManagePermissions;
+
+    impl RequiredAction for ManagePermissions {
+        const ACTION: Action = Action::ManagePermissions;
+    }
+}
+
+pub type Permissions = PermissionClient<Grantee, Action, PermissionBackend<Grantee, Action>>;
@@ -10,8 +10,51 @@ pub type RedisConnection = deadpool_redis::Connection;
 pub struct Services {
     pub token_provider: Arc<RwLock<crate::services::auth::io_provider::LocalTokenIO>>,
     pub account_provider: Arc<RwLock<crate::services::auth::io_provider::LocalAccountIO>>,
+    pub attempt_provider: Arc<RwLock<crate::services::auth::io_provider::LocalAttemptIO>>,
+    pub factor_provider: Arc<RwLock<crate::services::auth::io_provider::LocalFactorIO>>,
+    // external logins are disabled without an identity provider
+    #[from_ref(skip)]
+    pub identity_provider: Option<Arc<crate::services::auth::io_provider::LocalIdentityProviderIO>>,
+    #[from_ref(skip)]
+    pub reset_provider: Arc<RwLock<crate::services::auth::io_provider::LocalOneTimeTokenIO>>,
+    #[from_ref(skip)]
+    pub verification_provider: Arc<RwLock<crate::services::auth::io_provider::LocalOneTimeTokenIO>>,
+    pub mail_sender: Arc<crate::services::mail::FileMailSender>,
+    // base url clients reach this instance at, used for links in mails
+    pub public_url: Arc<str>,
+    // page user agents are sent to after logging in at an identity provider
+    #[from_ref(skip)]
+    pub app_url: Arc<str>,
     pub redis: RedisPool,
+    pub postgres: sqlx::PgPool,
+    pub permission: permission::Permission<
+        crate::services::permission::Grantee,
+        crate::services::permission::Action,
+        crate::services::permission::PermissionBackend<
+            crate::services::permission::Grantee,
+            crate::services::permission::Action,
+        >,
+    >,
 }
 
-#[derive(Copy, Clone, Debug)]
-pub struct UserId(pub Uuid);
+/// the authenticated account and the user it is acting as
+///
+/// an account has at most one user per tenant,
+/// user and tenant are None until the account switched to one of its users
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CurrentUser {
+    pub account: Uuid,
+    pub user: Option<Uuid>,
+    pub tenant: Option<Uuid>,
+}
+
+impl CurrentUser {
+    /// an account which has not switched to any of its users yet
+    pub fn without_user(account: Uuid) -> Self {
+        Self {
+            account,
+            user: None,
+            tenant: None,
+        }
+    }
+}
@@ -1,7 +1,7 @@
 use crate::services::auth::io::TokenIO;
 use crate::services::auth::io_provider::LocalTokenIO;
+use crate::types::CurrentUser;
 use crate::types::RedisPool;
-use crate::UserId;
 use axum::async_trait;
 use axum::extract::{FromRequestParts, State};
 use axum::http::request::Parts;
@@ -28,16 +28,12 @@ pub async fn auth_layer<B>(
     let Ok(mut conn) = auth.redis.get().await else {
         return StatusCode::UNAUTHORIZED.into_response();
     };
-    let Ok(internal_id) = auth.auth.verify(&token.to_string(), &mut conn).await else {
+    let Ok(current) = auth.auth.verify(&token.to_string(), &mut conn).await else {
         return StatusCode::UNAUTHORIZED.into_response();
     };
 
-    // insert InternalId to be extractable later on
-    if request
-        .extensions_mut()
-        .insert(UserId(internal_id))
-        .is_some()
-    {
+    // insert CurrentUser to be extractable later on
+    if request.extensions_mut().insert(current).is_some() {
         // inserted twice
         return StatusCode::UNAUTHORIZED.into_response();
     };
@@ -46,12 +42,12 @@ pub async fn auth_layer<B>(
 }
 
 #[async_trait]
-impl<S: Sized> FromRequestParts<S> for UserId {
+impl<S: Sized> FromRequestParts<S> for CurrentUser {
     type Rejection = ();
 
     async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
-        let user_id = parts.extensions.get::<UserId>().ok_or(())?;
+        let current = parts.extensions.get::<CurrentUser>().ok_or(())?;
 
-        Ok(*user_id)
+        Ok(*current)
     }
 }
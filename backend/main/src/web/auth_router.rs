@@ -1,57 +1,630 @@
 use crate::services::auth::io_provider::LocalAccountIO;
 use crate::services::auth::Auth;
-use crate::types::Services;
+use crate::services::auth::{
+    is_mail_address, AuthError, ExternalLogin, Login, LoginTokens, PasswordReset, TotpEnrollment,
+    Verification,
+};
+use crate::types::{CurrentUser, Services};
+use axum::extract::{ConnectInfo, Query};
 use axum::http::{header, HeaderMap, StatusCode};
-use axum::response::Response;
+use axum::response::{AppendHeaders, Redirect, Response};
+use axum::routing::get;
 use axum::{extract::State, response::IntoResponse, routing::post, Json, Router};
 use axum_tx_layer::Transaction;
-use serde::Deserialize;
+use jsonwebtoken::jwk::JwkSet;
+use serde::{Deserialize, Serialize};
 use sqlx::Postgres;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
 
 pub fn auth_router() -> Router<Arc<Services>> {
     Router::new()
         .route("/login", post(login))
+        .route("/login/totp", post(login_totp))
+        .route("/refresh", post(refresh))
+        .route("/oidc/authorize", get(oidc_authorize))
+        .route("/oidc/callback", get(oidc_callback))
         .route("/create_account", post(create))
+        .route("/reset", post(request_reset))
+        .route("/reset/confirm", post(confirm_reset))
+        .route("/verify", get(verify))
+        .route("/verify/resend", post(resend_verification))
+}
+
+/// public keys for other services verifying tokens
+pub fn well_known_router() -> Router<Arc<Services>> {
+    Router::new().route("/jwks.json", get(jwks))
+}
+
+/// routes of the auth service requiring a logged in user
+pub fn authenticated_auth_router() -> Router<Arc<Services>> {
+    Router::new()
+        .route("/password", post(change_password))
+        .route("/switch", post(switch))
+        .route("/totp", post(enroll_totp))
+        .route("/totp/confirm", post(confirm_totp))
 }
 
 #[axum::debug_handler(state = Arc<Services>)]
 async fn login(
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<Services>>,
     mut auth_client: Auth,
     mut tx: Transaction<sqlx::Transaction<'static, Postgres>>,
     mut redis: Transaction<deadpool_redis::Connection>,
     Json(login_data): Json<LoginPayload>,
 ) -> Response {
-    if let Ok(id) = auth_client
-        .login(&login_data.id, &login_data.password, &mut tx, &mut redis)
+    // failed logins are counted outside of the request transaction
+    let Ok(mut attempts) = state.redis.get().await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    match auth_client
+        .login(
+            &login_data.id,
+            &login_data.password,
+            client.ip(),
+            &mut tx,
+            &mut redis,
+            &mut attempts,
+        )
         .await
     {
-        let mut headers = HeaderMap::new();
-        headers.insert(header::AUTHORIZATION, id.parse().unwrap());
-        (StatusCode::OK, headers).into_response()
-    } else {
-        (StatusCode::FORBIDDEN).into_response()
+        Ok(Login::Tokens(tokens)) => tokens_response(tokens),
+        // the client has to complete the login with a code of the second factor
+        Ok(Login::SecondFactor { challenge }) => {
+            (StatusCode::ACCEPTED, Json(ChallengePayload { challenge })).into_response()
+        }
+        Err(AuthError::Unverified) => (StatusCode::FORBIDDEN, "unverified").into_response(),
+        Err(AuthError::TooManyAttempts(retry_after)) => too_many_attempts(retry_after),
+        Err(_) => (StatusCode::FORBIDDEN).into_response(),
+    }
+}
+
+/// completes a login with a code of the second factor or a recovery code
+#[axum::debug_handler(state = Arc<Services>)]
+async fn login_totp(
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<Services>>,
+    mut auth_client: Auth,
+    mut tx: Transaction<sqlx::Transaction<'static, Postgres>>,
+    mut redis: Transaction<deadpool_redis::Connection>,
+    Json(totp_data): Json<TotpLoginPayload>,
+) -> Response {
+    // failed codes are counted outside of the request transaction
+    let Ok(mut attempts) = state.redis.get().await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    match auth_client
+        .login_second_factor(
+            &totp_data.challenge,
+            &totp_data.code,
+            client.ip(),
+            &mut tx,
+            &mut redis,
+            &mut attempts,
+        )
+        .await
+    {
+        Ok(tokens) => tokens_response(tokens),
+        Err(AuthError::TooManyAttempts(retry_after)) => too_many_attempts(retry_after),
+        Err(AuthError::IO) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        Err(_) => StatusCode::FORBIDDEN.into_response(),
+    }
+}
+
+/// retry after is rounded up to whole seconds
+fn too_many_attempts(retry_after: Duration) -> Response {
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(
+            header::RETRY_AFTER,
+            retry_after.as_millis().div_ceil(1000).to_string(),
+        )],
+    )
+        .into_response()
+}
+
+/// the state of a login started at the identity provider, only sent back by the user agent which started it
+const OIDC_STATE_COOKIE: &str = "oidc_state";
+/// time a user agent has to log in at the identity provider
+const OIDC_STATE_MAX_AGE: Duration = Duration::from_secs(10 * 60);
+/// refresh token of a login completed at an identity provider, only readable by /auth/refresh
+const REFRESH_COOKIE: &str = "refresh_token";
+
+/// redirects the user agent to the identity provider
+///
+/// the state is bound to the user agent by a short lived cookie,
+/// thus nobody can make others complete a login started by themselves
+#[axum::debug_handler(state = Arc<Services>)]
+async fn oidc_authorize(
+    State(state): State<Arc<Services>>,
+    mut external_client: ExternalLogin,
+    mut redis: Transaction<deadpool_redis::Connection>,
+) -> Response {
+    match external_client.authorize(&mut redis).await {
+        Ok(authorization) => (
+            AppendHeaders([(
+                header::SET_COOKIE,
+                // the callback is a top level navigation from the identity provider
+                set_cookie(
+                    OIDC_STATE_COOKIE,
+                    &authorization.state,
+                    "/",
+                    Some(OIDC_STATE_MAX_AGE),
+                    "Lax",
+                    &state,
+                ),
+            )]),
+            Redirect::to(&authorization.url),
+        )
+            .into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// the identity provider redirects the user agent back with a code, it is exchanged for tokens
+///
+/// the user agent is redirected to the app with the refresh token as HttpOnly cookie,
+/// the app gets its access token from /auth/refresh,
+/// a challenge of the second factor is passed in the fragment of the app url
+#[axum::debug_handler(state = Arc<Services>)]
+async fn oidc_callback(
+    State(state): State<Arc<Services>>,
+    headers: HeaderMap,
+    mut external_client: ExternalLogin,
+    mut tx: Transaction<sqlx::Transaction<'static, Postgres>>,
+    mut redis: Transaction<deadpool_redis::Connection>,
+    Query(callback_data): Query<OidcCallbackPayload>,
+) -> Response {
+    if cookie(&headers, OIDC_STATE_COOKIE) != Some(callback_data.state.as_str()) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    // the login state is read outside of the request transaction
+    let Ok(mut login_state) = state.redis.get().await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    let clear_state = (
+        header::SET_COOKIE,
+        set_cookie(
+            OIDC_STATE_COOKIE,
+            "",
+            "/",
+            Some(Duration::ZERO),
+            "Lax",
+            &state,
+        ),
+    );
+    match external_client
+        .login(
+            &callback_data.code,
+            &callback_data.state,
+            &mut tx,
+            &mut redis,
+            &mut login_state,
+        )
+        .await
+    {
+        Ok(Login::Tokens(tokens)) => (
+            AppendHeaders([
+                clear_state,
+                (
+                    header::SET_COOKIE,
+                    set_cookie(
+                        REFRESH_COOKIE,
+                        &tokens.refresh_token,
+                        "/auth/refresh",
+                        None,
+                        "Strict",
+                        &state,
+                    ),
+                ),
+            ]),
+            Redirect::to(&state.app_url),
+        )
+            .into_response(),
+        Ok(Login::SecondFactor { challenge }) => (
+            AppendHeaders([clear_state]),
+            Redirect::to(&format!("{}#challenge={challenge}", state.app_url)),
+        )
+            .into_response(),
+        Err(AuthError::Unverified) => (StatusCode::FORBIDDEN, "unverified").into_response(),
+        Err(AuthError::IO) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        Err(_) => StatusCode::FORBIDDEN.into_response(),
+    }
+}
+
+/// rotates the refresh token, replaying an already used one logs out the client it was issued to
+///
+/// the refresh token is taken from the body, or from the cookie set by [oidc_callback],
+/// which is rotated then and never exposed in the body
+#[axum::debug_handler(state = Arc<Services>)]
+async fn refresh(
+    State(state): State<Arc<Services>>,
+    headers: HeaderMap,
+    mut auth_client: Auth,
+    refresh_data: Option<Json<RefreshPayload>>,
+) -> Response {
+    let refresh_token = match (&refresh_data, cookie(&headers, REFRESH_COOKIE)) {
+        (Some(Json(refresh_data)), _) => refresh_data.refresh_token.as_str(),
+        (None, Some(refresh_token)) => refresh_token,
+        (None, None) => return StatusCode::UNAUTHORIZED.into_response(),
+    };
+    // rotating needs the reply of redis, which is only queued within the request transaction
+    let Ok(mut redis) = state.redis.get().await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    match auth_client.refresh(refresh_token, &mut redis).await {
+        Ok(tokens) if refresh_data.is_none() => (
+            AppendHeaders([
+                (header::AUTHORIZATION, tokens.access_token),
+                (
+                    header::SET_COOKIE,
+                    set_cookie(
+                        REFRESH_COOKIE,
+                        &tokens.refresh_token,
+                        "/auth/refresh",
+                        None,
+                        "Strict",
+                        &state,
+                    ),
+                ),
+            ]),
+            StatusCode::NO_CONTENT,
+        )
+            .into_response(),
+        Ok(tokens) => tokens_response(tokens),
+        Err(AuthError::IO) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        Err(_) => StatusCode::UNAUTHORIZED.into_response(),
+    }
+}
+
+/// the access token is sent as authorization header, the refresh token in the body
+fn tokens_response(tokens: LoginTokens) -> Response {
+    let mut headers = HeaderMap::new();
+    headers.insert(header::AUTHORIZATION, tokens.access_token.parse().unwrap());
+    (
+        StatusCode::OK,
+        headers,
+        Json(RefreshPayload {
+            refresh_token: tokens.refresh_token,
+        }),
+    )
+        .into_response()
+}
+
+/// value of a cookie sent by the user agent
+fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .find_map(|pair| pair.trim().strip_prefix(name)?.strip_prefix('='))
+}
+
+/// a cookie hidden from scripts, it is kept until the user agent closes without max_age
+///
+/// it is only sent over https if this instance is reached over https
+fn set_cookie(
+    name: &str,
+    value: &str,
+    path: &str,
+    max_age: Option<Duration>,
+    same_site: &str,
+    state: &Services,
+) -> String {
+    let mut cookie = format!("{name}={value}; Path={path}; HttpOnly; SameSite={same_site}");
+    if let Some(max_age) = max_age {
+        cookie.push_str(&format!("; Max-Age={}", max_age.as_secs()));
     }
+    if state.public_url.starts_with("https://") {
+        cookie.push_str("; Secure");
+    }
+    cookie
+}
+
+async fn jwks(State(state): State<Arc<Services>>) -> Json<JwkSet> {
+    Json(state.token_provider.read().await.jwks())
 }
 
+/// accounts are created unverified, a verification link is sent to their id
 #[axum::debug_handler(state = Arc<Services>)]
 async fn create(
     mut auth_client: Auth,
+    mut verification_client: Verification,
     mut tx: Transaction<sqlx::Transaction<'static, Postgres>>,
     Json(login_data): Json<LoginPayload>,
 ) -> Response {
-    if let Ok(id) = auth_client
+    if !is_mail_address(&login_data.id) {
+        return StatusCode::UNPROCESSABLE_ENTITY.into_response();
+    }
+    let Ok(id) = auth_client
         .create_account(&login_data.id, &login_data.password, &mut tx)
         .await
+    else {
+        return (StatusCode::PRECONDITION_FAILED).into_response();
+    };
+
+    // the account is rolled back if the link can not be sent
+    match verification_client.send(&id, &login_data.id, &mut tx).await {
+        Ok(()) => (StatusCode::CREATED, id.to_string()).into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+#[axum::debug_handler(state = Arc<Services>)]
+async fn verify(
+    mut verification_client: Verification,
+    mut tx: Transaction<sqlx::Transaction<'static, Postgres>>,
+    Query(verify_data): Query<VerifyPayload>,
+) -> Response {
+    match verification_client
+        .confirm(&verify_data.token, &mut tx)
+        .await
+    {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(AuthError::IO) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        Err(_) => StatusCode::FORBIDDEN.into_response(),
+    }
+}
+
+/// always accepted unless sending fails, thus callers can not find out which accounts exist
+#[axum::debug_handler(state = Arc<Services>)]
+async fn resend_verification(
+    mut verification_client: Verification,
+    mut tx: Transaction<sqlx::Transaction<'static, Postgres>>,
+    Json(resend_data): Json<ResetPayload>,
+) -> Response {
+    match verification_client.resend(&resend_data.id, &mut tx).await {
+        Ok(()) => StatusCode::ACCEPTED.into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// always accepted unless sending fails, thus callers can not find out which accounts exist
+#[axum::debug_handler(state = Arc<Services>)]
+async fn request_reset(
+    mut reset_client: PasswordReset,
+    mut tx: Transaction<sqlx::Transaction<'static, Postgres>>,
+    Json(reset_data): Json<ResetPayload>,
+) -> Response {
+    match reset_client.request(&reset_data.id, &mut tx).await {
+        Ok(()) => StatusCode::ACCEPTED.into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// revokes all tokens of the user on success, the database changes are rolled back otherwise
+#[axum::debug_handler(state = Arc<Services>)]
+async fn confirm_reset(
+    mut reset_client: PasswordReset,
+    mut tx: Transaction<sqlx::Transaction<'static, Postgres>>,
+    mut redis: Transaction<deadpool_redis::Connection>,
+    Json(reset_data): Json<ConfirmResetPayload>,
+) -> Response {
+    match reset_client
+        .confirm(
+            &reset_data.token,
+            &reset_data.new_password,
+            &mut tx,
+            &mut redis,
+        )
+        .await
+    {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(AuthError::IO) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        Err(_) => StatusCode::FORBIDDEN.into_response(),
+    }
+}
+
+/// revokes all tokens of the user before the new password is committed
+///
+/// the password change is rolled back if revoking fails
+#[axum::debug_handler(state = Arc<Services>)]
+async fn change_password(
+    State(state): State<Arc<Services>>,
+    current: CurrentUser,
+    mut auth_client: Auth,
+    Json(password_data): Json<ChangePasswordPayload>,
+) -> Response {
+    let Ok(mut tx) = state.postgres.begin().await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    match auth_client
+        .change_password(
+            &current.account,
+            &password_data.password,
+            &password_data.new_password,
+            &mut tx,
+        )
+        .await
+    {
+        Ok(()) => {}
+        Err(AuthError::IO) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        Err(_) => return StatusCode::FORBIDDEN.into_response(),
+    }
+
+    // dropping tx rolls the password back
+    let Ok(mut redis) = state.redis.get().await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    if auth_client
+        .logout_all(&current.account, &mut redis)
+        .await
+        .is_err()
+    {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    match tx.commit().await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// switches to the user of the account in a tenant, the current tokens of the client are revoked
+#[axum::debug_handler(state = Arc<Services>)]
+async fn switch(
+    State(state): State<Arc<Services>>,
+    current: CurrentUser,
+    headers: HeaderMap,
+    mut auth_client: Auth,
+    mut tx: Transaction<sqlx::Transaction<'static, Postgres>>,
+    Json(switch_data): Json<SwitchPayload>,
+) -> Response {
+    let Some(Ok(access_token)) = headers.get(header::AUTHORIZATION).map(|h| h.to_str()) else {
+        return StatusCode::UNAUTHORIZED.into_response();
+    };
+    // revoking the refresh token needs the reply of redis, which is only queued within the request transaction
+    let Ok(mut redis) = state.redis.get().await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    match auth_client
+        .switch(
+            &current,
+            access_token,
+            &switch_data.refresh_token,
+            &switch_data.tenant,
+            &mut tx,
+            &mut redis,
+        )
+        .await
+    {
+        Ok(tokens) => tokens_response(tokens),
+        Err(AuthError::IO) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        Err(_) => StatusCode::FORBIDDEN.into_response(),
+    }
+}
+
+/// creates a totp secret, it is required on login once confirmed
+#[axum::debug_handler(state = Arc<Services>)]
+async fn enroll_totp(
+    current: CurrentUser,
+    mut auth_client: Auth,
+    mut tx: Transaction<sqlx::Transaction<'static, Postgres>>,
+) -> Response {
+    match auth_client.enroll_totp(&current.account, &mut tx).await {
+        Ok(TotpEnrollment { secret, uri }) => (
+            StatusCode::CREATED,
+            Json(TotpEnrollmentPayload { secret, uri }),
+        )
+            .into_response(),
+        Err(AuthError::IO) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        Err(_) => StatusCode::CONFLICT.into_response(),
+    }
+}
+
+/// confirms the totp secret with its first code, the recovery codes are only shown once
+#[axum::debug_handler(state = Arc<Services>)]
+async fn confirm_totp(
+    current: CurrentUser,
+    mut auth_client: Auth,
+    mut tx: Transaction<sqlx::Transaction<'static, Postgres>>,
+    Json(confirm_data): Json<TotpCodePayload>,
+) -> Response {
+    match auth_client
+        .confirm_totp(&current.account, &confirm_data.code, &mut tx)
+        .await
     {
-        (StatusCode::CREATED, id.to_string()).into_response()
-    } else {
-        (StatusCode::PRECONDITION_FAILED).into_response()
+        Ok(recovery_codes) => Json(RecoveryCodesPayload { recovery_codes }).into_response(),
+        Err(AuthError::IO) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        Err(_) => StatusCode::FORBIDDEN.into_response(),
     }
 }
 
+#[derive(Debug, Deserialize)]
+struct OidcCallbackPayload {
+    code: String,
+    state: String,
+}
+
+#[derive(Debug, Serialize)]
+struct ChallengePayload {
+    challenge: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TotpLoginPayload {
+    challenge: String,
+    code: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TotpCodePayload {
+    code: String,
+}
+
+#[derive(Debug, Serialize)]
+struct TotpEnrollmentPayload {
+    secret: String,
+    uri: String,
+}
+
+#[derive(Debug, Serialize)]
+struct RecoveryCodesPayload {
+    recovery_codes: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SwitchPayload {
+    tenant: Uuid,
+    refresh_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResetPayload {
+    id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct VerifyPayload {
+    token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ConfirmResetPayload {
+    token: String,
+    new_password: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChangePasswordPayload {
+    password: String,
+    new_password: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct RefreshPayload {
+    refresh_token: String,
+}
+
 #[derive(Debug, Deserialize)]
 struct LoginPayload {
     id: String,
     password: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::cookie;
+    use axum::http::{header, HeaderMap, HeaderValue};
+
+    #[test]
+    fn cookies_are_found_by_name() {
+        let mut headers = HeaderMap::new();
+        headers.append(
+            header::COOKIE,
+            HeaderValue::from_static("theme=dark; oidc_state_old=other"),
+        );
+        headers.append(
+            header::COOKIE,
+            HeaderValue::from_static("oidc_state=some-state"),
+        );
+
+        assert_eq!(cookie(&headers, "oidc_state"), Some("some-state"));
+        assert_eq!(cookie(&headers, "theme"), Some("dark"));
+        assert_eq!(cookie(&headers, "refresh_token"), None);
+    }
+}
@@ -1,4 +1,7 @@
 pub mod auth_layer;
 mod auth_router;
+pub mod permission_guard;
+mod permission_router;
 
 pub use self::auth_router::*;
+pub use self::permission_router::*;
@@ -0,0 +1,312 @@
+use crate::services::permission::{Action, Grantee, RequiredAction};
+use crate::types::{CurrentUser, Services};
+use axum::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use can_do::CanDoError;
+use permission::{PermissionChecker, PermissionError};
+use serde::Serialize;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+/// state granting [RequirePermission] access to permissions
+pub trait PermissionState {
+    type Checker: PermissionChecker<Grantee, Action>;
+
+    fn permissions(&self) -> &Self::Checker;
+}
+
+impl PermissionState for Arc<Services> {
+    type Checker = permission::Permission<
+        Grantee,
+        Action,
+        crate::services::permission::PermissionBackend<Grantee, Action>,
+    >;
+
+    fn permissions(&self) -> &Self::Checker {
+        &self.permission
+    }
+}
+
+/// guards a handler, extracting it requires the authenticated user to be permitted A
+///
+/// the user of the [CurrentUser] inserted by [auth_layer](super::auth_layer::auth_layer) is checked as [Grantee::User],
+/// which includes the roles it is a member of, then its tenant as [Grantee::Tenant]
+/// accounts which have not switched to one of their users are never permitted
+/// eg. `async fn handler(RequirePermission(user, ..): RequirePermission<ManagePermissions>)`
+pub struct RequirePermission<A: RequiredAction>(pub CurrentUser, pub PhantomData<A>);
+
+#[async_trait]
+impl<A, S> FromRequestParts<S> for RequirePermission<A>
+where
+    A: RequiredAction,
+    S: PermissionState + Send + Sync,
+{
+    type Rejection = PermissionRejection;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Some(&current) = parts.extensions.get::<CurrentUser>() else {
+            return Err(PermissionRejection::Unauthenticated);
+        };
+        let Some(user_id) = current.user else {
+            return Err(PermissionRejection::Forbidden(A::ACTION));
+        };
+
+        let grantees = [
+            Some(Grantee::User(user_id)),
+            current.tenant.map(Grantee::Tenant),
+        ];
+        ensure_permitted(state.permissions(), grantees.iter().flatten(), A::ACTION)?;
+        Ok(RequirePermission(current, PhantomData))
+    }
+}
+
+/// checks whether any of the grantees is permitted the action
+pub(crate) fn ensure_permitted<'a>(
+    checker: &impl PermissionChecker<Grantee, Action>,
+    grantees: impl IntoIterator<Item = &'a Grantee>,
+    action: Action,
+) -> Result<(), PermissionRejection> {
+    for grantee in grantees {
+        match checker.check(grantee, &action) {
+            Ok(true) => return Ok(()),
+            // unknown grantees and actions have never been granted anything
+            Ok(false)
+            | Err(PermissionError::Check(
+                CanDoError::GranteeNotFound | CanDoError::ActionNotFound,
+            )) => {}
+            Err(_) => return Err(PermissionRejection::Unavailable),
+        }
+    }
+    Err(PermissionRejection::Forbidden(action))
+}
+
+/// rejection of a [RequirePermission] guard
+#[derive(Debug)]
+pub enum PermissionRejection {
+    /// no authenticated user, the route is missing the auth_layer
+    Unauthenticated,
+    /// the user is not permitted the action
+    Forbidden(Action),
+    /// permissions could not be checked, eg. after failing to persist a change
+    Unavailable,
+}
+
+#[derive(Serialize)]
+struct RejectionBody {
+    error: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    action: Option<Action>,
+}
+
+impl IntoResponse for PermissionRejection {
+    fn into_response(self) -> Response {
+        let (status, error, action) = match self {
+            PermissionRejection::Unauthenticated => {
+                (StatusCode::UNAUTHORIZED, "unauthenticated", None)
+            }
+            PermissionRejection::Forbidden(action) => {
+                (StatusCode::FORBIDDEN, "forbidden", Some(action))
+            }
+            PermissionRejection::Unavailable => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "permissions_unavailable",
+                None,
+            ),
+        };
+        (status, Json(RejectionBody { error, action })).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PermissionRejection, PermissionState, RequirePermission};
+    use crate::services::permission::actions::ManagePermissions;
+    use crate::services::permission::{Action, Grantee};
+    use crate::types::CurrentUser;
+    use axum::extract::FromRequestParts;
+    use axum::http::{Request, StatusCode};
+    use axum::response::IntoResponse;
+    use can_do::CanDoError;
+    use permission::{Audit, Change, MemoryIO, MockPermissionChecker, Permission, PermissionError};
+    use uuid::Uuid;
+
+    struct TestState(MockPermissionChecker<Grantee, Action>);
+
+    impl PermissionState for TestState {
+        type Checker = MockPermissionChecker<Grantee, Action>;
+
+        fn permissions(&self) -> &Self::Checker {
+            &self.0
+        }
+    }
+
+    struct GrantedState(Permission<Grantee, Action, MemoryIO<Grantee, Action>>);
+
+    impl PermissionState for GrantedState {
+        type Checker = Permission<Grantee, Action, MemoryIO<Grantee, Action>>;
+
+        fn permissions(&self) -> &Self::Checker {
+            &self.0
+        }
+    }
+
+    fn tenant_user(user_id: Uuid) -> CurrentUser {
+        CurrentUser {
+            account: Uuid::new_v4(),
+            user: Some(user_id),
+            tenant: Some(Uuid::new_v4()),
+        }
+    }
+
+    async fn guard(
+        current: Option<CurrentUser>,
+        result: fn() -> Result<bool, PermissionError>,
+    ) -> Result<RequirePermission<ManagePermissions>, PermissionRejection> {
+        let grantees = current.map(|current| {
+            [
+                current.user.map(Grantee::User),
+                current.tenant.map(Grantee::Tenant),
+            ]
+        });
+        let mut checker = MockPermissionChecker::new();
+        checker
+            .expect_check()
+            .withf(move |grantee, action| {
+                grantees.is_some_and(|grantees| grantees.contains(&Some(*grantee)))
+                    && *action == Action::ManagePermissions
+            })
+            .returning(move |_, _| result());
+
+        RequirePermission::from_request_parts(&mut parts(current), &TestState(checker)).await
+    }
+
+    fn parts(current: Option<CurrentUser>) -> axum::http::request::Parts {
+        let (mut parts, _) = Request::builder()
+            .body(())
+            .expect("Expected request")
+            .into_parts();
+        if let Some(current) = current {
+            parts.extensions.insert(current);
+        }
+        parts
+    }
+
+    async fn granted(changes: Vec<Change<Grantee, Action>>) -> GrantedState {
+        let permission = Permission::new(MemoryIO::new(), &mut ())
+            .await
+            .expect("Expected permission");
+        permission
+            .change(changes, &Audit::new(Grantee::User(Uuid::new_v4())), &mut ())
+            .await
+            .expect("Expected changes to be applied");
+        GrantedState(permission)
+    }
+
+    #[tokio::test]
+    async fn permitted_users_pass() {
+        let user_id = Uuid::new_v4();
+        let RequirePermission(user, _) = guard(Some(tenant_user(user_id)), || Ok(true))
+            .await
+            .expect("Expected user to be permitted");
+        assert_eq!(user.user, Some(user_id));
+    }
+
+    #[tokio::test]
+    async fn forbidden_users_are_rejected_with_the_action() {
+        let rejection = guard(Some(tenant_user(Uuid::new_v4())), || Ok(false))
+            .await
+            .err();
+        assert!(matches!(
+            rejection,
+            Some(PermissionRejection::Forbidden(Action::ManagePermissions))
+        ));
+
+        let response = rejection.expect("Expected rejection").into_response();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .expect("Expected body");
+        assert_eq!(
+            &body[..],
+            br#"{"error":"forbidden","action":"ManagePermissions"}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn unknown_users_are_forbidden() {
+        assert!(matches!(
+            guard(Some(tenant_user(Uuid::new_v4())), || Err(
+                PermissionError::Check(CanDoError::GranteeNotFound)
+            ))
+            .await,
+            Err(PermissionRejection::Forbidden(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn failed_permissions_are_unavailable() {
+        let rejection = guard(Some(tenant_user(Uuid::new_v4())), || {
+            Err(PermissionError::Failed)
+        })
+        .await
+        .err()
+        .expect("Expected rejection");
+        assert_eq!(
+            rejection.into_response().status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+    }
+
+    #[tokio::test]
+    async fn accounts_without_user_are_forbidden() {
+        assert!(matches!(
+            guard(Some(CurrentUser::without_user(Uuid::new_v4())), || Ok(true)).await,
+            Err(PermissionRejection::Forbidden(Action::ManagePermissions))
+        ));
+    }
+
+    #[tokio::test]
+    async fn missing_users_are_unauthenticated() {
+        assert!(matches!(
+            guard(None, || Ok(true)).await,
+            Err(PermissionRejection::Unauthenticated)
+        ));
+    }
+
+    #[tokio::test]
+    async fn members_of_permitted_roles_pass() {
+        let current = tenant_user(Uuid::new_v4());
+        let user = Grantee::User(current.user.expect("Expected user"));
+        let role = Grantee::Role(Uuid::new_v4());
+        let state = granted(vec![
+            Change::AddGrant(role, Action::ManagePermissions),
+            Change::ConnectGrantees(user, role),
+        ])
+        .await;
+
+        let guard: Result<RequirePermission<ManagePermissions>, _> =
+            RequirePermission::from_request_parts(&mut parts(Some(current)), &state).await;
+        assert!(guard.is_ok());
+    }
+
+    #[tokio::test]
+    async fn users_of_permitted_tenants_pass() {
+        let current = tenant_user(Uuid::new_v4());
+        let tenant = Grantee::Tenant(current.tenant.expect("Expected tenant"));
+        let state = granted(vec![Change::AddGrant(tenant, Action::ManagePermissions)]).await;
+
+        let guard: Result<RequirePermission<ManagePermissions>, _> =
+            RequirePermission::from_request_parts(&mut parts(Some(current)), &state).await;
+        assert!(guard.is_ok());
+
+        // a user of another tenant is not
+        let other = tenant_user(Uuid::new_v4());
+        let guard: Result<RequirePermission<ManagePermissions>, _> =
+            RequirePermission::from_request_parts(&mut parts(Some(other)), &state).await;
+        assert!(matches!(guard, Err(PermissionRejection::Forbidden(_))));
+    }
+}
@@ -0,0 +1,209 @@
+use crate::services::auth::io::{AccountError, AccountIO};
+use crate::services::permission::actions::ManagePermissions;
+use crate::services::permission::{Action, Grantee, Permissions};
+use crate::types::{CurrentUser, Services};
+use crate::web::permission_guard::{ensure_permitted, PermissionRejection, RequirePermission};
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{Json, Router};
+use permission::{Audit, Change, PermissionChecker, PermissionError};
+use serde::Deserialize;
+use sqlx::PgConnection;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// routes changing permissions, they require the user to be permitted to manage permissions
+///
+/// grantees outside the tenant of the user additionally require [Action::ManageAllPermissions]
+pub fn permission_router() -> Router<Arc<Services>> {
+    Router::new()
+        .route("/grant", post(grant))
+        .route("/revoke", post(revoke))
+}
+
+/// permits a user or role to perform an action
+#[axum::debug_handler(state = Arc<Services>)]
+async fn grant(
+    State(state): State<Arc<Services>>,
+    RequirePermission(current, _): RequirePermission<ManagePermissions>,
+    permissions: Permissions,
+    Json(grant): Json<GrantPayload>,
+) -> Response {
+    change(
+        &state,
+        &permissions,
+        &current,
+        grant.grantee,
+        Change::AddGrant(grant.grantee, grant.action),
+    )
+    .await
+}
+
+/// removes a grant, actions inherited from roles stay permitted
+#[axum::debug_handler(state = Arc<Services>)]
+async fn revoke(
+    State(state): State<Arc<Services>>,
+    RequirePermission(current, _): RequirePermission<ManagePermissions>,
+    permissions: Permissions,
+    Json(grant): Json<GrantPayload>,
+) -> Response {
+    change(
+        &state,
+        &permissions,
+        &current,
+        grant.grantee,
+        Change::RemoveGrant(grant.grantee, grant.action),
+    )
+    .await
+}
+
+async fn change(
+    state: &Services,
+    permissions: &Permissions,
+    current: &CurrentUser,
+    target: Grantee,
+    change: Change<Grantee, Action>,
+) -> Response {
+    // the request transaction is only committed after responding,
+    // CanDo would be ahead of it until then
+    let Ok(mut conn) = state.postgres.acquire().await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    let Ok(tenant) = tenant_of(state, &target, &mut conn).await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    if let Err(rejection) = ensure_in_scope(&**permissions, current, tenant) {
+        return rejection.into_response();
+    }
+    let actor = current
+        .user
+        .map(Grantee::User)
+        .expect("Expected RequirePermission to require a user");
+
+    match permissions
+        .change(vec![change], &Audit::new(actor), &mut conn)
+        .await
+    {
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        // eg. revoking a grant which does not exist
+        Err(PermissionError::Rejected(_)) => StatusCode::CONFLICT.into_response(),
+        Err(PermissionError::Failed) => StatusCode::SERVICE_UNAVAILABLE.into_response(),
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// the tenant a grantee belongs to, None for roles which are shared by all tenants
+async fn tenant_of(
+    state: &Services,
+    grantee: &Grantee,
+    conn: &mut PgConnection,
+) -> Result<Option<Uuid>, AccountError> {
+    match grantee {
+        Grantee::Tenant(tenant) => Ok(Some(*tenant)),
+        Grantee::Role(_) => Ok(None),
+        Grantee::User(user) => match state
+            .account_provider
+            .read()
+            .await
+            .get_tenant(user, conn)
+            .await
+        {
+            Ok(tenant) => Ok(Some(tenant)),
+            // unknown users do not belong to the tenant of anyone
+            Err(AccountError::NotFound) => Ok(None),
+            Err(error) => Err(error),
+        },
+    }
+}
+
+/// permits changes of grantees in the tenant of the current user,
+/// any other grantee requires the user to be permitted [Action::ManageAllPermissions]
+fn ensure_in_scope(
+    checker: &impl PermissionChecker<Grantee, Action>,
+    current: &CurrentUser,
+    tenant: Option<Uuid>,
+) -> Result<(), PermissionRejection> {
+    if tenant.is_some() && tenant == current.tenant {
+        return Ok(());
+    }
+    let user = current
+        .user
+        .map(Grantee::User)
+        .expect("Expected RequirePermission to require a user");
+    ensure_permitted(checker, [&user], Action::ManageAllPermissions)
+}
+
+#[derive(Debug, Deserialize)]
+struct GrantPayload {
+    grantee: Grantee,
+    action: Action,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ensure_in_scope;
+    use crate::services::permission::{Action, Grantee};
+    use crate::types::CurrentUser;
+    use crate::web::permission_guard::PermissionRejection;
+    use permission::{Audit, Change, MemoryIO, Permission};
+    use uuid::Uuid;
+
+    fn tenant_user() -> CurrentUser {
+        CurrentUser {
+            account: Uuid::new_v4(),
+            user: Some(Uuid::new_v4()),
+            tenant: Some(Uuid::new_v4()),
+        }
+    }
+
+    async fn granted(
+        changes: Vec<Change<Grantee, Action>>,
+    ) -> Permission<Grantee, Action, MemoryIO<Grantee, Action>> {
+        let permission = Permission::new(MemoryIO::new(), &mut ())
+            .await
+            .expect("Expected permission");
+        permission
+            .change(changes, &Audit::new(Grantee::User(Uuid::new_v4())), &mut ())
+            .await
+            .expect("Expected changes to be applied");
+        permission
+    }
+
+    #[tokio::test]
+    async fn tenant_admins_manage_their_own_tenant() {
+        let current = tenant_user();
+        let tenant = Grantee::Tenant(current.tenant.expect("Expected tenant"));
+        let permission = granted(vec![Change::AddGrant(tenant, Action::ManagePermissions)]).await;
+
+        assert!(ensure_in_scope(&permission, &current, current.tenant).is_ok());
+    }
+
+    #[tokio::test]
+    async fn tenant_admins_can_not_manage_other_tenants() {
+        let current = tenant_user();
+        let tenant = Grantee::Tenant(current.tenant.expect("Expected tenant"));
+        let permission = granted(vec![Change::AddGrant(tenant, Action::ManagePermissions)]).await;
+
+        assert!(matches!(
+            ensure_in_scope(&permission, &current, Some(Uuid::new_v4())),
+            Err(PermissionRejection::Forbidden(Action::ManageAllPermissions))
+        ));
+        // roles are shared by all tenants
+        assert!(matches!(
+            ensure_in_scope(&permission, &current, None),
+            Err(PermissionRejection::Forbidden(Action::ManageAllPermissions))
+        ));
+    }
+
+    #[tokio::test]
+    async fn users_managing_all_permissions_manage_other_tenants() {
+        let current = tenant_user();
+        let user = Grantee::User(current.user.expect("Expected user"));
+        let permission = granted(vec![Change::AddGrant(user, Action::ManageAllPermissions)]).await;
+
+        assert!(ensure_in_scope(&permission, &current, Some(Uuid::new_v4())).is_ok());
+        assert!(ensure_in_scope(&permission, &current, None).is_ok());
+    }
+}
@@ -0,0 +1,135 @@
+use crate::Change;
+use std::time::SystemTime;
+
+/// who changed permissions, when and why
+///
+/// persisted by IO together with the batch of changes it belongs to
+#[derive(Clone, Debug)]
+pub struct Audit<GranteeId> {
+    /// None for changes made by the system itself, eg. during setup
+    pub actor: Option<GranteeId>,
+    pub at: SystemTime,
+    pub reason: Option<String>,
+    pub request_id: Option<String>,
+}
+
+impl<GranteeId> Audit<GranteeId> {
+    /// audit of a change made now by actor
+    pub fn new(actor: GranteeId) -> Self {
+        Self {
+            actor: Some(actor),
+            at: SystemTime::now(),
+            reason: None,
+            request_id: None,
+        }
+    }
+
+    /// audit of a change made now by the system itself
+    pub fn system() -> Self {
+        Self {
+            actor: None,
+            at: SystemTime::now(),
+            reason: None,
+            request_id: None,
+        }
+    }
+
+    pub fn with_reason(mut self, reason: impl Into<String>) -> Self {
+        self.reason = Some(reason.into());
+        self
+    }
+
+    pub fn with_request_id(mut self, request_id: impl Into<String>) -> Self {
+        self.request_id = Some(request_id.into());
+        self
+    }
+}
+
+/// a persisted batch of changes together with its audit
+///
+/// inverse reverts the batch, see [undo](crate::Permission::undo)
+#[derive(Clone, Debug)]
+pub struct AuditedBatch<GranteeId, ActionId> {
+    pub audit: Audit<GranteeId>,
+    pub changes: Vec<Change<GranteeId, ActionId>>,
+    pub inverse: Vec<Change<GranteeId, ActionId>>,
+}
+
+/// selects the audited batches touching a grantee or an action
+#[derive(Copy, Clone, Debug)]
+pub enum AuditQuery<GranteeId, ActionId> {
+    Grantee(GranteeId),
+    Action(ActionId),
+}
+
+impl<GranteeId: PartialEq, ActionId: PartialEq> AuditQuery<GranteeId, ActionId> {
+    /// whether change touches the queried grantee or action
+    ///
+    /// a [Clear](Change::Clear) touches everything
+    pub fn matches(&self, change: &Change<GranteeId, ActionId>) -> bool {
+        match (self, change) {
+            (_, Change::Clear) => true,
+            (AuditQuery::Grantee(queried), Change::RemoveGrantee(grantee_id))
+            | (AuditQuery::Grantee(queried), Change::AddGrant(grantee_id, _))
+            | (AuditQuery::Grantee(queried), Change::RemoveGrant(grantee_id, _))
+            | (AuditQuery::Grantee(queried), Change::AddRoot(grantee_id))
+            | (AuditQuery::Grantee(queried), Change::RemoveRoot(grantee_id)) => {
+                queried == grantee_id
+            }
+            (AuditQuery::Grantee(queried), Change::ConnectGrantees(grantee_id, grantee_of_id))
+            | (
+                AuditQuery::Grantee(queried),
+                Change::DisconnectGrantees(grantee_id, grantee_of_id),
+            ) => queried == grantee_id || queried == grantee_of_id,
+            (AuditQuery::Action(queried), Change::RemoveAction(action_id))
+            | (AuditQuery::Action(queried), Change::AddGrant(_, action_id))
+            | (AuditQuery::Action(queried), Change::RemoveGrant(_, action_id)) => {
+                queried == action_id
+            }
+            (
+                AuditQuery::Action(queried),
+                Change::ConnectActions(main_action_id, sub_action_id),
+            )
+            | (
+                AuditQuery::Action(queried),
+                Change::DisconnectActions(main_action_id, sub_action_id),
+            ) => queried == main_action_id || queried == sub_action_id,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{AuditQuery, Change};
+
+    #[test]
+    fn queries_match_both_sides_of_connections() {
+        let query: AuditQuery<u32, u32> = AuditQuery::Grantee(2);
+        assert!(query.matches(&Change::ConnectGrantees(1, 2)));
+        assert!(query.matches(&Change::DisconnectGrantees(2, 1)));
+        assert!(!query.matches(&Change::ConnectGrantees(1, 3)));
+
+        let query: AuditQuery<u32, u32> = AuditQuery::Action(2);
+        assert!(query.matches(&Change::ConnectActions(2, 1)));
+        assert!(query.matches(&Change::DisconnectActions(1, 2)));
+    }
+
+    #[test]
+    fn queries_do_not_confuse_grantees_and_actions() {
+        let query: AuditQuery<u32, u32> = AuditQuery::Grantee(1);
+        assert!(query.matches(&Change::AddGrant(1, 2)));
+        assert!(!query.matches(&Change::AddGrant(2, 1)));
+        assert!(!query.matches(&Change::RemoveAction(1)));
+
+        let query: AuditQuery<u32, u32> = AuditQuery::Action(1);
+        assert!(query.matches(&Change::RemoveGrant(2, 1)));
+        assert!(!query.matches(&Change::AddRoot(1)));
+    }
+
+    #[test]
+    fn clear_matches_everything() {
+        let query: AuditQuery<u32, u32> = AuditQuery::Grantee(1);
+        assert!(query.matches(&Change::Clear));
+    }
+}
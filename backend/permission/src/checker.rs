@@ -0,0 +1,31 @@
+use crate::PermissionError;
+use can_do::Replay;
+#[cfg(any(test, feature = "mock"))]
+use mockall::automock;
+
+/// read only access to permissions
+///
+/// handlers should depend on this instead of [Permission](crate::Permission)
+/// thus they can be tested with the generated MockPermissionChecker, enabled by the mock feature
+#[cfg_attr(any(test, feature = "mock"), automock)]
+pub trait PermissionChecker<GranteeId, ActionId> {
+    /// checks if a grantee can perform an action
+    fn check(&self, grantee_id: &GranteeId, action_id: &ActionId) -> Result<bool, PermissionError>;
+
+    /// checks if a grantee can perform each of the actions, answered by the same state
+    fn check_many(
+        &self,
+        grantee_id: &GranteeId,
+        action_ids: &[ActionId],
+    ) -> Result<Vec<bool>, PermissionError>;
+
+    /// returns the shortest chain of memberships, grant and action inheritances allowing an action
+    ///
+    /// returns None if the grantee can not perform the action
+    #[allow(clippy::type_complexity)]
+    fn explain(
+        &self,
+        grantee_id: &GranteeId,
+        action_id: &ActionId,
+    ) -> Result<Option<Vec<Replay<GranteeId, ActionId>>>, PermissionError>;
+}
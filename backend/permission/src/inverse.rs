@@ -0,0 +1,194 @@
+use crate::Change;
+use can_do::{CanDo, Replay};
+use std::hash::Hash;
+
+/// returns the changes reverting change
+///
+/// can_do has to be the state right before change is applied,
+/// edges removed by change are captured from it
+pub(crate) fn inverse_of<GranteeId, ActionId>(
+    can_do: &CanDo<GranteeId, ActionId>,
+    change: &Change<GranteeId, ActionId>,
+) -> Vec<Change<GranteeId, ActionId>>
+where
+    GranteeId: Hash + Eq + Copy,
+    ActionId: Hash + Eq + Copy,
+{
+    let grantee_has = |grantee_id: &GranteeId, edge: Replay<GranteeId, ActionId>| {
+        can_do
+            .grantee_edges(grantee_id)
+            .is_ok_and(|edges| edges.contains(&edge))
+    };
+
+    match *change {
+        Change::Clear => {
+            let mut replays = can_do.replays();
+            // replays skip the groups of roots
+            let roots: Vec<GranteeId> = replays
+                .iter()
+                .filter_map(|replay| match replay {
+                    Replay::Root(grantee_id) => Some(*grantee_id),
+                    _ => None,
+                })
+                .collect();
+            for root in roots {
+                replays.extend(can_do.grantee_edges(&root).into_iter().flatten().filter(
+                    |edge| matches!(edge, Replay::ConnectGrantees(grantee_id, _) if *grantee_id == root),
+                ));
+            }
+            replays.into_iter().map(Change::from).collect()
+        }
+        Change::RemoveGrantee(grantee_id) => can_do
+            .grantee_edges(&grantee_id)
+            .into_iter()
+            .flatten()
+            .map(Change::from)
+            .collect(),
+        Change::RemoveAction(action_id) => can_do
+            .action_edges(&action_id)
+            .into_iter()
+            .flatten()
+            .map(Change::from)
+            .collect(),
+        Change::AddGrant(grantee_id, action_id) => vec![Change::RemoveGrant(grantee_id, action_id)],
+        Change::RemoveGrant(grantee_id, action_id) => {
+            if grantee_has(&grantee_id, Replay::Grant(grantee_id, action_id)) {
+                vec![Change::AddGrant(grantee_id, action_id)]
+            } else {
+                vec![]
+            }
+        }
+        Change::ConnectGrantees(grantee_id, grantee_of_id) => {
+            vec![Change::DisconnectGrantees(grantee_id, grantee_of_id)]
+        }
+        Change::DisconnectGrantees(grantee_id, grantee_of_id) => {
+            if grantee_has(
+                &grantee_id,
+                Replay::ConnectGrantees(grantee_id, grantee_of_id),
+            ) {
+                vec![Change::ConnectGrantees(grantee_id, grantee_of_id)]
+            } else {
+                vec![]
+            }
+        }
+        Change::ConnectActions(main_action_id, sub_action_id) => {
+            vec![Change::DisconnectActions(main_action_id, sub_action_id)]
+        }
+        Change::DisconnectActions(main_action_id, sub_action_id) => {
+            if can_do.action_edges(&main_action_id).is_ok_and(|edges| {
+                edges.contains(&Replay::ConnectActions(main_action_id, sub_action_id))
+            }) {
+                vec![Change::ConnectActions(main_action_id, sub_action_id)]
+            } else {
+                vec![]
+            }
+        }
+        Change::AddRoot(grantee_id) => {
+            if grantee_has(&grantee_id, Replay::Root(grantee_id)) {
+                vec![]
+            } else {
+                vec![Change::RemoveRoot(grantee_id)]
+            }
+        }
+        Change::RemoveRoot(grantee_id) => {
+            if grantee_has(&grantee_id, Replay::Root(grantee_id)) {
+                vec![Change::AddRoot(grantee_id)]
+            } else {
+                vec![]
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::inverse_of;
+    use crate::lr::try_absorb_change;
+    use crate::Change;
+    use can_do::CanDo;
+
+    /// applies changes, then their inverse and compares the replays
+    fn assert_reverted(setup: &[Change<u32, u32>], changes: &[Change<u32, u32>]) {
+        let mut can_do = CanDo::new();
+        for change in setup {
+            try_absorb_change(&mut can_do, change).expect("Expected valid setup");
+        }
+        let mut before = can_do.replays();
+
+        let mut inverse = vec![];
+        for change in changes {
+            inverse.push(inverse_of(&can_do, change));
+            try_absorb_change(&mut can_do, change).expect("Expected valid change");
+        }
+        for change in inverse.into_iter().rev().flatten() {
+            try_absorb_change(&mut can_do, &change).expect("Expected valid inverse");
+        }
+
+        let mut after = can_do.replays();
+        before.sort_by_key(|replay| format!("{replay:?}"));
+        after.sort_by_key(|replay| format!("{replay:?}"));
+        assert_eq!(before, after);
+    }
+
+    #[test]
+    fn removed_grantees_are_restored_with_all_edges() {
+        assert_reverted(
+            &[
+                Change::ConnectGrantees(1, 2),
+                Change::ConnectGrantees(2, 3),
+                Change::AddGrant(2, 10),
+                Change::AddRoot(3),
+            ],
+            &[Change::RemoveGrantee(2)],
+        );
+    }
+
+    #[test]
+    fn removed_actions_are_restored_with_all_edges() {
+        assert_reverted(
+            &[
+                Change::ConnectActions(10, 11),
+                Change::ConnectActions(11, 12),
+                Change::AddGrant(1, 11),
+            ],
+            &[Change::RemoveAction(11)],
+        );
+    }
+
+    #[test]
+    fn batches_are_reverted_in_reverse_order() {
+        assert_reverted(
+            &[Change::AddGrant(1, 10), Change::AddRoot(1)],
+            &[
+                Change::RemoveGrant(1, 10),
+                Change::AddGrant(1, 11),
+                Change::ConnectGrantees(2, 1),
+                Change::RemoveRoot(1),
+                Change::DisconnectGrantees(2, 1),
+            ],
+        );
+    }
+
+    #[test]
+    fn clear_is_reverted() {
+        assert_reverted(
+            &[
+                Change::ConnectGrantees(1, 2),
+                Change::ConnectGrantees(2, 3),
+                Change::AddGrant(1, 10),
+                Change::ConnectActions(10, 11),
+                Change::AddRoot(2),
+            ],
+            &[Change::Clear],
+        );
+    }
+
+    #[test]
+    fn removing_missing_edges_has_no_inverse() {
+        let mut can_do: CanDo<u32, u32> = CanDo::new();
+        can_do.add_grant(&1, &10);
+
+        assert!(inverse_of(&can_do, &Change::DisconnectGrantees(1, 2)).is_empty());
+        assert!(inverse_of(&can_do, &Change::RemoveRoot(1)).is_empty());
+    }
+}
@@ -1,19 +1,68 @@
-use crate::Change;
+use crate::{Audit, AuditQuery, AuditedBatch, Change};
 use thiserror::Error;
 
+/// persists changes of a [Permission](crate::Permission)
+///
+/// implementors choose their own storage and the context needed to access it
+/// eg. a database connection or transaction
 pub trait IO<GranteeId, ActionId> {
-    fn read_all(&mut self) -> Box<dyn Iterator<Item = Change<GranteeId, ActionId>>>;
-    fn write(&mut self, change: &Change<GranteeId, ActionId>) -> Result<(), IOError>;
-    fn flush(&mut self) -> Result<(), IOError>;
-    fn clear(&mut self) -> Result<(), IOError>;
+    type Ctx;
+
+    /// returns every persisted change in order
+    async fn read_all(
+        &mut self,
+        ctx: &mut Self::Ctx,
+    ) -> Result<Vec<Change<GranteeId, ActionId>>, IOError>;
+
+    /// adds a change to the current batch
+    async fn write(
+        &mut self,
+        change: &Change<GranteeId, ActionId>,
+        ctx: &mut Self::Ctx,
+    ) -> Result<(), IOError>;
+
+    /// persists the current batch together with its audit and the batch reverting it
+    async fn flush(
+        &mut self,
+        audit: &Audit<GranteeId>,
+        inverse: &[Change<GranteeId, ActionId>],
+        ctx: &mut Self::Ctx,
+    ) -> Result<(), IOError>;
+
+    /// discards the current batch
+    async fn clear(&mut self, ctx: &mut Self::Ctx) -> Result<(), IOError>;
+
+    /// replaces every persisted change with the given snapshot
+    ///
+    /// [read_all](IO::read_all) returns the snapshot followed by all changes written afterwards
+    async fn checkpoint(
+        &mut self,
+        snapshot: &[Change<GranteeId, ActionId>],
+        ctx: &mut Self::Ctx,
+    ) -> Result<(), IOError>;
+
+    /// returns every flushed batch touching the queried grantee or action, oldest first
+    ///
+    /// unlike the changes themselves audited batches are kept on [checkpoint](IO::checkpoint)
+    async fn history(
+        &mut self,
+        query: &AuditQuery<GranteeId, ActionId>,
+        ctx: &mut Self::Ctx,
+    ) -> Result<Vec<AuditedBatch<GranteeId, ActionId>>, IOError>;
 }
 
 #[derive(Error, Debug)]
 pub enum IOError {
+    #[error("Error while reading from IO")]
+    Read,
     #[error("Error while writing to IO")]
     Write,
     #[error("Error while flushing IO")]
     Flush,
     #[error("Error while clearing IO")]
     Clear,
+    #[error("Error while writing checkpoint to IO")]
+    Checkpoint,
+    #[error("Error while reading history from IO")]
+    History,
 }
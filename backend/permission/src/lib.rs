@@ -1,91 +1,437 @@
-use can_do::CanDo;
-use left_right::{ReadHandle, WriteHandle};
+#![allow(async_fn_in_trait)]
+
+use can_do::{CanDo, Replay};
+use inverse::inverse_of;
+use left_right::{ReadHandle, ReadHandleFactory, WriteHandle};
+use lr::{try_absorb_change, Op, State};
 use std::hash::Hash;
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use thread_local::ThreadLocal;
+use tokio::sync::{broadcast, watch};
 
+mod audit;
+mod checker;
+mod inverse;
 mod io;
 mod lr;
+mod memory;
 mod types;
+#[cfg(feature = "serde")]
+mod wire;
+
+/// number of batches a [subscriber](Permission::subscribe) may lag behind
+pub const BATCH_CAPACITY: usize = 64;
 
+pub use audit::*;
+pub use checker::*;
 pub use io::*;
+pub use memory::*;
 pub use types::*;
 
-pub struct Permission<GranteeId, ActionId>
+/// shared handle to an in-memory CanDo persisted by IO
+///
+/// cloning is cheap, all clones share the same CanDo and IO
+/// reads are lock free, every thread lazily creates its own ReadHandle
+/// writes are serialized
+/// every applied batch is published to [subscribers](Self::subscribe) with a new version
+pub struct Permission<GranteeId, ActionId, Io>
+where
+    GranteeId: Hash + Eq + Copy + Send + Sync,
+    ActionId: Hash + Eq + Copy + Send + Sync,
+    Io: IO<GranteeId, ActionId>,
+{
+    inner: Arc<Inner<GranteeId, ActionId, Io>>,
+}
+
+struct Inner<GranteeId, ActionId, Io>
 where
-    GranteeId: Hash + Eq + Copy,
-    ActionId: Hash + Eq + Copy,
+    GranteeId: Hash + Eq + Copy + Send + Sync,
+    ActionId: Hash + Eq + Copy + Send + Sync,
 {
-    is_failed: bool,
-    writer: Mutex<WriteHandle<CanDo<GranteeId, ActionId>, Change<GranteeId, ActionId>>>,
-    reader: ReadHandle<CanDo<GranteeId, ActionId>>,
-    io: Mutex<Box<dyn IO<GranteeId, ActionId>>>,
+    is_failed: AtomicBool,
+    retry: Option<RetryPolicy>,
+    writer: Mutex<WriteHandle<State<GranteeId, ActionId>, Op<GranteeId, ActionId>>>,
+    readers: ReadHandleFactory<State<GranteeId, ActionId>>,
+    reader: ThreadLocal<ReadHandle<State<GranteeId, ActionId>>>,
+    // the io lock is held for the whole change to keep IO and CanDo in the same order
+    io: tokio::sync::Mutex<Io>,
+    batches: broadcast::Sender<ChangeBatch<GranteeId, ActionId>>,
+    version: watch::Sender<u64>,
 }
 
-impl<GranteeId, ActionId> Permission<GranteeId, ActionId>
+impl<GranteeId, ActionId, Io> Clone for Permission<GranteeId, ActionId, Io>
 where
-    GranteeId: Hash + Eq + Copy,
-    ActionId: Hash + Eq + Copy,
+    GranteeId: Hash + Eq + Copy + Send + Sync,
+    ActionId: Hash + Eq + Copy + Send + Sync,
+    Io: IO<GranteeId, ActionId>,
 {
-    pub fn new(mut io: Box<dyn IO<GranteeId, ActionId>>) -> Self {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<GranteeId, ActionId, Io> Permission<GranteeId, ActionId, Io>
+where
+    GranteeId: Hash + Eq + Copy + Send + Sync,
+    ActionId: Hash + Eq + Copy + Send + Sync,
+    Io: IO<GranteeId, ActionId>,
+{
+    /// replays all persisted changes into a new CanDo
+    pub async fn new(io: Io, ctx: &mut Io::Ctx) -> Result<Self, PermissionError> {
+        Self::build(io, None, ctx).await
+    }
+
+    /// replays all persisted changes into a new CanDo
+    ///
+    /// once failed, recovery is retried according to the given policy
+    pub async fn with_retry(
+        io: Io,
+        retry: RetryPolicy,
+        ctx: &mut Io::Ctx,
+    ) -> Result<Self, PermissionError> {
+        Self::build(io, Some(retry), ctx).await
+    }
+
+    async fn build(
+        mut io: Io,
+        retry: Option<RetryPolicy>,
+        ctx: &mut Io::Ctx,
+    ) -> Result<Self, PermissionError> {
         let (mut writer, reader) =
-            left_right::new::<CanDo<GranteeId, ActionId>, Change<GranteeId, ActionId>>();
+            left_right::new::<State<GranteeId, ActionId>, Op<GranteeId, ActionId>>();
 
-        for change in &mut io.read_all() {
-            writer.append(change);
+        for change in io.read_all(ctx).await.map_err(PermissionError::Io)? {
+            writer.append(Op::Change(change));
         }
         writer.publish();
 
-        Permission {
-            is_failed: false,
-            writer: Mutex::new(writer),
-            reader,
-            io: Mutex::new(io),
+        Ok(Permission {
+            inner: Arc::new(Inner {
+                is_failed: AtomicBool::new(false),
+                retry,
+                writer: Mutex::new(writer),
+                readers: reader.factory(),
+                reader: ThreadLocal::new(),
+                io: tokio::sync::Mutex::new(io),
+                batches: broadcast::channel(BATCH_CAPACITY).0,
+                version: watch::channel(0).0,
+            }),
+        })
+    }
+
+    /// returns the ReadHandle of the current thread
+    fn reader(&self) -> &ReadHandle<State<GranteeId, ActionId>> {
+        self.inner.reader.get_or(|| self.inner.readers.handle())
+    }
+
+    fn is_failed(&self) -> bool {
+        self.inner.is_failed.load(Ordering::Acquire)
+    }
+
+    /// version of the currently published CanDo
+    ///
+    /// starts at 0 and is incremented by every applied batch
+    pub fn version(&self) -> u64 {
+        *self.inner.version.borrow()
+    }
+
+    /// receives every batch applied after subscribing
+    ///
+    /// receivers lagging more than [BATCH_CAPACITY] batches behind miss batches,
+    /// they should treat the current state as new, just like after a [Change::Clear]
+    pub fn subscribe(&self) -> broadcast::Receiver<ChangeBatch<GranteeId, ActionId>> {
+        self.inner.batches.subscribe()
+    }
+
+    /// receives the latest version, for those only interested in whether something changed
+    pub fn watch(&self) -> watch::Receiver<u64> {
+        self.inner.version.subscribe()
+    }
+
+    /// applies a batch to CanDo and notifies subscribers
+    ///
+    /// returns the version of the batch
+    fn publish(&self, changes: Vec<Change<GranteeId, ActionId>>) -> u64 {
+        let mut can_do_writer = self
+            .inner
+            .writer
+            .lock()
+            .expect("Expected to get exclusive write lock");
+        // still holding the writer to keep versions in the order of batches
+        let version = self.version() + 1;
+        for change in &changes {
+            can_do_writer.append(Op::Change(*change));
+        }
+        can_do_writer.append(Op::Version(version));
+        can_do_writer.publish();
+
+        self.inner.version.send_replace(version);
+        // there might be no subscribers
+        let _ = self.inner.batches.send(ChangeBatch {
+            version,
+            changes: changes.into(),
+        });
+        version
+    }
+
+    /// leaves the failed state by rebuilding CanDo from IO
+    ///
+    /// the pending batch of IO is discarded and every persisted change is replayed
+    /// readers keep seeing the old CanDo until the rebuilt one is published
+    /// may also be called while not failed to resync with IO
+    pub async fn recover(&self, ctx: &mut Io::Ctx) -> Result<(), PermissionError> {
+        let mut io = self.inner.io.lock().await;
+        self.rebuild(&mut io, ctx).await
+    }
+
+    async fn rebuild(&self, io: &mut Io, ctx: &mut Io::Ctx) -> Result<(), PermissionError> {
+        io.clear(ctx).await.map_err(PermissionError::Io)?;
+        let mut changes = io.read_all(ctx).await.map_err(PermissionError::Io)?;
+        changes.insert(0, Change::Clear);
+        self.publish(changes);
+
+        self.inner.is_failed.store(false, Ordering::Release);
+        Ok(())
+    }
+
+    /// recovers according to the RetryPolicy if failed
+    ///
+    /// returns Failed if there is no policy or every attempt failed
+    async fn ensure_ready(&self, io: &mut Io, ctx: &mut Io::Ctx) -> Result<(), PermissionError> {
+        if !self.is_failed() {
+            return Ok(());
+        }
+        let Some(retry) = self.inner.retry else {
+            return Err(PermissionError::Failed);
+        };
+
+        let mut backoff = retry.backoff;
+        for attempt in 0..retry.attempts {
+            if attempt > 0 {
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(retry.max_backoff);
+            }
+            if self.rebuild(io, ctx).await.is_ok() {
+                return Ok(());
+            }
+        }
+        Err(PermissionError::Failed)
+    }
+
+    /// validates changes against a copy of the current CanDo
+    ///
+    /// every invalid change is reported, later changes are validated as if it was skipped
+    /// returns the CanDo resulting from a valid batch together with the inverse batch
+    #[allow(clippy::type_complexity)]
+    fn validate(
+        &self,
+        changes: &[Change<GranteeId, ActionId>],
+    ) -> Result<(CanDo<GranteeId, ActionId>, Vec<Change<GranteeId, ActionId>>), PermissionError>
+    {
+        let mut can_do = self
+            .reader()
+            .enter()
+            .expect("Expected to get ReadGuard on CanDo")
+            .can_do
+            .clone();
+
+        let mut errors = vec![];
+        let mut inverse = vec![];
+        for (index, change) in changes.iter().enumerate() {
+            // edges removed by the change are captured before applying it
+            let change_inverse = inverse_of(&can_do, change);
+            match try_absorb_change(&mut can_do, change) {
+                Ok(()) => inverse.push(change_inverse),
+                Err(error) => errors.push(ChangeError { index, error }),
+            }
+        }
+
+        if errors.is_empty() {
+            Ok((can_do, inverse.into_iter().rev().flatten().collect()))
+        } else {
+            Err(PermissionError::Rejected(errors))
         }
     }
 
+    /// validates a batch and returns what it would change without applying it
+    ///
+    /// returns Rejected if the batch is invalid, see [change](Self::change)
+    pub fn dry_run(
+        &self,
+        changes: &[Change<GranteeId, ActionId>],
+    ) -> Result<Diff<GranteeId, ActionId>, PermissionError> {
+        if self.is_failed() {
+            return Err(PermissionError::Failed);
+        }
+
+        let before = self
+            .reader()
+            .enter()
+            .expect("Expected to get ReadGuard on CanDo")
+            .can_do
+            .replays();
+        let after = self.validate(changes)?.0.replays();
+        Ok(Diff::between(before, after))
+    }
+
     /// persist event and apply changes to database
     /// batch a list of changes
-    pub fn change(
-        &mut self,
+    ///
+    /// the batch is validated against the current CanDo first
+    /// if any change is invalid, eg. removing an unknown grant, nothing is persisted or applied
+    /// and Rejected is returned with an error for every invalid change
+    ///
+    /// changes are only applied to CanDo after they have been flushed to IO
+    /// if ctx is a transaction use [prepare](Self::prepare) instead,
+    /// otherwise rolling it back leaves CanDo ahead of the persisted state
+    ///
+    /// returns the version of the batch, see [check_at_least](Self::check_at_least)
+    pub async fn change(
+        &self,
         changes: Vec<Change<GranteeId, ActionId>>,
-    ) -> Result<(), PermissionError> {
-        if self.is_failed {
-            return Err(PermissionError::Failed);
-        }
-        let io = &mut self
-            .io
-            .get_mut()
-            .expect("Expected to get exclusive write lock");
+        audit: &Audit<GranteeId>,
+        ctx: &mut Io::Ctx,
+    ) -> Result<u64, PermissionError> {
+        Ok(self.prepare(changes, audit, ctx).await?.publish())
+    }
+
+    /// validates and flushes a batch like [change](Self::change) without applying it to CanDo
+    ///
+    /// for ctx being a transaction, the returned batch has to be published once it has been committed
+    /// dropping it instead, eg. after a rollback, leaves CanDo untouched
+    /// other changes wait until the batch is either published or dropped
+    pub async fn prepare(
+        &self,
+        changes: Vec<Change<GranteeId, ActionId>>,
+        audit: &Audit<GranteeId>,
+        ctx: &mut Io::Ctx,
+    ) -> Result<PendingChange<'_, GranteeId, ActionId, Io>, PermissionError> {
+        let mut io = self.inner.io.lock().await;
+        self.ensure_ready(&mut io, ctx).await?;
+        // holding the io lock, no other batch is applied before this one
+        let (_, inverse) = self.validate(&changes)?;
 
         // batch write event into IO
         for change in &changes {
-            if let Err(write_error) = io.write(change) {
+            if let Err(write_error) = io.write(change, ctx).await {
+                if let Err(clear_error) = io.clear(ctx).await {
+                    self.inner.is_failed.store(true, Ordering::Release);
+                    return Err(PermissionError::Io(clear_error));
+                }
                 return Err(PermissionError::Io(write_error));
             }
         }
 
-        let can_do_writer = &mut self
-            .writer
-            .get_mut()
-            .expect("Expected to get exclusive write lock");
-
-        // batch write event into CanDo
-        for change in changes {
-            can_do_writer.append(change);
-        }
-
-        if let Err(flush_error) = io.flush() {
-            if let Err(clear_error) = io.clear() {
+        if let Err(flush_error) = io.flush(audit, &inverse, ctx).await {
+            if let Err(clear_error) = io.clear(ctx).await {
                 // could neither flush nor clear
-                self.is_failed = true;
+                self.inner.is_failed.store(true, Ordering::Release);
                 return Err(PermissionError::Io(clear_error));
             };
 
             return Err(PermissionError::Io(flush_error));
         }
-        // publish pending changes to CanDo
-        can_do_writer.flush();
-        Ok(())
+
+        Ok(PendingChange {
+            permission: self,
+            _io: io,
+            changes,
+        })
+    }
+
+    /// reverts a persisted batch by applying its inverse as a new batch
+    ///
+    /// the inverse has been captured when the batch was applied, eg. all edges of a removed grantee
+    /// it is validated like any other batch, thus undoing fails if the state has diverged since
+    /// eg. a grant added by the batch has already been removed
+    pub async fn undo(
+        &self,
+        batch: &AuditedBatch<GranteeId, ActionId>,
+        audit: &Audit<GranteeId>,
+        ctx: &mut Io::Ctx,
+    ) -> Result<u64, PermissionError> {
+        self.change(batch.inverse.clone(), audit, ctx).await
+    }
+
+    /// applies changes that have already been persisted elsewhere, eg. by another instance
+    ///
+    /// nothing is written to IO
+    /// returns Failed while failed, [recover](Self::recover) picks up these changes from IO
+    /// returns the version of the batch otherwise
+    pub async fn apply(
+        &self,
+        changes: Vec<Change<GranteeId, ActionId>>,
+    ) -> Result<u64, PermissionError> {
+        Ok(self
+            .apply_if(changes, || true)
+            .await?
+            .expect("Expected batch to be applied unconditionally"))
+    }
+
+    /// applies changes like [apply](Self::apply) if condition holds
+    ///
+    /// condition is checked while holding the IO, thus no local batch is flushed in between
+    /// eg. to check that the changes still follow the last batch flushed locally
+    /// returns None without applying anything if it does not hold
+    pub async fn apply_if(
+        &self,
+        changes: Vec<Change<GranteeId, ActionId>>,
+        condition: impl FnOnce() -> bool,
+    ) -> Result<Option<u64>, PermissionError> {
+        // wait for local changes in flight to keep CanDo in IO order
+        let _io = self.inner.io.lock().await;
+        if self.is_failed() {
+            return Err(PermissionError::Failed);
+        }
+        if !condition() {
+            return Ok(None);
+        }
+
+        Ok(Some(self.publish(changes)))
+    }
+
+    /// persist the current state as a snapshot and truncate the log behind it
+    ///
+    /// the snapshot is built from [CanDo::replays()] without compacting
+    /// thus grantees that are neither a root nor connected to one keep their grants
+    /// after a checkpoint only the snapshot and later changes have to be replayed on startup
+    pub async fn checkpoint(&self, ctx: &mut Io::Ctx) -> Result<(), PermissionError> {
+        // hold the io lock so no change slips in between snapshot and truncation
+        let mut io = self.inner.io.lock().await;
+        self.ensure_ready(&mut io, ctx).await?;
+
+        let snapshot: Vec<Change<GranteeId, ActionId>> = self
+            .reader()
+            .enter()
+            .expect("Expected to get ReadGuard on CanDo")
+            .can_do
+            .replays()
+            .into_iter()
+            .map(Change::from)
+            .collect();
+
+        io.checkpoint(&snapshot, ctx)
+            .await
+            .map_err(PermissionError::Io)
+    }
+
+    /// returns every persisted batch touching the queried grantee or action, oldest first
+    pub async fn history(
+        &self,
+        query: &AuditQuery<GranteeId, ActionId>,
+        ctx: &mut Io::Ctx,
+    ) -> Result<Vec<AuditedBatch<GranteeId, ActionId>>, PermissionError> {
+        self.inner
+            .io
+            .lock()
+            .await
+            .history(query, ctx)
+            .await
+            .map_err(PermissionError::Io)
     }
 
     pub fn check(
@@ -93,18 +439,934 @@ where
         grantee_id: &GranteeId,
         action_id: &ActionId,
     ) -> Result<bool, PermissionError> {
-        if !self.is_failed {
+        if self.is_failed() {
             return Err(PermissionError::Failed);
         }
 
         match self
-            .reader
+            .reader()
             .enter()
             .expect("Expected to get ReadGuard on CanDo")
+            .can_do
             .can_grantee_do(grantee_id, action_id)
         {
             Ok(result) => Ok(result),
             Err(check_error) => Err(PermissionError::Check(check_error)),
         }
     }
+
+    /// checks if a grantee can perform each of the actions
+    ///
+    /// all actions are checked against the same state
+    pub fn check_many(
+        &self,
+        grantee_id: &GranteeId,
+        action_ids: &[ActionId],
+    ) -> Result<Vec<bool>, PermissionError> {
+        if self.is_failed() {
+            return Err(PermissionError::Failed);
+        }
+
+        let state = self
+            .reader()
+            .enter()
+            .expect("Expected to get ReadGuard on CanDo");
+        action_ids
+            .iter()
+            .map(|action_id| {
+                state
+                    .can_do
+                    .can_grantee_do(grantee_id, action_id)
+                    .map_err(PermissionError::Check)
+            })
+            .collect()
+    }
+
+    /// returns the shortest chain of memberships, grant and action inheritances allowing an action
+    ///
+    /// returns None if the grantee can not perform the action
+    #[allow(clippy::type_complexity)]
+    pub fn explain(
+        &self,
+        grantee_id: &GranteeId,
+        action_id: &ActionId,
+    ) -> Result<Option<Vec<Replay<GranteeId, ActionId>>>, PermissionError> {
+        if self.is_failed() {
+            return Err(PermissionError::Failed);
+        }
+
+        self.reader()
+            .enter()
+            .expect("Expected to get ReadGuard on CanDo")
+            .can_do
+            .explain(grantee_id, action_id)
+            .map_err(PermissionError::Check)
+    }
+
+    /// checks once at least the given version has been published
+    ///
+    /// use the version returned by [change](Self::change) to read your own writes
+    /// returns Outdated if the version has not been reached within timeout
+    /// returns the result together with the version that answered otherwise
+    pub async fn check_at_least(
+        &self,
+        version: u64,
+        timeout: Duration,
+        grantee_id: &GranteeId,
+        action_id: &ActionId,
+    ) -> Result<(bool, u64), PermissionError> {
+        let mut published = self.watch();
+        // the version is only sent after its batch has been published
+        match tokio::time::timeout(
+            timeout,
+            published.wait_for(|published| *published >= version),
+        )
+        .await
+        {
+            Ok(Ok(_)) => {}
+            // the sender lives as long as self
+            Ok(Err(_)) => unreachable!("Expected version sender to outlive Permission"),
+            Err(_) => return Err(PermissionError::Outdated(version)),
+        }
+
+        if self.is_failed() {
+            return Err(PermissionError::Failed);
+        }
+
+        let state = self
+            .reader()
+            .enter()
+            .expect("Expected to get ReadGuard on CanDo");
+        match state.can_do.can_grantee_do(grantee_id, action_id) {
+            Ok(result) => Ok((result, state.version)),
+            Err(check_error) => Err(PermissionError::Check(check_error)),
+        }
+    }
+
+    /// returns all grantees a grantee is a direct or transitive member of
+    pub fn groups_of(&self, grantee_id: &GranteeId) -> Result<Vec<GranteeId>, PermissionError> {
+        if self.is_failed() {
+            return Err(PermissionError::Failed);
+        }
+
+        self.reader()
+            .enter()
+            .expect("Expected to get ReadGuard on CanDo")
+            .can_do
+            .groups_of(grantee_id)
+            .map_err(PermissionError::Check)
+    }
+
+    /// returns all members of a grantee
+    ///
+    /// if transitive is set members of members are returned as well
+    pub fn members_of(
+        &self,
+        grantee_id: &GranteeId,
+        transitive: bool,
+    ) -> Result<Vec<GranteeId>, PermissionError> {
+        if self.is_failed() {
+            return Err(PermissionError::Failed);
+        }
+
+        self.reader()
+            .enter()
+            .expect("Expected to get ReadGuard on CanDo")
+            .can_do
+            .members_of(grantee_id, transitive)
+            .map_err(PermissionError::Check)
+    }
+}
+
+/// a batch flushed to IO but not yet applied to CanDo, see [prepare](Permission::prepare)
+#[must_use = "the batch is only applied to CanDo once published"]
+pub struct PendingChange<'a, GranteeId, ActionId, Io>
+where
+    GranteeId: Hash + Eq + Copy + Send + Sync,
+    ActionId: Hash + Eq + Copy + Send + Sync,
+    Io: IO<GranteeId, ActionId>,
+{
+    permission: &'a Permission<GranteeId, ActionId, Io>,
+    // keeps other batches from being flushed or applied in between
+    _io: tokio::sync::MutexGuard<'a, Io>,
+    changes: Vec<Change<GranteeId, ActionId>>,
+}
+
+impl<GranteeId, ActionId, Io> PendingChange<'_, GranteeId, ActionId, Io>
+where
+    GranteeId: Hash + Eq + Copy + Send + Sync,
+    ActionId: Hash + Eq + Copy + Send + Sync,
+    Io: IO<GranteeId, ActionId>,
+{
+    /// applies the batch to CanDo and notifies subscribers
+    ///
+    /// returns the version of the batch, see [check_at_least](Permission::check_at_least)
+    pub fn publish(self) -> u64 {
+        self.permission.publish(self.changes)
+    }
+}
+
+impl<GranteeId, ActionId, Io> PermissionChecker<GranteeId, ActionId>
+    for Permission<GranteeId, ActionId, Io>
+where
+    GranteeId: Hash + Eq + Copy + Send + Sync,
+    ActionId: Hash + Eq + Copy + Send + Sync,
+    Io: IO<GranteeId, ActionId>,
+{
+    fn check(&self, grantee_id: &GranteeId, action_id: &ActionId) -> Result<bool, PermissionError> {
+        Permission::check(self, grantee_id, action_id)
+    }
+
+    fn check_many(
+        &self,
+        grantee_id: &GranteeId,
+        action_ids: &[ActionId],
+    ) -> Result<Vec<bool>, PermissionError> {
+        Permission::check_many(self, grantee_id, action_ids)
+    }
+
+    fn explain(
+        &self,
+        grantee_id: &GranteeId,
+        action_id: &ActionId,
+    ) -> Result<Option<Vec<Replay<GranteeId, ActionId>>>, PermissionError> {
+        Permission::explain(self, grantee_id, action_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        Audit, AuditQuery, AuditedBatch, Change, ChangeError, IOError, MemoryIO,
+        MockPermissionChecker, Permission, PermissionChecker, PermissionError, RetryPolicy, IO,
+    };
+    use can_do::{CanDoError, Replay};
+    use std::time::Duration;
+
+    /// fails flush and clear while set
+    #[derive(Default)]
+    struct Faults {
+        flush: bool,
+        clear: bool,
+    }
+
+    /// MemoryIO failing flush and clear on demand
+    #[derive(Default)]
+    struct FaultyIO(MemoryIO<u32, u32>);
+
+    impl IO<u32, u32> for FaultyIO {
+        type Ctx = Faults;
+
+        async fn read_all(&mut self, _ctx: &mut Faults) -> Result<Vec<Change<u32, u32>>, IOError> {
+            self.0.read_all(&mut ()).await
+        }
+
+        async fn write(
+            &mut self,
+            change: &Change<u32, u32>,
+            _ctx: &mut Faults,
+        ) -> Result<(), IOError> {
+            self.0.write(change, &mut ()).await
+        }
+
+        async fn flush(
+            &mut self,
+            audit: &Audit<u32>,
+            inverse: &[Change<u32, u32>],
+            ctx: &mut Faults,
+        ) -> Result<(), IOError> {
+            if ctx.flush {
+                return Err(IOError::Flush);
+            }
+            self.0.flush(audit, inverse, &mut ()).await
+        }
+
+        async fn clear(&mut self, ctx: &mut Faults) -> Result<(), IOError> {
+            if ctx.clear {
+                return Err(IOError::Clear);
+            }
+            self.0.clear(&mut ()).await
+        }
+
+        async fn checkpoint(
+            &mut self,
+            snapshot: &[Change<u32, u32>],
+            _ctx: &mut Faults,
+        ) -> Result<(), IOError> {
+            self.0.checkpoint(snapshot, &mut ()).await
+        }
+
+        async fn history(
+            &mut self,
+            query: &AuditQuery<u32, u32>,
+            _ctx: &mut Faults,
+        ) -> Result<Vec<AuditedBatch<u32, u32>>, IOError> {
+            self.0.history(query, &mut ()).await
+        }
+    }
+
+    fn broken() -> Faults {
+        Faults {
+            flush: true,
+            clear: true,
+        }
+    }
+
+    async fn failed_permission(retry: Option<RetryPolicy>) -> Permission<u32, u32, FaultyIO> {
+        let permission = match retry {
+            Some(retry) => {
+                Permission::with_retry(FaultyIO::default(), retry, &mut Faults::default()).await
+            }
+            None => Permission::new(FaultyIO::default(), &mut Faults::default()).await,
+        }
+        .expect("Expected Permission");
+
+        permission
+            .change(
+                vec![Change::AddGrant(1, 10)],
+                &Audit::system(),
+                &mut Faults::default(),
+            )
+            .await
+            .expect("Expected change to be persisted");
+        assert!(permission
+            .change(
+                vec![Change::AddGrant(2, 20)],
+                &Audit::system(),
+                &mut broken()
+            )
+            .await
+            .is_err());
+
+        permission
+    }
+
+    #[tokio::test]
+    async fn check_answers_unless_failed() {
+        let permission = Permission::new(FaultyIO::default(), &mut Faults::default())
+            .await
+            .expect("Expected Permission");
+        permission
+            .change(
+                vec![Change::AddGrant(1, 10), Change::AddGrant(2, 20)],
+                &Audit::system(),
+                &mut Faults::default(),
+            )
+            .await
+            .expect("Expected change to be persisted");
+
+        // checks used to be refused unless failed
+        assert!(matches!(permission.check(&1, &10), Ok(true)));
+        assert!(matches!(permission.check(&1, &20), Ok(false)));
+    }
+
+    #[tokio::test]
+    async fn failed_state_is_kept_without_recovery() {
+        let permission = failed_permission(None).await;
+
+        assert!(matches!(
+            permission.check(&1, &10),
+            Err(PermissionError::Failed)
+        ));
+        assert!(matches!(
+            permission
+                .change(
+                    vec![Change::AddGrant(3, 30)],
+                    &Audit::system(),
+                    &mut Faults::default()
+                )
+                .await,
+            Err(PermissionError::Failed)
+        ));
+    }
+
+    #[tokio::test]
+    async fn recover_rebuilds_from_io() {
+        let permission = failed_permission(None).await;
+
+        permission
+            .recover(&mut Faults::default())
+            .await
+            .expect("Expected to recover");
+
+        assert!(matches!(permission.check(&1, &10), Ok(true)));
+        // the failed batch was never persisted
+        assert!(matches!(
+            permission.check(&2, &20),
+            Err(PermissionError::Check(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn checkpoints_keep_grants_of_grantees_without_root() {
+        let permission = Permission::new(FaultyIO::default(), &mut Faults::default())
+            .await
+            .expect("Expected Permission");
+        permission
+            .change(
+                vec![Change::AddGrant(1, 10), Change::ConnectGrantees(2, 1)],
+                &Audit::system(),
+                &mut Faults::default(),
+            )
+            .await
+            .expect("Expected change to be persisted");
+
+        permission
+            .checkpoint(&mut Faults::default())
+            .await
+            .expect("Expected checkpoint");
+        permission
+            .recover(&mut Faults::default())
+            .await
+            .expect("Expected to recover");
+
+        assert!(matches!(permission.check(&1, &10), Ok(true)));
+        assert!(matches!(permission.check(&2, &10), Ok(true)));
+    }
+
+    #[tokio::test]
+    async fn recover_keeps_failed_state_on_io_error() {
+        let permission = failed_permission(None).await;
+
+        assert!(permission.recover(&mut broken()).await.is_err());
+        assert!(matches!(
+            permission.check(&1, &10),
+            Err(PermissionError::Failed)
+        ));
+    }
+
+    #[tokio::test]
+    async fn apply_does_not_write_to_io() {
+        let permission = Permission::new(FaultyIO::default(), &mut Faults::default())
+            .await
+            .expect("Expected Permission");
+
+        permission
+            .apply(vec![Change::AddGrant(1, 10)])
+            .await
+            .expect("Expected changes to be applied");
+        assert!(matches!(permission.check(&1, &10), Ok(true)));
+
+        // a resync only sees what has been persisted
+        permission
+            .recover(&mut Faults::default())
+            .await
+            .expect("Expected to recover");
+        assert!(matches!(
+            permission.check(&1, &10),
+            Err(PermissionError::Check(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn apply_if_checks_after_local_batches_in_flight() {
+        let permission = Permission::new(FaultyIO::default(), &mut Faults::default())
+            .await
+            .expect("Expected Permission");
+        let flushed = std::sync::atomic::AtomicBool::new(false);
+
+        let pending = permission
+            .prepare(
+                vec![Change::AddGrant(1, 10)],
+                &Audit::system(),
+                &mut Faults::default(),
+            )
+            .await
+            .expect("Expected change to be persisted");
+        let (applied, _) = tokio::join!(
+            permission.apply_if(vec![Change::AddGrant(2, 20)], || {
+                !flushed.load(std::sync::atomic::Ordering::Acquire)
+            }),
+            async {
+                flushed.store(true, std::sync::atomic::Ordering::Release);
+                pending.publish()
+            }
+        );
+
+        assert!(matches!(applied, Ok(None)));
+        assert!(matches!(permission.check(&1, &10), Ok(true)));
+        assert!(matches!(
+            permission.check(&2, &20),
+            Err(PermissionError::Check(_))
+        ));
+        assert!(matches!(
+            permission
+                .apply_if(vec![Change::AddGrant(2, 20)], || true)
+                .await,
+            Ok(Some(2))
+        ));
+    }
+
+    #[tokio::test]
+    async fn subscribers_receive_applied_batches() {
+        let permission = Permission::new(FaultyIO::default(), &mut Faults::default())
+            .await
+            .expect("Expected Permission");
+        let mut batches = permission.subscribe();
+        let version = permission.watch();
+
+        permission
+            .change(
+                vec![Change::AddGrant(1, 10)],
+                &Audit::system(),
+                &mut Faults::default(),
+            )
+            .await
+            .expect("Expected change to be persisted");
+        permission
+            .apply(vec![Change::AddGrant(2, 20)])
+            .await
+            .expect("Expected changes to be applied");
+        permission
+            .recover(&mut Faults::default())
+            .await
+            .expect("Expected to recover");
+
+        let batch = batches.recv().await.expect("Expected first batch");
+        assert_eq!(batch.version, 1);
+        assert!(matches!(*batch.changes, [Change::AddGrant(1, 10)]));
+        let batch = batches.recv().await.expect("Expected second batch");
+        assert_eq!(batch.version, 2);
+        assert!(matches!(*batch.changes, [Change::AddGrant(2, 20)]));
+        // a rebuild replaces everything
+        let batch = batches.recv().await.expect("Expected rebuilt batch");
+        assert_eq!(batch.version, 3);
+        assert!(matches!(
+            *batch.changes,
+            [Change::Clear, Change::AddGrant(1, 10)]
+        ));
+
+        assert_eq!(*version.borrow(), 3);
+        assert_eq!(permission.version(), 3);
+    }
+
+    #[tokio::test]
+    async fn failed_changes_are_not_published() {
+        let permission = failed_permission(None).await;
+        let mut batches = permission.subscribe();
+
+        assert_eq!(permission.version(), 1);
+        assert!(batches.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn check_at_least_reads_own_writes() {
+        let permission = Permission::new(FaultyIO::default(), &mut Faults::default())
+            .await
+            .expect("Expected Permission");
+
+        let version = permission
+            .change(
+                vec![Change::AddGrant(1, 10)],
+                &Audit::system(),
+                &mut Faults::default(),
+            )
+            .await
+            .expect("Expected change to be persisted");
+
+        assert!(matches!(
+            permission
+                .check_at_least(version, Duration::ZERO, &1, &10)
+                .await,
+            Ok((true, answered)) if answered == version
+        ));
+    }
+
+    #[tokio::test]
+    async fn prepared_batches_are_applied_once_published() {
+        let permission = Permission::new(FaultyIO::default(), &mut Faults::default())
+            .await
+            .expect("Expected Permission");
+
+        let pending = permission
+            .prepare(
+                vec![Change::AddGrant(1, 10)],
+                &Audit::system(),
+                &mut Faults::default(),
+            )
+            .await
+            .expect("Expected change to be persisted");
+        // the transaction it was flushed into has not been committed yet
+        assert!(matches!(
+            permission.check(&1, &10),
+            Err(PermissionError::Check(CanDoError::GranteeNotFound))
+        ));
+
+        assert_eq!(1, pending.publish());
+        assert!(matches!(permission.check(&1, &10), Ok(true)));
+    }
+
+    #[tokio::test]
+    async fn dropped_batches_are_not_applied() {
+        let permission = Permission::new(FaultyIO::default(), &mut Faults::default())
+            .await
+            .expect("Expected Permission");
+
+        let pending = permission
+            .prepare(
+                vec![Change::AddGrant(1, 10)],
+                &Audit::system(),
+                &mut Faults::default(),
+            )
+            .await
+            .expect("Expected change to be persisted");
+        // eg. the transaction has been rolled back
+        drop(pending);
+        assert_eq!(0, permission.version());
+
+        // later batches are not blocked
+        permission
+            .change(
+                vec![Change::AddGrant(2, 20)],
+                &Audit::system(),
+                &mut Faults::default(),
+            )
+            .await
+            .expect("Expected change to be persisted");
+        assert!(matches!(
+            permission.check(&1, &10),
+            Err(PermissionError::Check(CanDoError::GranteeNotFound))
+        ));
+        assert!(matches!(permission.check(&2, &20), Ok(true)));
+    }
+
+    #[tokio::test]
+    async fn check_at_least_waits_for_the_version() {
+        let permission = Permission::new(FaultyIO::default(), &mut Faults::default())
+            .await
+            .expect("Expected Permission");
+
+        let writer = permission.clone();
+        let write = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            writer
+                .apply(vec![Change::AddGrant(1, 10)])
+                .await
+                .expect("Expected changes to be applied")
+        });
+
+        assert!(matches!(
+            permission
+                .check_at_least(1, Duration::from_secs(5), &1, &10)
+                .await,
+            Ok((true, 1))
+        ));
+        write.await.expect("Expected write to finish");
+    }
+
+    #[tokio::test]
+    async fn check_at_least_times_out() {
+        let permission = Permission::new(FaultyIO::default(), &mut Faults::default())
+            .await
+            .expect("Expected Permission");
+
+        assert!(matches!(
+            permission
+                .check_at_least(1, Duration::from_millis(1), &1, &10)
+                .await,
+            Err(PermissionError::Outdated(1))
+        ));
+    }
+
+    #[tokio::test]
+    async fn history_returns_audited_batches() {
+        let permission = Permission::new(FaultyIO::default(), &mut Faults::default())
+            .await
+            .expect("Expected Permission");
+
+        permission
+            .change(
+                vec![Change::AddGrant(1, 10)],
+                &Audit::new(7).with_reason("board decision"),
+                &mut Faults::default(),
+            )
+            .await
+            .expect("Expected change to be persisted");
+        permission
+            .change(
+                vec![Change::AddGrant(2, 20)],
+                &Audit::system(),
+                &mut Faults::default(),
+            )
+            .await
+            .expect("Expected change to be persisted");
+
+        let history = permission
+            .history(&AuditQuery::Grantee(1), &mut Faults::default())
+            .await
+            .expect("Expected history");
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].audit.actor, Some(7));
+        assert_eq!(history[0].audit.reason.as_deref(), Some("board decision"));
+        assert!(matches!(history[0].changes[..], [Change::AddGrant(1, 10)]));
+    }
+
+    #[tokio::test]
+    async fn invalid_batches_are_rejected_as_a_whole() {
+        let permission = Permission::new(FaultyIO::default(), &mut Faults::default())
+            .await
+            .expect("Expected Permission");
+
+        let result = permission
+            .change(
+                vec![
+                    Change::AddGrant(1, 10),
+                    Change::RemoveGrant(2, 10),
+                    Change::RemoveGrant(1, 20),
+                ],
+                &Audit::system(),
+                &mut Faults::default(),
+            )
+            .await;
+
+        let Err(PermissionError::Rejected(errors)) = result else {
+            panic!("Expected batch to be rejected");
+        };
+        assert_eq!(
+            errors,
+            vec![
+                ChangeError {
+                    index: 1,
+                    error: CanDoError::GranteeNotFound
+                },
+                ChangeError {
+                    index: 2,
+                    error: CanDoError::ActionNotFound
+                },
+            ]
+        );
+        assert_eq!(permission.version(), 0);
+        assert!(matches!(
+            permission.check(&1, &10),
+            Err(PermissionError::Check(_))
+        ));
+        // nothing has been persisted
+        assert!(permission
+            .history(&AuditQuery::Grantee(1), &mut Faults::default())
+            .await
+            .expect("Expected history")
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn batches_may_depend_on_earlier_changes() {
+        let permission = Permission::new(FaultyIO::default(), &mut Faults::default())
+            .await
+            .expect("Expected Permission");
+
+        permission
+            .change(
+                vec![Change::AddGrant(1, 10), Change::RemoveGrant(1, 10)],
+                &Audit::system(),
+                &mut Faults::default(),
+            )
+            .await
+            .expect("Expected batch to be valid");
+        assert!(matches!(permission.check(&1, &10), Ok(false)));
+    }
+
+    #[tokio::test]
+    async fn dry_run_returns_the_diff_without_applying() {
+        let permission = Permission::new(FaultyIO::default(), &mut Faults::default())
+            .await
+            .expect("Expected Permission");
+        permission
+            .change(
+                vec![Change::AddGrant(1, 10), Change::ConnectGrantees(2, 1)],
+                &Audit::system(),
+                &mut Faults::default(),
+            )
+            .await
+            .expect("Expected change to be persisted");
+
+        let diff = permission
+            .dry_run(&[Change::AddGrant(1, 20), Change::DisconnectGrantees(2, 1)])
+            .expect("Expected batch to be valid");
+
+        assert_eq!(diff.added, vec![Replay::Grant(1, 20)]);
+        assert_eq!(diff.removed, vec![Replay::ConnectGrantees(2, 1)]);
+        assert_eq!(permission.version(), 1);
+        assert!(matches!(
+            permission.check(&1, &20),
+            Err(PermissionError::Check(_))
+        ));
+
+        assert!(matches!(
+            permission.dry_run(&[Change::RemoveGrantee(3)]),
+            Err(PermissionError::Rejected(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn undo_restores_removed_grantees() {
+        let permission = Permission::new(FaultyIO::default(), &mut Faults::default())
+            .await
+            .expect("Expected Permission");
+        permission
+            .change(
+                vec![
+                    Change::AddGrant(2, 10),
+                    Change::ConnectGrantees(1, 2),
+                    Change::AddRoot(2),
+                ],
+                &Audit::system(),
+                &mut Faults::default(),
+            )
+            .await
+            .expect("Expected change to be persisted");
+        permission
+            .change(
+                vec![Change::RemoveGrantee(2)],
+                &Audit::new(7),
+                &mut Faults::default(),
+            )
+            .await
+            .expect("Expected change to be persisted");
+        assert!(matches!(permission.check(&1, &10), Ok(false)));
+
+        let history = permission
+            .history(&AuditQuery::Grantee(2), &mut Faults::default())
+            .await
+            .expect("Expected history");
+        let removal = history.last().expect("Expected removal in history");
+        permission
+            .undo(removal, &Audit::new(7), &mut Faults::default())
+            .await
+            .expect("Expected undo");
+
+        assert!(matches!(permission.check(&1, &10), Ok(true)));
+        assert_eq!(permission.groups_of(&1).expect("Expected groups"), vec![2]);
+    }
+
+    #[tokio::test]
+    async fn undo_fails_on_diverged_state() {
+        let permission = Permission::new(FaultyIO::default(), &mut Faults::default())
+            .await
+            .expect("Expected Permission");
+        permission
+            .change(
+                vec![Change::AddGrant(1, 10)],
+                &Audit::system(),
+                &mut Faults::default(),
+            )
+            .await
+            .expect("Expected change to be persisted");
+        permission
+            .change(
+                vec![Change::RemoveGrantee(1)],
+                &Audit::system(),
+                &mut Faults::default(),
+            )
+            .await
+            .expect("Expected change to be persisted");
+
+        let history = permission
+            .history(&AuditQuery::Grantee(1), &mut Faults::default())
+            .await
+            .expect("Expected history");
+        // the grant has already been removed together with its grantee
+        assert!(matches!(
+            permission
+                .undo(&history[0], &Audit::system(), &mut Faults::default())
+                .await,
+            Err(PermissionError::Rejected(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn checker_explains_checks() {
+        let permission = Permission::new(FaultyIO::default(), &mut Faults::default())
+            .await
+            .expect("Expected Permission");
+        permission
+            .change(
+                vec![Change::ConnectGrantees(1, 2), Change::AddGrant(2, 10)],
+                &Audit::system(),
+                &mut Faults::default(),
+            )
+            .await
+            .expect("Expected change to be persisted");
+
+        let checker: &dyn PermissionChecker<u32, u32> = &permission;
+        assert!(matches!(checker.check_many(&1, &[10]), Ok(result) if result == [true]));
+        assert!(matches!(
+            checker.check_many(&1, &[10, 11]),
+            Err(PermissionError::Check(CanDoError::ActionNotFound))
+        ));
+        assert!(matches!(
+            checker.explain(&1, &10),
+            Ok(Some(explanation))
+                if explanation == [Replay::ConnectGrantees(1, 2), Replay::Grant(2, 10)]
+        ));
+        assert!(
+            matches!(checker.explain(&2, &10), Ok(Some(explanation)) if explanation.len() == 1)
+        );
+    }
+
+    #[test]
+    fn handlers_can_be_tested_with_mock_checker() {
+        fn can_manage(checker: &impl PermissionChecker<u32, u32>, grantee_id: u32) -> bool {
+            checker.check(&grantee_id, &10).unwrap_or(false)
+        }
+
+        let mut checker = MockPermissionChecker::<u32, u32>::new();
+        checker
+            .expect_check()
+            .returning(|grantee_id, _| Ok(*grantee_id == 1));
+        assert!(can_manage(&checker, 1));
+        assert!(!can_manage(&checker, 2));
+    }
+
+    #[tokio::test]
+    async fn memory_io_keeps_batches() {
+        let mut io = MemoryIO::<u32, u32>::new();
+        let permission = Permission::new(MemoryIO::new(), &mut ())
+            .await
+            .expect("Expected Permission");
+        permission
+            .change(vec![Change::AddGrant(1, 10)], &Audit::system(), &mut ())
+            .await
+            .expect("Expected change to be persisted");
+        assert!(matches!(permission.check(&1, &10), Ok(true)));
+
+        io.write(&Change::AddGrant(1, 10), &mut ())
+            .await
+            .expect("Expected write");
+        io.clear(&mut ()).await.expect("Expected clear");
+        io.flush(&Audit::system(), &[], &mut ())
+            .await
+            .expect("Expected flush");
+        assert!(io
+            .read_all(&mut ())
+            .await
+            .expect("Expected read")
+            .is_empty());
+        assert!(io
+            .history(&AuditQuery::Grantee(1), &mut ())
+            .await
+            .expect("Expected history")
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn retry_policy_recovers_on_next_change() {
+        let permission = failed_permission(Some(RetryPolicy {
+            attempts: 2,
+            backoff: Duration::ZERO,
+            max_backoff: Duration::ZERO,
+        }))
+        .await;
+
+        permission
+            .change(
+                vec![Change::AddGrant(3, 30)],
+                &Audit::system(),
+                &mut Faults::default(),
+            )
+            .await
+            .expect("Expected change after recovery");
+
+        assert!(matches!(permission.check(&1, &10), Ok(true)));
+        assert!(matches!(permission.check(&3, &30), Ok(true)));
+    }
 }
@@ -1,41 +1,43 @@
 use crate::Change;
-use can_do::CanDo;
+use can_do::{CanDo, CanDoError};
 use left_right::Absorb;
 use std::hash::Hash;
 
-impl<GranteeId, ActionId> Absorb<Change<GranteeId, ActionId>> for CanDo<GranteeId, ActionId>
+/// CanDo together with the version of the last batch applied to it
+#[derive(Clone)]
+pub(crate) struct State<GranteeId, ActionId> {
+    pub(crate) can_do: CanDo<GranteeId, ActionId>,
+    pub(crate) version: u64,
+}
+
+impl<GranteeId, ActionId> Default for State<GranteeId, ActionId>
+where
+    GranteeId: Hash + Eq + Copy,
+    ActionId: Hash + Eq + Copy,
+{
+    fn default() -> Self {
+        Self {
+            can_do: CanDo::default(),
+            version: 0,
+        }
+    }
+}
+
+pub(crate) enum Op<GranteeId, ActionId> {
+    Change(Change<GranteeId, ActionId>),
+    // appended after every batch so readers see a batch and its version at once
+    Version(u64),
+}
+
+impl<GranteeId, ActionId> Absorb<Op<GranteeId, ActionId>> for State<GranteeId, ActionId>
 where
     GranteeId: Hash + Eq + Copy,
     ActionId: Hash + Eq + Copy,
 {
-    /// apply changes to can_do
-    fn absorb_first(&mut self, change: &mut Change<GranteeId, ActionId>, _: &Self) {
-        match change {
-            Change::Clear => self.clear(),
-            Change::RemoveGrantee(grantee_id) => {
-                let _ = self.remove_grantee(grantee_id);
-            }
-            Change::RemoveAction(action_id) => {
-                let _ = self.remove_action(action_id);
-            }
-            Change::AddGrant(grantee_id, action_id) => self.add_grant(grantee_id, action_id),
-            Change::RemoveGrant(grantee_id, action_id) => {
-                self.remove_grant(grantee_id, action_id).unwrap()
-            }
-            Change::ConnectGrantees(grantee_id, grantee_of_id) => {
-                self.connect_grantees(grantee_id, grantee_of_id)
-            }
-            Change::DisconnectGrantees(grantee_id, grantee_of_id) => {
-                let _ = self.disconnect_grantees(grantee_id, grantee_of_id);
-            }
-            Change::AddRoot(grantee_id) => self.add_root(grantee_id),
-            Change::ConnectActions(main_action_id, sub_action_id) => {
-                self.connect_actions(main_action_id, sub_action_id)
-            }
-            Change::DisconnectActions(main_action_id, sub_action_id) => {
-                let _ = self.disconnect_actions(main_action_id, sub_action_id);
-            }
-            Change::RemoveRoot(grantee_id) => self.remove_root(grantee_id),
+    fn absorb_first(&mut self, op: &mut Op<GranteeId, ActionId>, _: &Self) {
+        match op {
+            Op::Change(change) => absorb_change(&mut self.can_do, change),
+            Op::Version(version) => self.version = *version,
         }
     }
 
@@ -45,3 +47,52 @@ where
         *self = first.clone()
     }
 }
+
+/// apply changes to can_do
+///
+/// local batches have been validated before, changes replayed from IO or applied from
+/// other instances are applied as far as possible instead of failing the writer
+fn absorb_change<GranteeId, ActionId>(
+    can_do: &mut CanDo<GranteeId, ActionId>,
+    change: &Change<GranteeId, ActionId>,
+) where
+    GranteeId: Hash + Eq + Copy,
+    ActionId: Hash + Eq + Copy,
+{
+    let _ = try_absorb_change(can_do, change);
+}
+
+/// apply a change to can_do
+///
+/// fails on removing unknown grantees, actions or connections between them
+pub(crate) fn try_absorb_change<GranteeId, ActionId>(
+    can_do: &mut CanDo<GranteeId, ActionId>,
+    change: &Change<GranteeId, ActionId>,
+) -> Result<(), CanDoError>
+where
+    GranteeId: Hash + Eq + Copy,
+    ActionId: Hash + Eq + Copy,
+{
+    match change {
+        Change::Clear => can_do.clear(),
+        Change::RemoveGrantee(grantee_id) => can_do.remove_grantee(grantee_id)?,
+        Change::RemoveAction(action_id) => can_do.remove_action(action_id)?,
+        Change::AddGrant(grantee_id, action_id) => can_do.add_grant(grantee_id, action_id),
+        Change::RemoveGrant(grantee_id, action_id) => can_do.remove_grant(grantee_id, action_id)?,
+        Change::ConnectGrantees(grantee_id, grantee_of_id) => {
+            can_do.connect_grantees(grantee_id, grantee_of_id)
+        }
+        Change::DisconnectGrantees(grantee_id, grantee_of_id) => {
+            can_do.disconnect_grantees(grantee_id, grantee_of_id)?
+        }
+        Change::AddRoot(grantee_id) => can_do.add_root(grantee_id),
+        Change::ConnectActions(main_action_id, sub_action_id) => {
+            can_do.connect_actions(main_action_id, sub_action_id)
+        }
+        Change::DisconnectActions(main_action_id, sub_action_id) => {
+            can_do.disconnect_actions(main_action_id, sub_action_id)?
+        }
+        Change::RemoveRoot(grantee_id) => can_do.remove_root(grantee_id),
+    }
+    Ok(())
+}
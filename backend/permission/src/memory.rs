@@ -0,0 +1,96 @@
+use crate::{Audit, AuditQuery, AuditedBatch, Change, IOError, IO};
+
+/// keeps every change in memory, nothing survives a restart
+///
+/// meant for tests and tools that do not need persistence
+#[derive(Debug)]
+pub struct MemoryIO<GranteeId, ActionId> {
+    persisted: Vec<Change<GranteeId, ActionId>>,
+    pending: Vec<Change<GranteeId, ActionId>>,
+    audited: Vec<AuditedBatch<GranteeId, ActionId>>,
+}
+
+impl<GranteeId, ActionId> MemoryIO<GranteeId, ActionId> {
+    pub fn new() -> Self {
+        MemoryIO {
+            persisted: vec![],
+            pending: vec![],
+            audited: vec![],
+        }
+    }
+}
+
+impl<GranteeId, ActionId> Default for MemoryIO<GranteeId, ActionId> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<GranteeId, ActionId> IO<GranteeId, ActionId> for MemoryIO<GranteeId, ActionId>
+where
+    GranteeId: PartialEq + Copy,
+    ActionId: PartialEq + Copy,
+{
+    type Ctx = ();
+
+    async fn read_all(
+        &mut self,
+        _ctx: &mut (),
+    ) -> Result<Vec<Change<GranteeId, ActionId>>, IOError> {
+        Ok(self.persisted.clone())
+    }
+
+    async fn write(
+        &mut self,
+        change: &Change<GranteeId, ActionId>,
+        _ctx: &mut (),
+    ) -> Result<(), IOError> {
+        self.pending.push(*change);
+        Ok(())
+    }
+
+    async fn flush(
+        &mut self,
+        audit: &Audit<GranteeId>,
+        inverse: &[Change<GranteeId, ActionId>],
+        _ctx: &mut (),
+    ) -> Result<(), IOError> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        self.audited.push(AuditedBatch {
+            audit: audit.clone(),
+            changes: self.pending.clone(),
+            inverse: inverse.to_vec(),
+        });
+        self.persisted.append(&mut self.pending);
+        Ok(())
+    }
+
+    async fn clear(&mut self, _ctx: &mut ()) -> Result<(), IOError> {
+        self.pending.clear();
+        Ok(())
+    }
+
+    async fn checkpoint(
+        &mut self,
+        snapshot: &[Change<GranteeId, ActionId>],
+        _ctx: &mut (),
+    ) -> Result<(), IOError> {
+        self.persisted = snapshot.to_vec();
+        Ok(())
+    }
+
+    async fn history(
+        &mut self,
+        query: &AuditQuery<GranteeId, ActionId>,
+        _ctx: &mut (),
+    ) -> Result<Vec<AuditedBatch<GranteeId, ActionId>>, IOError> {
+        Ok(self
+            .audited
+            .iter()
+            .filter(|batch| batch.changes.iter().any(|change| query.matches(change)))
+            .cloned()
+            .collect())
+    }
+}
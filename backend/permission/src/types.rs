@@ -1,5 +1,9 @@
 use crate::IOError;
-use can_do::CanDoError;
+use can_do::{CanDoError, Replay};
+use std::collections::HashSet;
+use std::hash::Hash;
+use std::sync::Arc;
+use std::time::Duration;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -10,9 +14,93 @@ pub enum PermissionError {
     Check(CanDoError),
     #[error("Error during IO Performance\n\t{0}")]
     Io(IOError),
+    #[error("Permission did not reach version {0} in time")]
+    Outdated(u64),
+    #[error("Batch rejected, no change has been applied\n\t{0:?}")]
+    Rejected(Vec<ChangeError>),
 }
 
+/// why a change of a batch is invalid
+#[derive(Error, Debug, PartialEq)]
+#[error("Change {index} is invalid: {error}")]
+pub struct ChangeError {
+    /// position of the change within its batch
+    pub index: usize,
+    pub error: CanDoError,
+}
+
+/// what a batch would change, see [dry_run](crate::Permission::dry_run)
+#[derive(Clone, Debug)]
+pub struct Diff<GranteeId, ActionId> {
+    pub added: Vec<Replay<GranteeId, ActionId>>,
+    pub removed: Vec<Replay<GranteeId, ActionId>>,
+}
+
+impl<GranteeId, ActionId> Diff<GranteeId, ActionId>
+where
+    GranteeId: Hash + Eq + Copy,
+    ActionId: Hash + Eq + Copy,
+{
+    /// compares the replays of two CanDos
+    pub(crate) fn between(
+        before: Vec<Replay<GranteeId, ActionId>>,
+        after: Vec<Replay<GranteeId, ActionId>>,
+    ) -> Self {
+        let before_set: HashSet<_> = before.iter().copied().collect();
+        let after_set: HashSet<_> = after.iter().copied().collect();
+
+        Self {
+            added: after
+                .into_iter()
+                .filter(|replay| !before_set.contains(replay))
+                .collect(),
+            removed: before
+                .into_iter()
+                .filter(|replay| !after_set.contains(replay))
+                .collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// how a failed [Permission](crate::Permission) tries to recover on its own
+///
+/// recovery is attempted before the next change or checkpoint
+/// the delay between attempts starts at backoff and doubles up to max_backoff
 #[derive(Copy, Clone, Debug)]
+pub struct RetryPolicy {
+    pub attempts: u32,
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+/// with the serde feature a change is serialized as `{"v1": {"add_grant": [grantee, action]}}`
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(
+        into = "crate::wire::VersionedChange<GranteeId, ActionId>",
+        from = "crate::wire::VersionedChange<GranteeId, ActionId>",
+        bound(
+            serialize = "GranteeId: serde::Serialize + Clone, ActionId: serde::Serialize + Clone",
+            deserialize = "GranteeId: serde::Deserialize<'de>, ActionId: serde::Deserialize<'de>"
+        )
+    )
+)]
 pub enum Change<GranteeId, ActionId> {
     Clear,
     RemoveGrantee(GranteeId),
@@ -27,3 +115,27 @@ pub enum Change<GranteeId, ActionId> {
     AddRoot(GranteeId),
     RemoveRoot(GranteeId),
 }
+
+/// a batch of changes applied to a [Permission](crate::Permission)
+///
+/// version is the version of the Permission right after the batch has been applied
+#[derive(Clone, Debug)]
+pub struct ChangeBatch<GranteeId, ActionId> {
+    pub version: u64,
+    pub changes: Arc<[Change<GranteeId, ActionId>]>,
+}
+
+impl<GranteeId, ActionId> From<Replay<GranteeId, ActionId>> for Change<GranteeId, ActionId> {
+    fn from(replay: Replay<GranteeId, ActionId>) -> Self {
+        match replay {
+            Replay::Grant(grantee_id, action_id) => Change::AddGrant(grantee_id, action_id),
+            Replay::ConnectGrantees(grantee_id, grantee_of_id) => {
+                Change::ConnectGrantees(grantee_id, grantee_of_id)
+            }
+            Replay::ConnectActions(main_action_id, sub_action_id) => {
+                Change::ConnectActions(main_action_id, sub_action_id)
+            }
+            Replay::Root(grantee_id) => Change::AddRoot(grantee_id),
+        }
+    }
+}
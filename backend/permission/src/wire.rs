@@ -0,0 +1,134 @@
+//! stable wire format of [Change]
+//!
+//! every change is wrapped into its format version, the variant names are snake_case
+//! a format version must never change once released, changes require a new version
+//! older versions are still deserialized and converted into the current Change
+use crate::Change;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum VersionedChange<GranteeId, ActionId> {
+    V1(ChangeV1<GranteeId, ActionId>),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ChangeV1<GranteeId, ActionId> {
+    Clear,
+    RemoveGrantee(GranteeId),
+    RemoveAction(ActionId),
+    AddGrant(GranteeId, ActionId),
+    RemoveGrant(GranteeId, ActionId),
+    ConnectGrantees(GranteeId, GranteeId),
+    DisconnectGrantees(GranteeId, GranteeId),
+    ConnectActions(ActionId, ActionId),
+    DisconnectActions(ActionId, ActionId),
+    AddRoot(GranteeId),
+    RemoveRoot(GranteeId),
+}
+
+impl<GranteeId, ActionId> From<Change<GranteeId, ActionId>>
+    for VersionedChange<GranteeId, ActionId>
+{
+    fn from(change: Change<GranteeId, ActionId>) -> Self {
+        VersionedChange::V1(match change {
+            Change::Clear => ChangeV1::Clear,
+            Change::RemoveGrantee(grantee_id) => ChangeV1::RemoveGrantee(grantee_id),
+            Change::RemoveAction(action_id) => ChangeV1::RemoveAction(action_id),
+            Change::AddGrant(grantee_id, action_id) => ChangeV1::AddGrant(grantee_id, action_id),
+            Change::RemoveGrant(grantee_id, action_id) => {
+                ChangeV1::RemoveGrant(grantee_id, action_id)
+            }
+            Change::ConnectGrantees(grantee_id, grantee_of_id) => {
+                ChangeV1::ConnectGrantees(grantee_id, grantee_of_id)
+            }
+            Change::DisconnectGrantees(grantee_id, grantee_of_id) => {
+                ChangeV1::DisconnectGrantees(grantee_id, grantee_of_id)
+            }
+            Change::ConnectActions(main_action_id, sub_action_id) => {
+                ChangeV1::ConnectActions(main_action_id, sub_action_id)
+            }
+            Change::DisconnectActions(main_action_id, sub_action_id) => {
+                ChangeV1::DisconnectActions(main_action_id, sub_action_id)
+            }
+            Change::AddRoot(grantee_id) => ChangeV1::AddRoot(grantee_id),
+            Change::RemoveRoot(grantee_id) => ChangeV1::RemoveRoot(grantee_id),
+        })
+    }
+}
+
+impl<GranteeId, ActionId> From<VersionedChange<GranteeId, ActionId>>
+    for Change<GranteeId, ActionId>
+{
+    fn from(change: VersionedChange<GranteeId, ActionId>) -> Self {
+        let VersionedChange::V1(change) = change;
+        match change {
+            ChangeV1::Clear => Change::Clear,
+            ChangeV1::RemoveGrantee(grantee_id) => Change::RemoveGrantee(grantee_id),
+            ChangeV1::RemoveAction(action_id) => Change::RemoveAction(action_id),
+            ChangeV1::AddGrant(grantee_id, action_id) => Change::AddGrant(grantee_id, action_id),
+            ChangeV1::RemoveGrant(grantee_id, action_id) => {
+                Change::RemoveGrant(grantee_id, action_id)
+            }
+            ChangeV1::ConnectGrantees(grantee_id, grantee_of_id) => {
+                Change::ConnectGrantees(grantee_id, grantee_of_id)
+            }
+            ChangeV1::DisconnectGrantees(grantee_id, grantee_of_id) => {
+                Change::DisconnectGrantees(grantee_id, grantee_of_id)
+            }
+            ChangeV1::ConnectActions(main_action_id, sub_action_id) => {
+                Change::ConnectActions(main_action_id, sub_action_id)
+            }
+            ChangeV1::DisconnectActions(main_action_id, sub_action_id) => {
+                Change::DisconnectActions(main_action_id, sub_action_id)
+            }
+            ChangeV1::AddRoot(grantee_id) => Change::AddRoot(grantee_id),
+            ChangeV1::RemoveRoot(grantee_id) => Change::RemoveRoot(grantee_id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Change;
+
+    #[test]
+    fn v1_format_is_stable() {
+        let changes: Vec<Change<u32, &str>> = vec![
+            Change::Clear,
+            Change::RemoveGrantee(1),
+            Change::RemoveAction("read"),
+            Change::AddGrant(1, "read"),
+            Change::RemoveGrant(1, "read"),
+            Change::ConnectGrantees(1, 2),
+            Change::DisconnectGrantees(1, 2),
+            Change::ConnectActions("write", "read"),
+            Change::DisconnectActions("write", "read"),
+            Change::AddRoot(2),
+            Change::RemoveRoot(2),
+        ];
+
+        let json = serde_json::to_string(&changes).expect("Expected changes to serialize");
+        assert_eq!(
+            json,
+            concat!(
+                r#"[{"v1":"clear"},{"v1":{"remove_grantee":1}},{"v1":{"remove_action":"read"}},"#,
+                r#"{"v1":{"add_grant":[1,"read"]}},{"v1":{"remove_grant":[1,"read"]}},"#,
+                r#"{"v1":{"connect_grantees":[1,2]}},{"v1":{"disconnect_grantees":[1,2]}},"#,
+                r#"{"v1":{"connect_actions":["write","read"]}},{"v1":{"disconnect_actions":["write","read"]}},"#,
+                r#"{"v1":{"add_root":2}},{"v1":{"remove_root":2}}]"#,
+            )
+        );
+
+        let restored: Vec<Change<u32, &str>> =
+            serde_json::from_str(&json).expect("Expected changes to deserialize");
+        assert_eq!(format!("{changes:?}"), format!("{restored:?}"));
+    }
+
+    #[test]
+    fn unknown_versions_are_rejected() {
+        assert!(serde_json::from_str::<Change<u32, u32>>(r#"{"v0":"clear"}"#).is_err());
+        assert!(serde_json::from_str::<Change<u32, u32>>(r#""clear""#).is_err());
+    }
+}